@@ -1,30 +1,169 @@
+//! Sprite animation. Clips are named frame sequences loaded from a RON `.anim` asset, and every
+//! animated sprite plays them through the same system.
+use crate::atlas::SpriteFrames;
+use crate::clock::GameClock;
+use crate::GameSystem;
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
 use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::HashMap;
+use serde::Deserialize;
+
+/// Clips available to every sprite.
+pub const DEFAULT_ANIMATIONS: &str = "sprites.anim";
+
+/// The given entity played a `PlaybackMode::Once` clip to the end.
+pub struct AnimationFinishedEvent {
+    pub entity: Entity,
+    pub clip: String,
+}
 
 pub struct SpriteAnimationPlugin;
 
 impl Plugin for SpriteAnimationPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(animate_sprites);
+        app.add_event::<AnimationFinishedEvent>()
+            .add_asset::<AnimationLibrary>()
+            .init_asset_loader::<AnimationLibraryLoader>()
+            .add_startup_system(setup)
+            .add_system(
+                animate_sprites
+                    .with_run_criteria(crate::not_paused)
+                    .label(GameSystem::Reaction)
+                    .after(GameSystem::Collision)
+                    .after(crate::yar::animate),
+            );
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize)]
+pub enum PlaybackMode {
+    #[default]
+    Loop,
+    /// Hold the last frame and send `AnimationFinishedEvent`.
+    Once,
+}
+
+/// A sequence of sprite atlas frames.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AnimationClip {
+    /// Frame names from the atlas manifest.
+    pub frames: Vec<String>,
+    /// Seconds each frame is shown for.
+    pub frame_duration: f32,
+    #[serde(default)]
+    pub mode: PlaybackMode,
+}
+
+/// Named animation clips, loaded from a RON `.anim` asset.
+#[derive(Debug, Clone, Deserialize, TypeUuid)]
+#[uuid = "c2b7e4d1-93a6-4f0e-b5d8-1e7a4c9f3b62"]
+pub struct AnimationLibrary {
+    pub clips: HashMap<String, AnimationClip>,
+}
+
+#[derive(Default)]
+pub struct AnimationLibraryLoader;
+
+impl AssetLoader for AnimationLibraryLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let library: AnimationLibrary = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(library));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["anim"]
     }
 }
 
-#[derive(Component, Deref, DerefMut)]
-pub struct AnimationTimer(pub Timer);
+/// The clips sprites are animated from.
+pub struct ActiveAnimations(pub Handle<AnimationLibrary>);
+
+/// Plays a clip on the entity's `TextureAtlasSprite`.
+#[derive(Component, Debug, Clone)]
+pub struct SpriteAnimation {
+    pub clip: String,
+    pub frame: usize,
+    /// Seconds the current frame has been shown for.
+    elapsed: f32,
+    finished: bool,
+}
+
+impl SpriteAnimation {
+    pub fn new(clip: &str) -> Self {
+        Self {
+            clip: clip.to_string(),
+            frame: 0,
+            elapsed: 0.0,
+            finished: false,
+        }
+    }
+
+    /// Switch to the given clip, starting it from the beginning unless it is already playing.
+    pub fn play(&mut self, clip: &str) {
+        if self.clip != clip {
+            *self = Self::new(clip);
+        }
+    }
+}
+
+pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(ActiveAnimations(asset_server.load(DEFAULT_ANIMATIONS)));
+}
 
 pub fn animate_sprites(
-    time: Res<Time>,
-    texture_atlases: Res<Assets<TextureAtlas>>,
-    mut query: Query<(
-        &mut AnimationTimer,
-        &mut TextureAtlasSprite,
-        &Handle<TextureAtlas>,
-    )>,
+    clock: Res<GameClock>,
+    sprite_frames: Res<SpriteFrames>,
+    active_animations: Option<Res<ActiveAnimations>>,
+    libraries: Res<Assets<AnimationLibrary>>,
+    mut finished_event: EventWriter<AnimationFinishedEvent>,
+    mut query: Query<(Entity, &mut SpriteAnimation, &mut TextureAtlasSprite)>,
 ) {
-    for (mut timer, mut sprite, texture_atlas_handle) in query.iter_mut() {
-        timer.tick(time.delta());
-        if timer.just_finished() {
-            let texture_atlas = texture_atlases.get(texture_atlas_handle).unwrap();
-            sprite.index = (sprite.index + 1) % texture_atlas.textures.len();
+    let library = match active_animations.and_then(|active| libraries.get(&active.0)) {
+        Some(library) => library,
+        None => return,
+    };
+
+    for (e, mut animation, mut sprite) in query.iter_mut() {
+        let clip = match library.clips.get(&animation.clip) {
+            Some(clip) => clip,
+            None => {
+                warn!("no animation clip named `{}`", animation.clip);
+                continue;
+            }
+        };
+        if clip.frames.is_empty() || clip.frame_duration <= 0.0 || animation.finished {
+            continue;
         }
+
+        animation.elapsed += clock.delta_seconds();
+        while animation.elapsed >= clip.frame_duration && !animation.finished {
+            animation.elapsed -= clip.frame_duration;
+            animation.frame += 1;
+
+            if animation.frame >= clip.frames.len() {
+                match clip.mode {
+                    PlaybackMode::Loop => animation.frame = 0,
+                    PlaybackMode::Once => {
+                        animation.frame = clip.frames.len() - 1;
+                        animation.finished = true;
+                        finished_event.send(AnimationFinishedEvent {
+                            entity: e,
+                            clip: animation.clip.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        sprite.index =
+            sprite_frames.index(&clip.frames[animation.frame.min(clip.frames.len() - 1)]);
     }
 }
@@ -0,0 +1,116 @@
+//! The sprite atlas. A RON `.atlas` manifest describes how the sprite sheet is cut into frames and
+//! names each one, so systems never need to know where a frame lives on the sheet.
+use crate::GameSystem;
+use bevy::asset::{AssetLoader, AssetPath, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::HashMap;
+use serde::Deserialize;
+
+/// Manifest for the sprites everything is drawn from.
+pub const DEFAULT_ATLAS: &str = "sprites.atlas";
+/// Label of the `TextureAtlas` built from a manifest.
+pub const ATLAS_LABEL: &str = "atlas";
+
+pub struct AtlasPlugin;
+
+impl Plugin for AtlasPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<AtlasManifest>()
+            .init_asset_loader::<AtlasManifestLoader>()
+            .init_resource::<SpriteFrames>()
+            .add_system(manifest_loaded.label(GameSystem::Start));
+    }
+}
+
+/// A sprite sheet cut into a grid of equally sized frames, loaded from a RON `.atlas` asset.
+#[derive(Debug, Clone, Deserialize, TypeUuid)]
+#[uuid = "8e2f5a17-3c9b-4d60-a1e4-7b58c0d29f34"]
+pub struct AtlasManifest {
+    /// Path of the sprite sheet image.
+    pub texture: String,
+    /// Size of each frame in pixels.
+    pub tile_size: (f32, f32),
+    pub columns: usize,
+    pub rows: usize,
+    /// Pixels between neighbouring frames.
+    #[serde(default)]
+    pub padding: (f32, f32),
+    /// Frame index for each name, counting across then down from the top left.
+    pub frames: HashMap<String, usize>,
+}
+
+#[derive(Default)]
+pub struct AtlasManifestLoader;
+
+impl AssetLoader for AtlasManifestLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let manifest: AtlasManifest = ron::de::from_bytes(bytes)?;
+
+            let texture_path = AssetPath::from(manifest.texture.as_str()).to_owned();
+            let atlas = TextureAtlas::from_grid_with_padding(
+                load_context.get_handle(texture_path.clone()),
+                Vec2::new(manifest.tile_size.0, manifest.tile_size.1),
+                manifest.columns,
+                manifest.rows,
+                Vec2::new(manifest.padding.0, manifest.padding.1),
+            );
+
+            load_context.set_labeled_asset(ATLAS_LABEL, LoadedAsset::new(atlas));
+            load_context
+                .set_default_asset(LoadedAsset::new(manifest).with_dependency(texture_path));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["atlas"]
+    }
+}
+
+/// Names of the frames in the loaded sprite atlas.
+#[derive(Debug, Default)]
+pub struct SpriteFrames {
+    frames: HashMap<String, usize>,
+}
+
+impl SpriteFrames {
+    pub fn get(&self, name: &str) -> Option<usize> {
+        self.frames.get(name).copied()
+    }
+
+    /// Index of the named frame, or the first frame if there is no such name.
+    pub fn index(&self, name: &str) -> usize {
+        self.get(name).unwrap_or_else(|| {
+            warn!("no sprite frame named `{}`", name);
+            0
+        })
+    }
+}
+
+/// Pick up frame names whenever the manifest loads or its file is edited.
+pub fn manifest_loaded(
+    mut manifest_event: EventReader<AssetEvent<AtlasManifest>>,
+    mut sprite_frames: ResMut<SpriteFrames>,
+    game_state: Res<crate::GameState>,
+    manifests: Res<Assets<AtlasManifest>>,
+) {
+    for event in manifest_event.iter() {
+        let handle = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+        if *handle != game_state.sprite_manifest {
+            continue;
+        }
+
+        if let Some(manifest) = manifests.get(handle) {
+            sprite_frames.frames = manifest.frames.clone();
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Computer players. A bot flies Yar or aims the cannon by giving the same commands the
+//! keyboard would, so its games are recorded and replayed like anyone else's.
+use crate::control::{self, BotCommand, ControlTarget};
+use crate::destroyer_missile::DestroyerMissile;
+use crate::motion::GamePos;
+use crate::qotile::{Qotile, SwirlState};
+use crate::shield::ShieldBlock;
+use crate::yar::{Yar, YarCommandEvent, YarDirection, YAR_BOUNDS};
+use crate::zorlon_cannon::{CannonCommandEvent, CannonDirection, ZorlonCannon};
+use crate::GameSystem;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+/// Distance from the missile at which Yar starts getting out of its way.
+const MISSILE_DANGER: i32 = 24;
+/// Distance from a swirl, launched or about to be, at which Yar starts getting out of its way.
+const SWIRL_DANGER: i32 = 48;
+/// How close the cannon has to be to Qotile's row before it fires.
+const AIM_TOLERANCE: i32 = 2;
+/// Frames between shots while Yar works on the shield.
+const SHOT_PERIOD: u32 = 8;
+
+pub struct BotPlugin;
+
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Bots>().add_system(
+            think
+                .with_run_criteria(crate::not_paused)
+                .label(GameSystem::Input)
+                .after(GameSystem::Start)
+                .after(control::on_yar_respawn)
+                .before(control::commands),
+        );
+    }
+}
+
+/// Everything a bot gets to know about the playfield.
+#[derive(Debug, Default, Clone)]
+pub struct BotView {
+    /// The live Yar.
+    pub yar: Option<IVec2>,
+    pub qotile: Option<(IVec2, SwirlState)>,
+    pub missile: Option<IVec2>,
+    /// The cannon, and whether it has been fired.
+    pub cannon: Option<(IVec2, bool)>,
+    pub shield: Vec<IVec2>,
+}
+
+/// Something that can play a control target, one command per frame.
+pub trait Bot<E>: Send + Sync {
+    fn command(&mut self, view: &BotView) -> E;
+}
+
+/// The bots playing each control target. Targets without one are played from the keyboard.
+#[derive(Default)]
+pub struct Bots {
+    pub yar: Option<Box<dyn Bot<YarCommandEvent>>>,
+    pub cannon: Option<Box<dyn Bot<CannonCommandEvent>>>,
+}
+
+impl Bots {
+    /// Step through no bots, a bot on the cannon, a bot on Yar and bots on both.
+    pub fn cycle(&mut self) {
+        *self = match (self.yar.is_some(), self.cannon.is_some()) {
+            (false, false) => Self {
+                yar: None,
+                cannon: Some(Box::new(RuleBot::default())),
+            },
+            (false, true) => Self {
+                yar: Some(Box::new(RuleBot::default())),
+                cannon: None,
+            },
+            (true, false) => Self {
+                yar: Some(Box::new(RuleBot::default())),
+                cannon: Some(Box::new(RuleBot::default())),
+            },
+            (true, true) => Self::default(),
+        };
+    }
+
+    /// Which targets the bots are playing, for showing on screen.
+    pub fn label(&self) -> &'static str {
+        match (self.yar.is_some(), self.cannon.is_some()) {
+            (false, false) => "OFF",
+            (false, true) => "CANNON",
+            (true, false) => "YAR",
+            (true, true) => "BOTH",
+        }
+    }
+}
+
+/// Plays by a handful of fixed rules.
+///
+/// As Yar it gets out of the way of the swirl, the missile and a waiting cannon, in that
+/// order, and otherwise eats its way into the shield, shooting as it goes. As the cannon it
+/// lines up with Qotile and fires once Yar is out of the way.
+#[derive(Debug, Default, Clone)]
+pub struct RuleBot {
+    /// Frames until Yar shoots again.
+    shot_cooldown: u32,
+}
+
+/// Offset from `from` to `to`, rounded to the nearest direction with a little slack so that
+/// the bot doesn't wobble once it is close.
+fn heading(from: IVec2, to: IVec2, slack: i32) -> IVec2 {
+    let offset = to - from;
+    IVec2::new(
+        if offset.x.abs() > slack { offset.x } else { 0 },
+        if offset.y.abs() > slack { offset.y } else { 0 },
+    )
+}
+
+/// Which way to go to get out of a row centred on `danger_y`.
+fn leave_row(yar: IVec2, danger_y: i32) -> i32 {
+    if yar.y >= danger_y {
+        1
+    } else {
+        -1
+    }
+}
+
+impl Bot<YarCommandEvent> for RuleBot {
+    fn command(&mut self, view: &BotView) -> YarCommandEvent {
+        let yar = match view.yar {
+            Some(yar) => yar,
+            None => return YarCommandEvent::default(),
+        };
+
+        let swirl = view.qotile.and_then(|(pos, state)| match state {
+            SwirlState::NotSwirl => None,
+            _ => Some(pos),
+        });
+        let waiting_cannon = view
+            .cannon
+            .and_then(|(pos, launched)| (!launched).then_some(pos));
+
+        let mut shoot = false;
+        let offset = if let Some(swirl) =
+            swirl.filter(|swirl| (*swirl - yar).abs().max_element() < SWIRL_DANGER)
+        {
+            // Swirls fly straight at where Yar was; move across their path and back off.
+            IVec2::new(-1, leave_row(yar, swirl.y))
+        } else if let Some(missile) = view
+            .missile
+            .filter(|missile| (*missile - yar).abs().max_element() < MISSILE_DANGER)
+        {
+            heading(missile, yar, 0)
+        } else if let Some(cannon) =
+            waiting_cannon.filter(|cannon| (cannon.y - yar.y).abs() < YAR_BOUNDS.y)
+        {
+            // Stay out of the cannon's line of fire.
+            IVec2::new(0, leave_row(yar, cannon.y))
+        } else if let Some(block) = view
+            .shield
+            .iter()
+            .min_by_key(|block| (**block - yar).abs().max_element())
+        {
+            shoot = self.shot_cooldown == 0;
+            self.shot_cooldown = self.shot_cooldown.checked_sub(1).unwrap_or(SHOT_PERIOD);
+            heading(yar, *block, 1)
+        } else {
+            IVec2::ZERO
+        };
+
+        YarCommandEvent {
+            direction: YarDirection::towards(offset),
+            shoot,
+        }
+    }
+}
+
+impl Bot<CannonCommandEvent> for RuleBot {
+    fn command(&mut self, view: &BotView) -> CannonCommandEvent {
+        let (cannon, target) = match (view.cannon, view.qotile) {
+            (Some((cannon, false)), Some((qotile, _))) => (cannon, qotile),
+            _ => return CannonCommandEvent::default(),
+        };
+
+        let dy = target.y - cannon.y;
+        let yar_in_line = view
+            .yar
+            .is_some_and(|yar| (yar.y - cannon.y).abs() * 2 < YAR_BOUNDS.y);
+        let direction = if dy > AIM_TOLERANCE {
+            Some(CannonDirection::Up)
+        } else if dy < -AIM_TOLERANCE {
+            Some(CannonDirection::Down)
+        } else {
+            None
+        };
+        CannonCommandEvent {
+            direction,
+            shoot: direction.is_none() && !yar_in_line,
+        }
+    }
+}
+
+/// The queries a `BotView` is gathered from.
+#[derive(SystemParam)]
+pub struct Playfield<'w, 's> {
+    yar: Query<'w, 's, (&'static GamePos, &'static Yar)>,
+    qotile: Query<'w, 's, (&'static GamePos, &'static Qotile)>,
+    missile: Query<'w, 's, &'static GamePos, With<DestroyerMissile>>,
+    cannon: Query<'w, 's, (&'static GamePos, &'static ZorlonCannon)>,
+    shield: Query<'w, 's, &'static GamePos, With<ShieldBlock>>,
+}
+
+impl<'w, 's> Playfield<'w, 's> {
+    pub fn view(&self) -> BotView {
+        BotView {
+            yar: self
+                .yar
+                .iter()
+                .find(|(_, yar)| !yar.is_dead())
+                .map(|(pos, _)| pos.0),
+            qotile: self
+                .qotile
+                .iter()
+                .next()
+                .map(|(pos, qotile)| (pos.0, qotile.swirl_state)),
+            missile: self.missile.iter().next().map(|pos| pos.0),
+            cannon: self
+                .cannon
+                .iter()
+                .next()
+                .map(|(pos, cannon)| (pos.0, cannon.is_launched())),
+            shield: self.shield.iter().map(|pos| pos.0).collect(),
+        }
+    }
+}
+
+/// Let the bot playing the live target decide what to do this frame.
+pub fn think(
+    control_target: Res<ControlTarget>,
+    mut bots: ResMut<Bots>,
+    mut yar_command: ResMut<BotCommand<YarCommandEvent>>,
+    mut cannon_command: ResMut<BotCommand<CannonCommandEvent>>,
+    playfield: Playfield,
+) {
+    let bots = &mut *bots;
+    let (yar_bot, cannon_bot) = match *control_target {
+        ControlTarget::Yar => (bots.yar.as_mut(), None),
+        ControlTarget::Cannon => (None, bots.cannon.as_mut()),
+        ControlTarget::Demo => (None, None),
+    };
+    if yar_bot.is_none() && cannon_bot.is_none() {
+        return;
+    }
+
+    let view = playfield.view();
+    if let Some(bot) = yar_bot {
+        yar_command.0 = Some(bot.command(&view));
+    }
+    if let Some(bot) = cannon_bot {
+        cannon_command.0 = Some(bot.command(&view));
+    }
+}
@@ -1,144 +1,226 @@
+use crate::atlas::SpriteFrames;
+use crate::motion::{GamePos, Velocity};
 use crate::neutral_zone::{NeutralZone, NEUTRAL_ZONE_BOUNDS};
-use crate::shield::{ShieldBlock, ShieldHealth, SHIELD_BLOCK_SPRITE_SIZE};
+use crate::qotile::QotileDiedEvent;
+use crate::shield::{ShieldBlock, ShieldDamageEvent, ShieldDamageSource, SHIELD_BLOCK_SPRITE_SIZE};
 use crate::util;
+use crate::util::DespawnReason;
 use crate::yar::{Yar, YarDiedEvent, YarShootEvent, YAR_BOUNDS};
-use crate::SCREEN_SCALE;
-use bevy::math::const_vec2;
+use crate::{AppState, GameSystem};
+use bevy::math::const_ivec2;
 use bevy::prelude::*;
+use bevy::utils::HashMap;
 
-const BULLET_SPEED: f32 = 6.0;
-const BULLET_BOUNDS: Vec2 = const_vec2!([2.0 * SCREEN_SCALE, 2.0 * SCREEN_SCALE]);
+/// Scanlines per frame.
+const BULLET_SPEED: f32 = 1.5;
+pub const BULLET_BOUNDS: IVec2 = const_ivec2!([1, 2]);
 
-pub struct DespawnBulletEvent;
+/// Despawn the given bullet entity.
+pub struct DespawnBulletEvent {
+    pub entity: Entity,
+    pub reason: DespawnReason,
+}
 
 pub struct BulletPlugin;
 
 impl Plugin for BulletPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<DespawnBulletEvent>()
-            .add_system(despawn)
-            .add_system(shoot)
-            .add_system(fly)
-            .add_system(collide_shield);
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .label(GameSystem::Movement)
+                    .after(GameSystem::Input)
+                    .with_system(shoot.after(crate::yar::input)),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .label(GameSystem::Collision)
+                    .after(GameSystem::Movement)
+                    .with_system(collide_shield.after(crate::yar::collide_shield))
+                    .with_system(leave_world.after(collide_shield)),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .label(GameSystem::Reaction)
+                    .after(GameSystem::Collision)
+                    .with_system(yar_died)
+                    .with_system(qotile_died.after(yar_died)),
+            )
+            .add_system(despawn.label(GameSystem::Spawn).after(GameSystem::Reaction));
     }
 }
 
 #[derive(Component)]
 pub struct Bullet {
-    velocity: Vec3,
+    /// The Yar that fired this bullet.
+    pub shooter: Entity,
 }
 
 pub fn despawn(
     mut commands: Commands,
     mut despawn_event: EventReader<DespawnBulletEvent>,
-    mut death_event: EventReader<YarDiedEvent>,
     query: Query<Entity, With<Bullet>>,
 ) {
-    if (despawn_event.iter().next().is_none() && death_event.iter().next().is_none())
-        || query.is_empty()
+    let targets: HashMap<Entity, DespawnReason> = despawn_event
+        .iter()
+        .map(|event| (event.entity, event.reason))
+        .collect();
+
+    for e in query.iter() {
+        if let Some(reason) = targets.get(&e) {
+            debug!("despawning bullet {:?}: {:?}", e, reason);
+            commands.entity(e).despawn();
+        }
+    }
+}
+
+/// A dead Yar's bullet goes with it.
+pub fn yar_died(
+    mut death_event: EventReader<YarDiedEvent>,
+    mut despawn_event: EventWriter<DespawnBulletEvent>,
+    query: Query<(Entity, &Bullet)>,
+) {
+    for YarDiedEvent {
+        yar: yar_entity, ..
+    } in death_event.iter()
     {
+        for (e, bullet) in query.iter() {
+            if bullet.shooter == *yar_entity {
+                despawn_event.send(DespawnBulletEvent {
+                    entity: e,
+                    reason: DespawnReason::Death,
+                });
+            }
+        }
+    }
+}
+
+/// Clear the playfield for the explosion.
+pub fn qotile_died(
+    mut death_event: EventReader<QotileDiedEvent>,
+    mut despawn_event: EventWriter<DespawnBulletEvent>,
+    query: Query<Entity, With<Bullet>>,
+) {
+    if death_event.iter().count() == 0 {
         return;
     }
 
-    let e = query.single();
-    commands.entity(e).despawn();
+    for e in query.iter() {
+        despawn_event.send(DespawnBulletEvent {
+            entity: e,
+            reason: DespawnReason::RoundEnd,
+        });
+    }
 }
 
 pub fn shoot(
     mut commands: Commands,
     mut shoot_event: EventReader<YarShootEvent>,
-    yar_query: Query<(&Transform, &Handle<TextureAtlas>, &Yar)>,
+    sprite_frames: Res<SpriteFrames>,
+    yar_query: Query<(&GamePos, &Transform, &Handle<TextureAtlas>, &Yar)>,
     bullet_query: Query<&Bullet, Without<Yar>>,
-    nz_query: Query<&Transform, With<NeutralZone>>,
+    nz_query: Query<&GamePos, With<NeutralZone>>,
 ) {
-    if shoot_event.iter().next().is_none() || yar_query.is_empty() || !bullet_query.is_empty() {
-        return;
-    }
-
-    let (transform, texture_atlas_handle, yar) = yar_query.single();
+    for YarShootEvent(yar_entity) in shoot_event.iter() {
+        // Each Yar may only have one bullet in flight.
+        if bullet_query
+            .iter()
+            .any(|bullet| bullet.shooter == *yar_entity)
+        {
+            continue;
+        }
 
-    if !nz_query.is_empty() {
-        let nz_transform = nz_query.single();
+        let (pos, transform, texture_atlas_handle, yar) = match yar_query.get(*yar_entity) {
+            Ok(yar) => yar,
+            Err(_) => continue,
+        };
 
         // Yar cannot shoot while in the Neutral Zone
-        if util::intersect_rect(
-            &transform.translation,
-            &YAR_BOUNDS,
-            &nz_transform.translation,
-            &NEUTRAL_ZONE_BOUNDS,
-        ) {
-            return;
+        if nz_query
+            .iter()
+            .any(|nz_pos| util::intersect_rect(pos, &YAR_BOUNDS, nz_pos, &NEUTRAL_ZONE_BOUNDS))
+        {
+            continue;
         }
-    }
 
-    commands
-        .spawn_bundle(SpriteSheetBundle {
-            sprite: TextureAtlasSprite {
-                index: 21,
+        commands
+            .spawn_bundle(SpriteSheetBundle {
+                sprite: TextureAtlasSprite {
+                    index: sprite_frames.index("bullet"),
+                    ..default()
+                },
+                texture_atlas: texture_atlas_handle.clone(),
+                transform: *transform,
                 ..default()
-            },
-            texture_atlas: texture_atlas_handle.clone(),
-            transform: *transform,
-            ..default()
-        })
-        .insert(Bullet {
-            velocity: yar.direction_to_vector() * BULLET_SPEED,
-        });
+            })
+            .insert(*pos)
+            .insert(Velocity::from_direction(
+                yar.direction_to_vector().as_vec2(),
+                BULLET_SPEED,
+            ))
+            .insert(Bullet {
+                shooter: *yar_entity,
+            });
+    }
 }
 
-pub fn fly(
+pub fn leave_world(
     mut despawn_event: EventWriter<DespawnBulletEvent>,
-    mut query: Query<(&mut Transform, &Bullet)>,
+    query: Query<(Entity, &GamePos), With<Bullet>>,
 ) {
-    if query.is_empty() {
-        return;
-    }
-
-    let (mut transform, bullet) = query.single_mut();
-
-    if util::is_offscreen(transform.translation) {
-        despawn_event.send(DespawnBulletEvent);
-        return;
+    for (e, pos) in query.iter() {
+        if util::is_offscreen(pos.0) {
+            despawn_event.send(DespawnBulletEvent {
+                entity: e,
+                reason: DespawnReason::Offscreen,
+            });
+        }
     }
-
-    transform.translation += bullet.velocity;
 }
 
 pub fn collide_shield(
     mut despawn_event: EventWriter<DespawnBulletEvent>,
-    mut shield_query: Query<(&Transform, &mut ShieldHealth, &ShieldBlock), Without<Bullet>>,
-    bullet_query: Query<&Transform, (With<Bullet>, Without<ShieldBlock>)>,
+    mut damage_event: EventWriter<ShieldDamageEvent>,
+    shield_query: Query<(Entity, &GamePos, &ShieldBlock), Without<Bullet>>,
+    bullet_query: Query<(Entity, &GamePos), (With<Bullet>, Without<ShieldBlock>)>,
 ) {
-    if shield_query.is_empty() || bullet_query.is_empty() {
-        return;
-    }
-
-    let bullet_transform = bullet_query.single();
-
-    let mut struck_block_position = Vec2::default();
-
-    for (shield_transform, _, shield_block) in shield_query.iter_mut() {
-        if util::intersect_rect(
-            &shield_transform.translation,
-            &BULLET_BOUNDS,
-            &bullet_transform.translation,
-            &SHIELD_BLOCK_SPRITE_SIZE,
-        ) {
-            despawn_event.send(DespawnBulletEvent);
-            struck_block_position = shield_block.position;
-            break;
-        }
-    }
+    for (bullet_entity, bullet_pos) in bullet_query.iter() {
+        let struck_block_position = shield_query
+            .iter()
+            .find(|(_, shield_pos, _)| {
+                util::intersect_rect(
+                    shield_pos,
+                    &BULLET_BOUNDS,
+                    bullet_pos,
+                    &SHIELD_BLOCK_SPRITE_SIZE,
+                )
+            })
+            .map(|(_, _, shield_block)| shield_block.position);
+
+        let struck_block_position = match struck_block_position {
+            Some(position) => position,
+            None => continue,
+        };
+
+        despawn_event.send(DespawnBulletEvent {
+            entity: bullet_entity,
+            reason: DespawnReason::Collision,
+        });
 
-    // Bullet kills blocks in a cross shape.
-    for (_, mut shield_health, shield_block) in shield_query.iter_mut() {
-        if shield_block.position == struck_block_position
-            || (shield_block.position + Vec2::new(1.0, 0.0)) == struck_block_position
-            || (shield_block.position + Vec2::new(0.0, 1.0)) == struck_block_position
-            || (shield_block.position + Vec2::new(-1.0, 0.0)) == struck_block_position
-            || (shield_block.position + Vec2::new(0.0, -1.0)) == struck_block_position
-        {
-            shield_health.health -= 5;
+        // Bullet kills blocks in a cross shape.
+        for (block, _, shield_block) in shield_query.iter() {
+            if shield_block.position == struck_block_position
+                || (shield_block.position + Vec2::new(1.0, 0.0)) == struck_block_position
+                || (shield_block.position + Vec2::new(0.0, 1.0)) == struck_block_position
+                || (shield_block.position + Vec2::new(-1.0, 0.0)) == struck_block_position
+                || (shield_block.position + Vec2::new(0.0, -1.0)) == struck_block_position
+            {
+                damage_event.send(ShieldDamageEvent {
+                    block,
+                    damage: 5,
+                    source: ShieldDamageSource::Bullet,
+                });
+            }
         }
     }
 }
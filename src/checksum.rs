@@ -0,0 +1,205 @@
+//! Checksums of the game's state, written into replays every so often so that playing one back
+//! can tell the first frame it stops playing out the way it was recorded.
+//!
+//! A checksum covers where everything is, how much of the shield is left and where the random
+//! number generator has got to. Checksums only come out the same when the game runs at a fixed
+//! step, as it does headless; at real speed no two runs take frames of the same lengths.
+use crate::clock::GameClock;
+use crate::control::{ControlTarget, GameRecording};
+use crate::motion::GamePos;
+use crate::rng::GameRng;
+use crate::shield::ShieldHealth;
+use crate::AppState;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+pub struct ChecksumPlugin;
+
+impl Plugin for ChecksumPlugin {
+    fn build(&self, app: &mut App) {
+        // Last, once everything that happens in a frame has happened.
+        app.init_resource::<ChecksumPeriod>()
+            .init_resource::<ReplayCheck>()
+            .add_system_to_stage(CoreStage::Last, record.with_run_criteria(crate::not_paused))
+            .add_system_to_stage(CoreStage::Last, verify.with_run_criteria(crate::not_paused));
+    }
+}
+
+/// Frames between the checksums written into a game's replay, or `None` to write none.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ChecksumPeriod(pub Option<u32>);
+
+/// The state of the game on a frame of a replay.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateChecksum {
+    /// Frames since the game started.
+    pub tick: u64,
+    pub hash: u64,
+}
+
+/// The first checksum a replay failed to match.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Desync {
+    pub tick: u64,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+/// Checking the replay being played against its checksums.
+#[derive(Debug, Default, Clone)]
+pub struct ReplayCheck {
+    start_tick: u64,
+    pending: VecDeque<StateChecksum>,
+    /// Checksums matched so far.
+    pub matched: usize,
+    pub desync: Option<Desync>,
+}
+
+impl ReplayCheck {
+    /// Check a replay starting now against `checksums`.
+    pub fn new(clock: &GameClock, checksums: &[StateChecksum]) -> Self {
+        Self {
+            start_tick: clock.tick,
+            pending: checksums.iter().copied().collect(),
+            ..default()
+        }
+    }
+
+    /// Frames since the replay started.
+    pub fn tick(&self, clock: &GameClock) -> u64 {
+        clock.tick.saturating_sub(self.start_tick)
+    }
+
+    /// Whether every checksum has been checked, or checking has stopped at a desync.
+    pub fn is_finished(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// FNV-1a. Unlike the standard library's hashers it is pinned down, so the same state hashes
+/// the same on every machine and with every compiler.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_i32(&mut self, value: i32) {
+        self.write(&value.to_le_bytes());
+    }
+}
+
+/// The parts of the game a checksum covers.
+#[derive(SystemParam)]
+pub struct ChecksumState<'w, 's> {
+    positions: Query<'w, 's, (&'static GamePos, Option<&'static ShieldHealth>)>,
+    rng: Res<'w, GameRng>,
+}
+
+impl<'w, 's> ChecksumState<'w, 's> {
+    pub fn checksum(&self) -> u64 {
+        // Sorted, so that the same things in the same places hash the same whatever order they
+        // were spawned in.
+        let mut positions: Vec<(i32, i32, Option<i32>)> = self
+            .positions
+            .iter()
+            .map(|(pos, health)| (pos.x, pos.y, health.map(|health| health.health)))
+            .collect();
+        positions.sort_unstable();
+
+        let mut hash = Fnv::new();
+        for (x, y, health) in positions {
+            hash.write_i32(x);
+            hash.write_i32(y);
+            if let Some(health) = health {
+                hash.write_i32(health);
+            }
+        }
+        // The generator can't be looked into, but the next number it would give stands in for
+        // where it has got to.
+        hash.write(&self.rng.0.clone().next_u64().to_le_bytes());
+        hash.0
+    }
+}
+
+fn in_game(state: &State<AppState>) -> bool {
+    matches!(
+        state.current(),
+        AppState::Playing | AppState::QotileDeathSequence
+    )
+}
+
+/// Write a checksum into the game's replay every `ChecksumPeriod` frames.
+pub fn record(
+    period: Res<ChecksumPeriod>,
+    control_target: Res<ControlTarget>,
+    app_state: Res<State<AppState>>,
+    clock: Res<GameClock>,
+    mut recording: ResMut<GameRecording>,
+    state: ChecksumState,
+) {
+    let period = match period.0 {
+        Some(period) => period.max(1) as u64,
+        None => return,
+    };
+    // Replays already have their checksums.
+    if matches!(*control_target, ControlTarget::Demo) || !in_game(&app_state) {
+        return;
+    }
+
+    let tick = recording.tick(&clock);
+    let replay = match recording.replay.as_mut() {
+        Some(replay) => replay,
+        None => return,
+    };
+    let due = replay.checksums.last().map_or(0, |last| last.tick + period);
+    if tick >= due {
+        replay.checksums.push(StateChecksum {
+            tick,
+            hash: state.checksum(),
+        });
+    }
+}
+
+/// Check the replay being played against its checksums, and stop at the first that doesn't
+/// match.
+pub fn verify(clock: Res<GameClock>, mut check: ResMut<ReplayCheck>, state: ChecksumState) {
+    let tick = check.tick(&clock);
+    while let Some(expected) = check.pending.front().copied() {
+        if expected.tick > tick {
+            return;
+        }
+        check.pending.pop_front();
+        // Checksums from frames that didn't run here, such as while paused, can't be checked.
+        if expected.tick < tick {
+            continue;
+        }
+
+        let actual = state.checksum();
+        if actual == expected.hash {
+            check.matched += 1;
+        } else {
+            warn!(
+                "replay desynced on frame {}: expected checksum {:016x}, got {:016x}",
+                tick, expected.hash, actual
+            );
+            check.desync = Some(Desync {
+                tick,
+                expected: expected.hash,
+                actual,
+            });
+            check.pending.clear();
+        }
+    }
+}
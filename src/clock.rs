@@ -0,0 +1,48 @@
+//! The game's clock. The 2600 did everything once per frame, so motion counts frames rather than
+//! measuring time. Anything in the game that does measure time, such as timers and replays,
+//! measures it on this clock rather than `Time`, so that it stops while the game is paused and
+//! can be run at a fixed step without a real clock behind it.
+use bevy::core::CoreSystem;
+use bevy::prelude::*;
+use bevy::utils::Duration;
+
+pub struct ClockPlugin;
+
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameClock>()
+            .init_resource::<FixedStep>()
+            .add_system_to_stage(
+                CoreStage::First,
+                tick.with_run_criteria(crate::not_paused)
+                    .after(CoreSystem::Time),
+            );
+    }
+}
+
+/// Frames and time since the game started.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct GameClock {
+    pub tick: u64,
+    pub elapsed: Duration,
+    /// Time since the previous frame.
+    pub delta: Duration,
+}
+
+impl GameClock {
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+}
+
+/// How far the clock moves each frame: by this much if it is set, otherwise by however long
+/// the frame really took.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FixedStep(pub Option<Duration>);
+
+pub fn tick(mut clock: ResMut<GameClock>, fixed_step: Res<FixedStep>, time: Res<Time>) {
+    let delta = fixed_step.0.unwrap_or_else(|| time.delta());
+    clock.tick += 1;
+    clock.elapsed += delta;
+    clock.delta = delta;
+}
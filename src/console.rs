@@ -0,0 +1,487 @@
+//! The developer console: commands typed over the game while playtesting, such as `spawn swirl`
+//! or `set round.swirl_delay_base 1`. The backquote key opens and closes it.
+//!
+//! `AppState::Console` is pushed on top of whatever the game was doing, which stands still
+//! until the console closes, the same as it does for `AppState::Paused`.
+//!
+//! Each plugin adds its own commands and tuning variables to the [`ConsoleRegistry`] with
+//! [`ConsoleAppExt`]; `help` lists them. Anything that changes the game discards its replay,
+//! which could not reproduce it.
+use crate::control::GameRecording;
+use crate::font;
+use crate::title::{TEXT_COLOR, TITLE_COLOR};
+use crate::{AppState, GameSystem, PLAYFIELD_SIZE};
+use anyhow::{anyhow, bail, Context};
+use bevy::prelude::*;
+use bevy::window::ReceivedCharacter;
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// In front of the pause menu, behind the letterbox.
+const CONSOLE_Z: f32 = 30.0;
+const ROW_HEIGHT: i32 = 12;
+/// Rows of output shown above the line being typed.
+const LOG_ROWS: usize = 14;
+/// Characters that fit across the playfield, with a margin.
+const LINE_LEN: usize = 38;
+/// Output kept for scrolling back through; older lines are dropped.
+const MAX_LOG: usize = 100;
+const OVERLAY_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.8);
+
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Console>()
+            .init_resource::<ConsoleRegistry>()
+            // Ahead of every menu, so the key that opens the console does nothing else.
+            .add_system(toggle.before(GameSystem::Menu))
+            .add_system_set(
+                SystemSet::on_enter(AppState::Console)
+                    .label(GameSystem::Menu)
+                    .before(GameSystem::Start)
+                    .with_system(open),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Console)
+                    .label(GameSystem::Menu)
+                    .before(GameSystem::Start)
+                    .with_system(type_line.after(open))
+                    .with_system(run_lines.exclusive_system().at_end())
+                    .with_system(draw.after(type_line)),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::Console).with_system(hide));
+    }
+}
+
+/// Run a command, given its arguments. Returns what to print.
+pub type CommandFn = fn(&mut World, &[&str]) -> anyhow::Result<String>;
+
+pub struct ConsoleCommand {
+    /// One or more words, such as `spawn swirl`.
+    pub name: &'static str,
+    /// The arguments that follow the name, for `help`.
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub run: CommandFn,
+}
+
+/// Shows a variable, after setting it if given a value.
+type AccessFn = dyn Fn(&mut World, Option<&str>) -> anyhow::Result<String> + Send + Sync;
+
+/// A tuning value that `get` shows and `set` changes.
+#[derive(Clone)]
+pub struct ConsoleVar {
+    /// Dotted after what it tunes, such as `yar.speed`.
+    pub name: &'static str,
+    pub help: &'static str,
+    access: Arc<AccessFn>,
+}
+
+impl ConsoleVar {
+    /// A variable kept in the world wherever `field` finds it, which `set` only changes to
+    /// values in `valid`.
+    pub fn new<T>(
+        name: &'static str,
+        help: &'static str,
+        valid: RangeInclusive<T>,
+        field: for<'w> fn(&'w mut World) -> &'w mut T,
+    ) -> Self
+    where
+        T: FromStr + Display + PartialOrd + Send + Sync + 'static,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        Self {
+            name,
+            help,
+            access: Arc::new(move |world, value| {
+                if let Some(value) = value {
+                    let value: T = value
+                        .parse()
+                        .with_context(|| format!("`{}` won't do for {}", value, name))?;
+                    if !valid.contains(&value) {
+                        bail!(
+                            "{} takes {} to {}, not {}",
+                            name,
+                            valid.start(),
+                            valid.end(),
+                            value
+                        );
+                    }
+                    *field(world) = value;
+                }
+                Ok(format!("{} = {}", name, field(world)))
+            }),
+        }
+    }
+}
+
+/// Every command and tuning variable the console knows.
+pub struct ConsoleRegistry {
+    commands: Vec<ConsoleCommand>,
+    vars: Vec<ConsoleVar>,
+}
+
+impl Default for ConsoleRegistry {
+    /// The console's own commands, for working with the rest.
+    fn default() -> Self {
+        Self {
+            commands: vec![
+                ConsoleCommand {
+                    name: "help",
+                    usage: "[COMMAND]",
+                    help: "List the commands, or explain one.",
+                    run: help,
+                },
+                ConsoleCommand {
+                    name: "get",
+                    usage: "[VARIABLE]",
+                    help: "Show a tuning variable, or all of them.",
+                    run: get,
+                },
+                ConsoleCommand {
+                    name: "set",
+                    usage: "VARIABLE VALUE",
+                    help: "Change a tuning variable.",
+                    run: set,
+                },
+                ConsoleCommand {
+                    name: "clear",
+                    usage: "",
+                    help: "Clear the console.",
+                    run: clear,
+                },
+            ],
+            vars: Vec::new(),
+        }
+    }
+}
+
+impl ConsoleRegistry {
+    /// The command named by the first words of `words`, taking the longest name that matches,
+    /// along with how many words the name is.
+    pub fn command(&self, words: &[&str]) -> Option<(&ConsoleCommand, usize)> {
+        self.commands
+            .iter()
+            .filter_map(|command| {
+                let name: Vec<&str> = command.name.split(' ').collect();
+                let matches = name.len() <= words.len()
+                    && name
+                        .iter()
+                        .zip(words)
+                        .all(|(part, word)| part.eq_ignore_ascii_case(word));
+                matches.then_some((command, name.len()))
+            })
+            .max_by_key(|(_, len)| *len)
+    }
+
+    pub fn var(&self, name: &str) -> Option<&ConsoleVar> {
+        self.vars
+            .iter()
+            .find(|var| var.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Registering commands and variables, from whichever plugin they belong to.
+pub trait ConsoleAppExt {
+    fn add_console_command(&mut self, command: ConsoleCommand) -> &mut Self;
+    fn add_console_var(&mut self, var: ConsoleVar) -> &mut Self;
+}
+
+impl ConsoleAppExt for App {
+    fn add_console_command(&mut self, command: ConsoleCommand) -> &mut Self {
+        self.init_resource::<ConsoleRegistry>();
+        self.world
+            .resource_mut::<ConsoleRegistry>()
+            .commands
+            .push(command);
+        self
+    }
+
+    fn add_console_var(&mut self, var: ConsoleVar) -> &mut Self {
+        self.init_resource::<ConsoleRegistry>();
+        self.world.resource_mut::<ConsoleRegistry>().vars.push(var);
+        self
+    }
+}
+
+/// Run a line as typed into the console. Returns what to print.
+pub fn run_line(world: &mut World, line: &str) -> anyhow::Result<String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if words.is_empty() {
+        return Ok(String::new());
+    }
+    let (run, len) = world
+        .get_resource::<ConsoleRegistry>()
+        .and_then(|registry| registry.command(&words))
+        .map(|(command, len)| (command.run, len))
+        .ok_or_else(|| anyhow!("no command `{}`; try `help`", words[0]))?;
+    run(world, &words[len..])
+}
+
+/// The game has been changed in a way its replay can't reproduce.
+pub fn discard_replay(world: &mut World) {
+    if let Some(mut recording) = world.get_resource_mut::<GameRecording>() {
+        recording.discard();
+    }
+}
+
+fn help(world: &mut World, args: &[&str]) -> anyhow::Result<String> {
+    let registry = world.resource::<ConsoleRegistry>();
+    if args.is_empty() {
+        let names: Vec<&str> = registry
+            .commands
+            .iter()
+            .map(|command| command.name)
+            .collect();
+        return Ok(format!(
+            "commands: {}. `get` shows the variables.",
+            names.join(", ")
+        ));
+    }
+    let (command, _) = registry
+        .command(args)
+        .ok_or_else(|| anyhow!("no command `{}`", args.join(" ")))?;
+    let usage = [command.name, command.usage].join(" ");
+    Ok(format!("{}: {}", usage.trim_end(), command.help))
+}
+
+fn get(world: &mut World, args: &[&str]) -> anyhow::Result<String> {
+    let registry = world.resource::<ConsoleRegistry>();
+    let vars: Vec<ConsoleVar> = match args {
+        [] => registry.vars.clone(),
+        [name] => vec![registry
+            .var(name)
+            .ok_or_else(|| anyhow!("no variable `{}`", name))?
+            .clone()],
+        _ => bail!("usage: get [VARIABLE]"),
+    };
+    let mut lines = Vec::new();
+    for var in vars {
+        lines.push((var.access)(world, None)?);
+    }
+    Ok(lines.join("\n"))
+}
+
+fn set(world: &mut World, args: &[&str]) -> anyhow::Result<String> {
+    let (name, value) = match args {
+        [name, value] => (name, value),
+        _ => bail!("usage: set VARIABLE VALUE"),
+    };
+    let var = world
+        .resource::<ConsoleRegistry>()
+        .var(name)
+        .ok_or_else(|| anyhow!("no variable `{}`", name))?
+        .clone();
+    let shown = (var.access)(world, Some(value))?;
+    discard_replay(world);
+    Ok(shown)
+}
+
+fn clear(world: &mut World, _args: &[&str]) -> anyhow::Result<String> {
+    if let Some(mut console) = world.get_resource_mut::<Console>() {
+        console.log.clear();
+    }
+    Ok(String::new())
+}
+
+/// What has been typed into the console and printed by it.
+#[derive(Debug, Default)]
+pub struct Console {
+    /// The line being typed.
+    pub input: String,
+    /// Lines entered but not yet run.
+    pending: Vec<String>,
+    /// Everything entered and printed, oldest first, wrapped to fit.
+    pub log: Vec<String>,
+}
+
+impl Console {
+    pub fn print(&mut self, text: &str) {
+        for line in text.lines() {
+            self.log.extend(wrap(line, LINE_LEN));
+        }
+        let excess = self.log.len().saturating_sub(MAX_LOG);
+        self.log.drain(..excess);
+    }
+}
+
+/// Split `line` into lines of at most `width` characters, between words where possible.
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for word in line.split(' ') {
+        if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+        while let Some((split, _)) = current.char_indices().nth(width) {
+            let rest = current.split_off(split);
+            lines.push(std::mem::replace(&mut current, rest));
+        }
+    }
+    lines.push(current);
+    lines
+}
+
+/// Part of the console overlay.
+#[derive(Component)]
+pub struct ConsoleScreen;
+
+/// The backquote key opens the console over anything, and closes it again.
+pub fn toggle(mut keys: ResMut<Input<KeyCode>>, mut state: ResMut<State<AppState>>) {
+    if !keys.just_pressed(KeyCode::Grave) {
+        return;
+    }
+    let _ = if *state.current() == AppState::Console {
+        state.pop()
+    } else {
+        state.push(AppState::Console)
+    };
+    keys.clear();
+}
+
+/// Draw the console as it was left.
+pub fn open(mut console: ResMut<Console>) {
+    console.set_changed();
+}
+
+/// Typed characters go on the end of the line, backspace takes them off and enter runs it.
+/// Escape closes the console.
+pub fn type_line(
+    mut console: ResMut<Console>,
+    mut chars: EventReader<ReceivedCharacter>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<AppState>>,
+) {
+    for event in chars.iter() {
+        // The key that opened the console may still be on its way in.
+        if (event.char.is_ascii_graphic() || event.char == ' ') && !matches!(event.char, '`' | '~')
+        {
+            console.input.push(event.char);
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        console.input.pop();
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        let line = std::mem::take(&mut console.input);
+        console.print(&format!("> {}", line));
+        console.pending.push(line);
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        let _ = state.pop();
+        // Don't let the game underneath take it as a pause.
+        keys.clear();
+    }
+}
+
+/// Run the lines entered this frame.
+pub fn run_lines(world: &mut World) {
+    if world.resource::<Console>().pending.is_empty() {
+        return;
+    }
+    let lines = std::mem::take(&mut world.resource_mut::<Console>().pending);
+    for line in lines {
+        let output = run_line(world, &line).unwrap_or_else(|err| format!("{:#}", err));
+        if !output.is_empty() {
+            world.resource_mut::<Console>().print(&output);
+        }
+    }
+}
+
+/// Redraw the console whenever it changes.
+pub fn draw(
+    mut commands: Commands,
+    console: Res<Console>,
+    query: Query<Entity, With<ConsoleScreen>>,
+) {
+    if !console.is_changed() {
+        return;
+    }
+    for e in query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+
+    let overlay = commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: OVERLAY_COLOR,
+                custom_size: Some(PLAYFIELD_SIZE.as_vec2()),
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 0.0, CONSOLE_Z - 1.0),
+            ..default()
+        })
+        .id();
+    commands.entity(overlay).insert(ConsoleScreen);
+
+    let first = console.log.len().saturating_sub(LOG_ROWS);
+    let prompt = format!("> {}_", console.input);
+    // The end of a long line, where the typing is.
+    let prompt = &prompt[prompt.len().saturating_sub(LINE_LEN)..];
+    let rows = console.log[first..]
+        .iter()
+        .map(|line| (line.as_str(), TEXT_COLOR))
+        .chain([(prompt, TITLE_COLOR)]);
+
+    let left = 4 - PLAYFIELD_SIZE.x / 2;
+    let top = PLAYFIELD_SIZE.y / 2 - 10;
+    for (row, (text, color)) in rows.enumerate() {
+        let center = IVec2::new(
+            left + font::text_size(text, 1).x / 2,
+            top - row as i32 * ROW_HEIGHT,
+        );
+        let e = font::spawn_text(&mut commands, text, center, CONSOLE_Z, 1, color);
+        commands.entity(e).insert(ConsoleScreen);
+    }
+}
+
+pub fn hide(mut commands: Commands, query: Query<Entity, With<ConsoleScreen>>) {
+    for e in query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Tuning(f32);
+
+    fn tuning_var() -> ConsoleVar {
+        ConsoleVar::new("test.tuning", "", 0.5..=2.0, |world| {
+            &mut world.resource_mut::<Tuning>().into_inner().0
+        })
+    }
+
+    #[test]
+    fn sets_values_in_range() {
+        let mut world = World::new();
+        world.insert_resource(Tuning(1.0));
+        let shown = (tuning_var().access)(&mut world, Some("2")).unwrap();
+        assert_eq!(shown, "test.tuning = 2");
+        assert_eq!(world.resource::<Tuning>().0, 2.0);
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        let mut world = World::new();
+        world.insert_resource(Tuning(1.0));
+        for value in ["-10", "1e9", "NaN", "inf", "fast"] {
+            assert!(
+                (tuning_var().access)(&mut world, Some(value)).is_err(),
+                "{}",
+                value
+            );
+        }
+        assert_eq!(world.resource::<Tuning>().0, 1.0);
+        let error = (tuning_var().access)(&mut world, Some("3")).unwrap_err();
+        assert_eq!(error.to_string(), "test.tuning takes 0.5 to 2, not 3");
+    }
+}
@@ -1,10 +1,27 @@
 //! Control system. Generates control events from user input.
+use crate::checksum::{ReplayCheck, StateChecksum};
+use crate::clock::GameClock;
+use crate::console::{self, ConsoleAppExt, ConsoleCommand};
+use crate::difficulty::{DifficultySwitches, PlayerSwitches};
+use crate::level::{CurrentRound, NewGameEvent};
+use crate::rng::GameRng;
+use crate::stats::SessionStats;
+use crate::storage;
 use crate::yar::{YarCommandEvent, YarRespawnEvent};
 use crate::zorlon_cannon::CannonCommandEvent;
+use crate::GameSystem;
+use anyhow::{anyhow, bail};
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::ecs::system::SystemState;
 use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
 use bevy::utils::Duration;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
-use std::time::Instant;
+
+/// Held commands are repeated once per frame, as if the keys were held down.
+const HELD_COMMAND_PERIOD: f32 = 1.0 / 60.0;
 
 /// Plugin for controlling Yar that alternates between control/replay on respawn
 pub struct ReplayControlPlugin;
@@ -14,25 +31,123 @@ impl Plugin for ReplayControlPlugin {
         app.insert_resource(ControlTarget::Yar)
             .insert_resource(Record::<YarCommandEvent>::default())
             .insert_resource(Record::<CannonCommandEvent>::default())
+            .init_resource::<BotCommand<YarCommandEvent>>()
+            .init_resource::<BotCommand<CannonCommandEvent>>()
+            .init_resource::<GameRecording>()
+            .add_asset::<Replay>()
+            .init_asset_loader::<ReplayLoader>()
             .add_startup_system(init_record_write::<YarCommandEvent>)
             .add_startup_system(init_record_read::<CannonCommandEvent>)
-            .add_system(on_yar_respawn)
-            .add_system(commands);
+            // Before the first round starts, so that it draws from the game's own seed.
+            .add_system(
+                new_game
+                    .label(GameSystem::Start)
+                    .before(crate::level::new_game),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .label(GameSystem::Input)
+                    .after(GameSystem::Start)
+                    .with_system(on_yar_respawn.before(commands))
+                    .with_system(commands.with_run_criteria(crate::not_paused)),
+            )
+            .add_system(
+                record_game
+                    .with_run_criteria(crate::not_paused)
+                    .label(GameSystem::Bookkeeping)
+                    .after(GameSystem::Spawn),
+            )
+            .add_console_command(ConsoleCommand {
+                name: "control",
+                usage: "yar|cannon",
+                help: "Take over Yar or the cannon, as if Yar had respawned.",
+                run: control,
+            })
+            .add_console_command(ConsoleCommand {
+                name: "record save",
+                usage: "NAME",
+                help: "Save the game so far to `saves/replays/NAME.replay`.",
+                run: record_save,
+            });
     }
 }
 
-pub fn init_record_write<E>(mut record: ResMut<Record<E>>, time: Res<Time>)
-where
+pub fn init_record_write<E>(
+    mut record: ResMut<Record<E>>,
+    clock: Res<GameClock>,
+    switches: Res<DifficultySwitches>,
+    round: Res<CurrentRound>,
+) where
     E: Send + Sync + 'static,
 {
-    record.set_write_mode(&time)
+    record.set_write_mode(&clock, ReplayHeader::new(&switches, round.variation))
 }
 
-pub fn init_record_read<E>(mut record: ResMut<Record<E>>, time: Res<Time>)
+pub fn init_record_read<E>(mut record: ResMut<Record<E>>, clock: Res<GameClock>)
 where
     E: Send + Sync + 'static,
 {
-    record.set_read_mode(&time)
+    record.set_read_mode(&clock)
+}
+
+/// The player starts each game flying Yar, unless the game is a replay, which plays out
+/// entirely from its records.
+///
+/// Each game draws its random numbers from a seed of its own, which goes into its replay so that
+/// the replay draws the same ones. A replay also plays under the difficulty switches it was
+/// recorded with, and the player's own come back with the next game they play.
+#[allow(clippy::too_many_arguments)]
+pub fn new_game(
+    mut new_game_event: EventReader<NewGameEvent>,
+    mut control_target: ResMut<ControlTarget>,
+    mut yar_record: ResMut<Record<YarCommandEvent>>,
+    mut cannon_record: ResMut<Record<CannonCommandEvent>>,
+    mut recording: ResMut<GameRecording>,
+    mut replay_check: ResMut<ReplayCheck>,
+    mut rng: ResMut<GameRng>,
+    mut switches: ResMut<DifficultySwitches>,
+    mut player_switches: ResMut<PlayerSwitches>,
+    clock: Res<GameClock>,
+    replays: Res<Assets<Replay>>,
+) {
+    for event in new_game_event.iter() {
+        let replay = event.replay.as_ref().and_then(|replay| replays.get(replay));
+        match replay {
+            Some(replay) => {
+                player_switches.0.get_or_insert(*switches);
+                *switches = replay.header.difficulty;
+            }
+            None => {
+                if let Some(own) = player_switches.0.take() {
+                    *switches = own;
+                }
+            }
+        }
+        let header = ReplayHeader::new(&switches, event.variation);
+        let seed = replay
+            .and_then(|replay| replay.seed)
+            .unwrap_or_else(|| rng.gen());
+        *rng = GameRng::seeded(seed);
+        *recording = GameRecording::new(&clock, header, seed);
+        match replay {
+            Some(replay) => {
+                *yar_record = Record::from_held(replay.header, &replay.yar);
+                *cannon_record = Record::from_held(replay.header, &replay.cannon);
+                yar_record.set_read_mode(&clock);
+                yar_record.check_header(&header);
+                cannon_record.set_read_mode(&clock);
+                *replay_check = ReplayCheck::new(&clock, &replay.checksums);
+                *control_target = ControlTarget::Demo;
+            }
+            None => {
+                yar_record.set_write_mode(&clock, header);
+                *cannon_record = Record::default();
+                cannon_record.set_read_mode(&clock);
+                *replay_check = ReplayCheck::default();
+                *control_target = ControlTarget::Yar;
+            }
+        }
+    }
 }
 
 pub fn on_yar_respawn(
@@ -40,67 +155,341 @@ pub fn on_yar_respawn(
     mut control_target: ResMut<ControlTarget>,
     mut yar_record: ResMut<Record<YarCommandEvent>>,
     mut cannon_record: ResMut<Record<CannonCommandEvent>>,
-    time: Res<Time>,
+    clock: Res<GameClock>,
+    switches: Res<DifficultySwitches>,
+    round: Res<CurrentRound>,
 ) {
     if yar_respawn.iter().next().is_none() {
         return;
     }
 
+    let header = ReplayHeader::new(&switches, round.variation);
+    switch_control(
+        &mut control_target,
+        &mut yar_record,
+        &mut cannon_record,
+        &clock,
+        header,
+    );
+}
+
+/// Hand the player whichever of Yar and the cannon they aren't controlling, recording it from
+/// now on, while the other replays its record.
+fn switch_control(
+    control_target: &mut ControlTarget,
+    yar_record: &mut Record<YarCommandEvent>,
+    cannon_record: &mut Record<CannonCommandEvent>,
+    clock: &GameClock,
+    header: ReplayHeader,
+) {
     *control_target = match *control_target {
         ControlTarget::Yar => {
-            yar_record.set_read_mode(&time);
-            cannon_record.set_write_mode(&time);
+            yar_record.set_read_mode(clock);
+            yar_record.check_header(&header);
+            cannon_record.set_write_mode(clock, header);
             ControlTarget::Cannon
         }
         ControlTarget::Cannon => {
-            yar_record.set_write_mode(&time);
-            cannon_record.set_read_mode(&time);
+            yar_record.set_write_mode(clock, header);
+            cannon_record.set_read_mode(clock);
+            cannon_record.check_header(&header);
             ControlTarget::Yar
         }
+        // A replay keeps playing from the same records until it runs out.
+        ControlTarget::Demo => ControlTarget::Demo,
+    };
+}
+
+fn control(world: &mut World, args: &[&str]) -> anyhow::Result<String> {
+    let target = match args {
+        [arg] if arg.eq_ignore_ascii_case("yar") => ControlTarget::Yar,
+        [arg] if arg.eq_ignore_ascii_case("cannon") => ControlTarget::Cannon,
+        _ => bail!("usage: control yar|cannon"),
+    };
+    let mut state: SystemState<(
+        ResMut<ControlTarget>,
+        ResMut<Record<YarCommandEvent>>,
+        ResMut<Record<CannonCommandEvent>>,
+        Res<GameClock>,
+        Res<DifficultySwitches>,
+        Res<CurrentRound>,
+    )> = SystemState::new(world);
+    {
+        let (mut control_target, mut yar_record, mut cannon_record, clock, switches, round) =
+            state.get_mut(world);
+        match *control_target {
+            ControlTarget::Demo => bail!("a replay is playing"),
+            current if current == target => bail!("already controlling {:?}", target),
+            _ => {}
+        }
+        let header = ReplayHeader::new(&switches, round.variation);
+        switch_control(
+            &mut control_target,
+            &mut yar_record,
+            &mut cannon_record,
+            &clock,
+            header,
+        );
+    }
+    console::discard_replay(world);
+    Ok(format!("controlling {:?}", target))
+}
+
+fn record_save(world: &mut World, args: &[&str]) -> anyhow::Result<String> {
+    let name = match args {
+        [name] => *name,
+        _ => bail!("usage: record save NAME"),
     };
+    let replay = world
+        .resource::<GameRecording>()
+        .replay
+        .as_ref()
+        .ok_or_else(|| anyhow!("this game can no longer be replayed"))?;
+    let key = replay_key(name)?;
+    let text = ron::ser::to_string_pretty(replay, ron::ser::PrettyConfig::new())?;
+    storage::save(&key, &text)?;
+    Ok(format!("saved {}", key))
+}
+
+/// Where `record save NAME` saves the game. The name stays a single file in the replays folder.
+fn replay_key(name: &str) -> anyhow::Result<String> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+        bail!("`{}` is not a file name", name);
+    }
+    // Whatever extension it's given, as replays only load from `.replay` files.
+    Ok(format!(
+        "replays/{}",
+        std::path::Path::new(name)
+            .with_extension("replay")
+            .display()
+    ))
 }
 
 /// Which object the player is controlling
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ControlTarget {
     Yar,
     Cannon,
+    /// Nothing; a replay is driving both Yar and the cannon.
+    Demo,
+}
+
+/// Game settings in effect while a record was written.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplayHeader {
+    pub difficulty: DifficultySwitches,
+    /// Game variation, as numbered on the title screen.
+    pub variation: u8,
+}
+
+impl ReplayHeader {
+    pub fn new(switches: &DifficultySwitches, variation: u8) -> Self {
+        Self {
+            difficulty: *switches,
+            variation,
+        }
+    }
+}
+
+/// A command held down for a stretch of a replay.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeldCommand<E> {
+    pub command: E,
+    /// Seconds from the start of the replay.
+    pub from: f32,
+    pub to: f32,
+}
+
+/// A recorded game, loaded from a RON `.replay` asset.
+///
+/// Input is stored as the commands held down over time rather than frame by frame, so that
+/// replays can be written by hand.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, TypeUuid)]
+#[uuid = "3d9a6f42-8c1e-4b75-9e20-5f4b7a1c8d36"]
+pub struct Replay {
+    #[serde(default)]
+    pub header: ReplayHeader,
+    /// Seed the game's random numbers were drawn from. Replays without one draw different
+    /// numbers each time they are played.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub yar: Vec<HeldCommand<YarCommandEvent>>,
+    #[serde(default)]
+    pub cannon: Vec<HeldCommand<CannonCommandEvent>>,
+    /// Checksums of the game's state every so often, to check the replay against as it plays.
+    #[serde(default)]
+    pub checksums: Vec<StateChecksum>,
+    /// How the game went, once it is over.
+    #[serde(default)]
+    pub stats: Option<SessionStats>,
+}
+
+/// Extend the last held command if `command` carries it on, otherwise start holding `command`.
+fn hold<E: PartialEq + Clone>(held: &mut Vec<HeldCommand<E>>, command: &E, at: f32) {
+    match held.last_mut() {
+        Some(last) if last.command == *command && at <= last.to + HELD_COMMAND_PERIOD / 2.0 => {
+            last.to = at + HELD_COMMAND_PERIOD;
+        }
+        _ => held.push(HeldCommand {
+            command: command.clone(),
+            from: at,
+            to: at + HELD_COMMAND_PERIOD,
+        }),
+    }
+}
+
+#[derive(Default)]
+pub struct ReplayLoader;
+
+impl AssetLoader for ReplayLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let replay: Replay = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(replay));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["replay"]
+    }
+}
+
+/// Every command given to Yar and the cannon since the game started, live or replayed, so the
+/// whole game can be saved as a replay.
+#[derive(Default, Debug, Clone)]
+pub struct GameRecording {
+    start_time: Option<Duration>,
+    start_tick: u64,
+    /// `None` once something has happened that a replay can't reproduce, such as restarting
+    /// a round.
+    pub replay: Option<Replay>,
+}
+
+impl GameRecording {
+    pub fn new(clock: &GameClock, header: ReplayHeader, seed: u64) -> Self {
+        Self {
+            start_time: Some(clock.elapsed),
+            start_tick: clock.tick,
+            replay: Some(Replay {
+                header,
+                seed: Some(seed),
+                ..default()
+            }),
+        }
+    }
+
+    /// Frames since the game started.
+    pub fn tick(&self, clock: &GameClock) -> u64 {
+        clock.tick.saturating_sub(self.start_tick)
+    }
+
+    /// Stop recording; the game can no longer be replayed.
+    pub fn discard(&mut self) {
+        self.replay = None;
+    }
 }
 
 /// Record of a time series of events.
 #[derive(Default, Debug, Clone)]
 pub struct Record<E> {
-    /// When the command sequence (read or write) was started.
-    pub start_time: Option<Instant>,
+    /// Settings the events were recorded under.
+    pub header: ReplayHeader,
+    /// Game clock time the command sequence (read or write) was started at.
+    pub start_time: Option<Duration>,
     /// For each command, a duration since `start_time`
     events: VecDeque<(E, Duration)>,
 }
 
+impl<E: Clone> Record<E> {
+    /// A record that repeats each held command every frame it is held for.
+    pub fn from_held(header: ReplayHeader, held: &[HeldCommand<E>]) -> Self {
+        let mut events: Vec<(E, Duration)> = held
+            .iter()
+            .flat_map(|held| {
+                // Held commands are whole frames long, give or take rounding.
+                let frames = ((held.to - held.from) / HELD_COMMAND_PERIOD)
+                    .round()
+                    .max(1.0) as u32;
+                (0..frames).map(move |frame| {
+                    let delay = held.from.max(0.0) + frame as f32 * HELD_COMMAND_PERIOD;
+                    (held.command.clone(), Duration::from_secs_f32(delay))
+                })
+            })
+            .collect();
+        events.sort_by_key(|(_, delay)| *delay);
+
+        Self {
+            header,
+            start_time: None,
+            events: events.into(),
+        }
+    }
+}
+
 impl<E> Record<E> {
-    pub fn set_read_mode(&mut self, time: &Time) {
-        self.start_time = Some(latest(time));
+    /// Whether every event has been replayed.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
     }
 
-    pub fn set_write_mode(&mut self, time: &Time) {
-        self.start_time = Some(latest(time));
+    pub fn set_read_mode(&mut self, clock: &GameClock) {
+        self.start_time = Some(clock.elapsed);
+    }
+
+    pub fn set_write_mode(&mut self, clock: &GameClock, header: ReplayHeader) {
+        self.header = header;
+        self.start_time = Some(clock.elapsed);
         self.events.clear();
     }
 
-    /// Push an event recorded at the given time as a duration from `start_time`.
-    pub fn push(&mut self, event: E, time: &Time) {
-        let delay = latest(time).duration_since(self.start_time.unwrap());
+    /// Warn if the record is about to be replayed under different settings than it was written.
+    pub fn check_header(&self, header: &ReplayHeader) {
+        if self.header != *header {
+            warn!(
+                "replaying a record written with {:?} under {:?}; it may play out differently",
+                self.header, header
+            );
+        }
+    }
+
+    /// Push an event recorded now as a duration from `start_time`.
+    pub fn push(&mut self, event: E, clock: &GameClock) {
+        let delay = since(clock, self.start_time.unwrap());
         self.events.push_back((event, delay));
     }
 
-    /// Pop the next command if its time offset from `start_time` is <= the given time.
-    pub fn pop_next_before(&mut self, time: &Time) -> Option<E> {
-        let delay = latest(time).duration_since(self.start_time.unwrap());
+    /// Time since `start_time` up to which commands are due. Commands are recorded on the frame
+    /// they were given, and stored times are rounded, so a command is due from half a frame
+    /// before its time; otherwise it would play a frame late, or not, by the luck of rounding.
+    fn due(&self, clock: &GameClock) -> Duration {
+        since(clock, self.start_time.unwrap()) + Duration::from_secs_f32(HELD_COMMAND_PERIOD / 2.0)
+    }
+
+    /// Pop the next command if it is due.
+    pub fn pop_next_before(&mut self, clock: &GameClock) -> Option<E> {
+        let delay = self.due(clock);
         if self.events.front()?.1 < delay {
             Some(self.events.pop_front()?.0)
         } else {
             None
         }
     }
+
+    /// Commands that will be popped within `lookahead` from now, without popping them.
+    pub fn peek_before(&self, clock: &GameClock, lookahead: Duration) -> impl Iterator<Item = &E> {
+        let delay = self.due(clock) + lookahead;
+        self.events
+            .iter()
+            .take_while(move |(_, event_delay)| *event_delay < delay)
+            .map(|(event, _)| event)
+    }
 }
 
 pub trait ControlEvent: for<'a> From<&'a Input<KeyCode>> + Send + Sync + 'static {
@@ -108,52 +497,127 @@ pub trait ControlEvent: for<'a> From<&'a Input<KeyCode>> + Send + Sync + 'static
     fn is_noop(&self) -> bool;
 }
 
+/// The command a bot gives its target this frame, used in place of the keyboard.
+#[derive(Debug, Clone)]
+pub struct BotCommand<E>(pub Option<E>);
+
+impl<E> Default for BotCommand<E> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
 /// Generate control command events
+#[allow(clippy::too_many_arguments)]
 pub fn commands(
     control_target: Res<ControlTarget>,
     keys: Res<Input<KeyCode>>,
+    mut yar_bot: ResMut<BotCommand<YarCommandEvent>>,
+    mut cannon_bot: ResMut<BotCommand<CannonCommandEvent>>,
     mut yar_record: ResMut<Record<YarCommandEvent>>,
     mut cannon_record: ResMut<Record<CannonCommandEvent>>,
     yar_commands: EventWriter<YarCommandEvent>,
     cannon_commands: EventWriter<CannonCommandEvent>,
-    time: Res<Time>,
+    clock: Res<GameClock>,
 ) {
+    // Bots only get a say while their target is live.
+    let yar_bot = yar_bot.0.take();
+    let cannon_bot = cannon_bot.0.take();
     match *control_target {
         ControlTarget::Yar => {
-            target_commands(true, &mut yar_record, &keys, &time, yar_commands);
-            target_commands(false, &mut cannon_record, &keys, &time, cannon_commands);
+            let live = live_command(yar_bot, &keys);
+            target_commands(Some(live), &mut yar_record, &clock, yar_commands);
+            target_commands(None, &mut cannon_record, &clock, cannon_commands);
         }
         ControlTarget::Cannon => {
-            target_commands(false, &mut yar_record, &keys, &time, yar_commands);
-            target_commands(true, &mut cannon_record, &keys, &time, cannon_commands);
+            let live = live_command(cannon_bot, &keys);
+            target_commands(None, &mut yar_record, &clock, yar_commands);
+            target_commands(Some(live), &mut cannon_record, &clock, cannon_commands);
+        }
+        ControlTarget::Demo => {
+            target_commands(None, &mut yar_record, &clock, yar_commands);
+            target_commands(None, &mut cannon_record, &clock, cannon_commands);
         }
     }
 }
 
-/// Generae commands for a particular target.
+/// The bot's command if a bot is flying the target, otherwise the keyboard's.
+fn live_command<E: ControlEvent>(bot: Option<E>, keys: &Input<KeyCode>) -> E {
+    bot.unwrap_or_else(|| E::from(keys))
+}
+
+/// Generae commands for a particular target: the live command if there is one, otherwise
+/// whatever is due from the record.
 pub fn target_commands<E>(
-    live: bool,
+    live: Option<E>,
     record: &mut Record<E>,
-    keys: &Input<KeyCode>,
-    time: &Time,
+    clock: &GameClock,
     mut commands: EventWriter<E>,
 ) where
     E: ControlEvent + Clone,
 {
-    if live {
-        let command = E::from(keys);
-        if !command.is_noop() {
-            record.push(command.clone(), time);
-            commands.send(command);
+    match live {
+        Some(command) => {
+            if !command.is_noop() {
+                record.push(command.clone(), clock);
+                commands.send(command);
+            }
         }
-    } else {
-        while let Some(command) = record.pop_next_before(time) {
-            commands.send(command);
+        None => {
+            while let Some(command) = record.pop_next_before(clock) {
+                commands.send(command);
+            }
         }
     }
 }
 
-/// The most recent instant recorded by a `Time`.
-fn latest(time: &Time) -> Instant {
-    time.last_update().unwrap_or_else(|| time.startup())
+pub fn record_game(
+    control_target: Res<ControlTarget>,
+    mut recording: ResMut<GameRecording>,
+    mut yar_commands: EventReader<YarCommandEvent>,
+    mut cannon_commands: EventReader<CannonCommandEvent>,
+    clock: Res<GameClock>,
+) {
+    // Replays are already saved.
+    if matches!(*control_target, ControlTarget::Demo) {
+        return;
+    }
+    let (start_time, replay) = match (recording.start_time, recording.replay.as_mut()) {
+        (Some(start_time), Some(replay)) => (start_time, replay),
+        _ => return,
+    };
+
+    let at = since(&clock, start_time).as_secs_f32();
+    for command in yar_commands.iter() {
+        hold(&mut replay.yar, command, at);
+    }
+    for command in cannon_commands.iter() {
+        hold(&mut replay.cannon, command, at);
+    }
+}
+
+/// Game time since `start_time`.
+fn since(clock: &GameClock, start_time: Duration) -> Duration {
+    clock.elapsed.saturating_sub(start_time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_save_as_replay_files_in_the_replays_folder() {
+        assert_eq!(replay_key("best").unwrap(), "replays/best.replay");
+        assert_eq!(replay_key("best.txt").unwrap(), "replays/best.replay");
+        for name in [
+            "",
+            "../best",
+            "saves/best",
+            "saves\\best",
+            "..",
+            "best..replay",
+        ] {
+            assert!(replay_key(name).is_err(), "`{}` was accepted", name);
+        }
+    }
 }
@@ -1,128 +1,250 @@
+use crate::clock::GameClock;
+use crate::level::CurrentRound;
+use crate::motion::{GamePos, Velocity};
 use crate::neutral_zone::{NeutralZone, NEUTRAL_ZONE_BOUNDS};
-use crate::qotile::Qotile;
+use crate::qotile::{Qotile, QotileDiedEvent};
 use crate::util;
-use crate::yar::{Yar, YarDiedEvent, YAR_BOUNDS};
-use crate::SCREEN_SCALE;
-use bevy::math::const_vec2;
+use crate::util::DespawnReason;
+use crate::yar::{DeathCause, GodMode, Yar, YarDiedEvent, YAR_BOUNDS};
+use crate::{AppState, GameSystem};
+use bevy::ecs::query::{FilterFetch, WorldQuery};
+use bevy::math::const_ivec2;
 use bevy::prelude::*;
+use bevy::utils::HashMap;
 
 // Gameplay Note: Not sure if the destroyer missile spawns instantly in all difficulty modes.
 // Need to check...
 
-const DESTROYER_MISSILE_SPEED: f32 = 0.5;
-pub const DESTROYER_MISSILE_BOUNDS: Vec2 = const_vec2!([8.0 * SCREEN_SCALE, 2.0 * SCREEN_SCALE]);
+pub const DESTROYER_MISSILE_BOUNDS: IVec2 = const_ivec2!([4, 2]);
+pub const DESTROYER_MISSILE_TEXTURE: &str = "destroyer_missile.png";
 
 //pub struct SpawnDestroyerMissileEvent;
-pub struct DespawnDestroyerMissileEvent;
+/// Despawn the given Destroyer Missile entity.
+pub struct DespawnDestroyerMissileEvent {
+    pub entity: Entity,
+    pub reason: DespawnReason,
+}
 
 #[derive(Component)]
-pub struct DestroyerMissile;
+pub struct DestroyerMissile {
+    /// The Qotile that launched this missile.
+    pub qotile: Entity,
+    /// The Yar this missile is hunting.
+    pub target: Entity,
+}
 
 pub struct DestroyerMissilePlugin;
 
 impl Plugin for DestroyerMissilePlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<DespawnDestroyerMissileEvent>()
-            .add_system(spawn)
-            .add_system(despawn)
-            .add_system(track)
-            .add_system(collide_yar);
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .label(GameSystem::Movement)
+                    .after(GameSystem::Input)
+                    .with_system(track.after(crate::bullet::shoot)),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .label(GameSystem::Collision)
+                    .after(GameSystem::Movement)
+                    .with_system(collide_yar.after(crate::zorlon_cannon::collide_yar)),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .label(GameSystem::Reaction)
+                    .after(GameSystem::Collision)
+                    .with_system(yar_died)
+                    .with_system(qotile_died.after(yar_died)),
+            )
+            .add_system(despawn.label(GameSystem::Spawn).after(GameSystem::Reaction))
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .label(GameSystem::Spawn)
+                    .after(GameSystem::Reaction)
+                    .with_system(spawn.after(despawn)),
+            );
     }
 }
 
 pub fn spawn(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    missile_query: Query<Entity, (With<DestroyerMissile>, Without<Yar>, Without<Qotile>)>,
-    qotile_query: Query<&Transform, (With<Qotile>, Without<Yar>, Without<DestroyerMissile>)>,
-    yar_query: Query<&Yar, (Without<DestroyerMissile>, Without<Qotile>)>,
+    missile_query: Query<&DestroyerMissile, (Without<Yar>, Without<Qotile>)>,
+    qotile_query: Query<
+        (Entity, &GamePos, &Transform),
+        (With<Qotile>, Without<Yar>, Without<DestroyerMissile>),
+    >,
+    yar_query: Query<(Entity, &GamePos, &Yar), (Without<DestroyerMissile>, Without<Qotile>)>,
 ) {
-    if !missile_query.is_empty() || yar_query.is_empty() || qotile_query.is_empty() {
-        return;
-    }
+    // Each Qotile keeps one missile in play. A missile outlives the Qotile that launched it, so
+    // a respawned Qotile inherits the orphaned missile rather than launching a new one.
+    let mut missile_count = missile_query.iter().count();
+    for (qotile_entity, qotile_pos, qotile_transform) in qotile_query.iter() {
+        if missile_count >= qotile_query.iter().count() {
+            return;
+        }
 
-    let yar = yar_query.single();
-    if yar.is_dead() {
-        return;
-    }
+        if missile_query
+            .iter()
+            .any(|missile| missile.qotile == qotile_entity)
+        {
+            continue;
+        }
 
-    let qotile_transform = qotile_query.single();
+        // There is nothing to hunt while every Yar is dead.
+        let target = match closest_live_yar(qotile_pos.0, &yar_query) {
+            Some((target, _)) => target,
+            None => return,
+        };
+
+        commands
+            .spawn_bundle(SpriteBundle {
+                texture: asset_server.load(DESTROYER_MISSILE_TEXTURE),
+                transform: *qotile_transform,
+                ..default()
+            })
+            .insert(*qotile_pos)
+            .insert(DestroyerMissile {
+                qotile: qotile_entity,
+                target,
+            });
+        missile_count += 1;
+    }
+}
 
-    commands
-        .spawn_bundle(SpriteBundle {
-            texture: asset_server.load("destroyer_missile.png"),
-            transform: *qotile_transform,
-            ..default()
+/// The live Yar closest to `point`, and its position.
+fn closest_live_yar<F>(
+    point: IVec2,
+    yar_query: &Query<(Entity, &GamePos, &Yar), F>,
+) -> Option<(Entity, IVec2)>
+where
+    F: WorldQuery,
+    F::Fetch: FilterFetch,
+{
+    yar_query
+        .iter()
+        .filter(|(_, _, yar)| !yar.is_dead())
+        .map(|(e, pos, _)| (e, pos.0))
+        .min_by_key(|(_, pos)| {
+            let offset = *pos - point;
+            offset.dot(offset)
         })
-        .insert(DestroyerMissile);
 }
 
 pub fn despawn(
     mut commands: Commands,
     mut despawn_event: EventReader<DespawnDestroyerMissileEvent>,
-    mut death_event: EventReader<YarDiedEvent>,
     query: Query<Entity, With<DestroyerMissile>>,
 ) {
-    if (despawn_event.iter().next().is_none() && death_event.iter().next().is_none())
-        || query.is_empty()
+    let targets: HashMap<Entity, DespawnReason> = despawn_event
+        .iter()
+        .map(|event| (event.entity, event.reason))
+        .collect();
+
+    for e in query.iter() {
+        if let Some(reason) = targets.get(&e) {
+            debug!("despawning destroyer missile {:?}: {:?}", e, reason);
+            commands.entity(e).despawn();
+        }
+    }
+}
+
+/// A missile is called off once the Yar it is hunting dies.
+pub fn yar_died(
+    mut death_event: EventReader<YarDiedEvent>,
+    mut despawn_event: EventWriter<DespawnDestroyerMissileEvent>,
+    query: Query<(Entity, &DestroyerMissile)>,
+) {
+    for YarDiedEvent {
+        yar: yar_entity, ..
+    } in death_event.iter()
     {
-        return;
+        for (e, missile) in query.iter() {
+            if missile.target == *yar_entity {
+                despawn_event.send(DespawnDestroyerMissileEvent {
+                    entity: e,
+                    reason: DespawnReason::Death,
+                });
+            }
+        }
     }
+}
 
-    let e = query.single();
-    commands.entity(e).despawn();
+/// Destroying a Qotile ends the round for its missile.
+pub fn qotile_died(
+    mut death_event: EventReader<QotileDiedEvent>,
+    mut despawn_event: EventWriter<DespawnDestroyerMissileEvent>,
+    query: Query<(Entity, &DestroyerMissile)>,
+) {
+    for QotileDiedEvent { qotile, .. } in death_event.iter() {
+        for (e, missile) in query.iter() {
+            if missile.qotile == *qotile {
+                despawn_event.send(DespawnDestroyerMissileEvent {
+                    entity: e,
+                    reason: DespawnReason::RoundEnd,
+                });
+            }
+        }
+    }
 }
 
 pub fn track(
-    mut missile_query: Query<&mut Transform, (With<DestroyerMissile>, Without<Yar>)>,
-    yar_query: Query<&Transform, (With<Yar>, Without<DestroyerMissile>)>,
+    clock: Res<GameClock>,
+    round: Res<CurrentRound>,
+    mut missile_query: Query<(&mut GamePos, &mut DestroyerMissile), Without<Yar>>,
+    yar_query: Query<(Entity, &GamePos, &Yar), Without<DestroyerMissile>>,
 ) {
-    if missile_query.is_empty() || yar_query.is_empty() {
-        return;
+    for (mut missile_pos, mut missile) in missile_query.iter_mut() {
+        // If the target is gone, hunt the closest Yar instead.
+        let target = match yar_query.get(missile.target) {
+            Ok((_, yar_pos, _)) => yar_pos.0,
+            Err(_) => match closest_live_yar(missile_pos.0, &yar_query) {
+                Some((target, pos)) => {
+                    missile.target = target;
+                    pos
+                }
+                None => continue,
+            },
+        };
+
+        let direction = (target - missile_pos.0).as_vec2().normalize_or_zero();
+        missile_pos.0 += Velocity::from_direction(direction, round.params.destroyer_missile_speed)
+            .step(clock.tick);
     }
-
-    let yar_transform = yar_query.single();
-    let mut missile_transform = missile_query.single_mut();
-    let direction = (yar_transform.translation - missile_transform.translation).normalize();
-
-    missile_transform.translation += direction * DESTROYER_MISSILE_SPEED;
 }
-
 pub fn collide_yar(
     mut death_event: EventWriter<YarDiedEvent>,
     mut despawn_event: EventWriter<DespawnDestroyerMissileEvent>,
-    yar_query: Query<&Transform, (With<DestroyerMissile>, Without<Yar>)>,
-    dm_query: Query<&Transform, (With<Yar>, Without<DestroyerMissile>)>,
-    nz_query: Query<&Transform, With<NeutralZone>>,
+    yar_query: Query<(Entity, &GamePos), (With<Yar>, Without<DestroyerMissile>)>,
+    dm_query: Query<(Entity, &GamePos), (With<DestroyerMissile>, Without<Yar>)>,
+    nz_query: Query<&GamePos, With<NeutralZone>>,
+    god_mode: Res<GodMode>,
 ) {
-    if dm_query.is_empty() || yar_query.is_empty() {
+    if god_mode.0 {
         return;
     }
-
-    let yar_transform = yar_query.single();
-    let dm_transform = dm_query.single();
-
-    if !nz_query.is_empty() {
-        let nz_transform = nz_query.single();
-
+    for (yar_entity, yar_pos) in yar_query.iter() {
         // Destroyer missile cannot harm Yar if it is within the neutral zone.
-        if util::intersect_rect(
-            &dm_transform.translation,
-            &DESTROYER_MISSILE_BOUNDS,
-            &nz_transform.translation,
-            &NEUTRAL_ZONE_BOUNDS,
-        ) {
-            return;
+        if nz_query
+            .iter()
+            .any(|nz_pos| util::intersect_rect(yar_pos, &YAR_BOUNDS, nz_pos, &NEUTRAL_ZONE_BOUNDS))
+        {
+            continue;
         }
-    }
 
-    if util::intersect_rect(
-        &yar_transform.translation,
-        &YAR_BOUNDS,
-        &dm_transform.translation,
-        &DESTROYER_MISSILE_BOUNDS,
-    ) {
-        death_event.send(YarDiedEvent);
-        despawn_event.send(DespawnDestroyerMissileEvent);
+        for (dm_entity, dm_pos) in dm_query.iter() {
+            if util::intersect_rect(yar_pos, &YAR_BOUNDS, dm_pos, &DESTROYER_MISSILE_BOUNDS) {
+                death_event.send(YarDiedEvent {
+                    yar: yar_entity,
+                    cause: DeathCause::DestroyerMissile,
+                    pos: yar_pos.0,
+                });
+                despawn_event.send(DespawnDestroyerMissileEvent {
+                    entity: dm_entity,
+                    reason: DespawnReason::Collision,
+                });
+            }
+        }
     }
 }
@@ -0,0 +1,99 @@
+//! The console's difficulty switches.
+use crate::control::GameRecording;
+use crate::{AppState, GameSystem};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub struct DifficultyPlugin;
+
+impl Plugin for DifficultyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DifficultySwitches>()
+            .init_resource::<PlayerSwitches>()
+            // A switch flipped in the frame a game starts counts for that game.
+            .add_system(
+                toggle
+                    .label(GameSystem::Start)
+                    .before(crate::control::new_game),
+            );
+    }
+}
+
+/// Position of a difficulty switch.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Difficulty {
+    /// Advanced: the Zorlon Cannon bounces off the shield, the Swirl launches sooner and Yar is
+    /// knocked further back by each bite of the shield.
+    A,
+    /// Beginner.
+    #[default]
+    B,
+}
+
+/// One difficulty switch per player, as on the 2600 console, in the positions the game is
+/// played under.
+///
+/// The left switch belongs to player one and the right switch to player two. There are no
+/// two-player games, so the right switch is recorded in replays but changes nothing.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DifficultySwitches {
+    pub left: Difficulty,
+    pub right: Difficulty,
+}
+
+impl DifficultySwitches {
+    /// The switch of the player currently playing, who is always player one.
+    pub fn active(&self) -> Difficulty {
+        self.left
+    }
+}
+
+/// The player's own switches, set aside while a replay plays under the switches it was
+/// recorded with.
+#[derive(Debug, Default)]
+pub struct PlayerSwitches(pub Option<DifficultySwitches>);
+
+/// Flip the switches from the keyboard, using the same keys as the Stella emulator.
+///
+/// While a replay plays, the keys flip the player's own switches for when it is over. Flipping a
+/// switch during a game changes how the rest of it plays, which its replay can't reproduce.
+pub fn toggle(
+    keys: Res<Input<KeyCode>>,
+    mut switches: ResMut<DifficultySwitches>,
+    mut player_switches: ResMut<PlayerSwitches>,
+    mut recording: ResMut<GameRecording>,
+    state: Res<State<AppState>>,
+) {
+    let replaying = player_switches.0.is_some();
+    let switches = match player_switches.0.as_mut() {
+        Some(player_switches) => player_switches,
+        None => &mut *switches,
+    };
+    let before = *switches;
+
+    if keys.just_pressed(KeyCode::F5) {
+        switches.left = Difficulty::B;
+    }
+    if keys.just_pressed(KeyCode::F6) {
+        switches.left = Difficulty::A;
+    }
+    if keys.just_pressed(KeyCode::F7) {
+        switches.right = Difficulty::B;
+    }
+    if keys.just_pressed(KeyCode::F8) {
+        switches.right = Difficulty::A;
+    }
+
+    if *switches == before {
+        return;
+    }
+    info!("difficulty switches: {:?}", *switches);
+    let in_game = matches!(
+        state.current(),
+        AppState::Playing | AppState::Paused | AppState::Console | AppState::QotileDeathSequence
+    );
+    if in_game && !replaying {
+        recording.discard();
+    }
+}
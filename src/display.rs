@@ -0,0 +1,114 @@
+//! Gets the Atari's playfield onto the window. The camera looks at the playfield in Atari pixels
+//! and stretches them to fit the window, whatever its size.
+use crate::{ATARI_RES_X, ATARI_RES_Y, PIXEL_ASPECT, PLAYFIELD_SIZE};
+use bevy::prelude::*;
+use bevy::render::camera::ScalingMode;
+use bevy::window::WindowResized;
+
+/// Above everything else in the playfield.
+const LETTERBOX_Z: f32 = 900.0;
+
+pub struct DisplayPlugin;
+
+impl Plugin for DisplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DisplaySettings>()
+            .add_startup_system(setup)
+            .add_system(fit_to_window);
+    }
+}
+
+/// How the playfield is fitted to the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplaySettings {
+    /// Only scale by whole numbers, so every Atari pixel is drawn the same size.
+    pub integer_scaling: bool,
+    /// Hide anything outside the playfield behind black bars.
+    pub letterbox: bool,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            integer_scaling: true,
+            letterbox: true,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct GameCamera;
+
+#[derive(Component)]
+pub struct LetterboxMask;
+
+pub fn setup(mut commands: Commands) {
+    let mut camera = OrthographicCameraBundle::new_2d();
+    camera.orthographic_projection.scaling_mode = ScalingMode::None;
+    commands.spawn_bundle(camera).insert(GameCamera);
+
+    // One bar on each side of the playfield, big enough to cover any window.
+    let playfield_size = PLAYFIELD_SIZE.as_vec2();
+    let bar_size = playfield_size * 16.0;
+    let offsets = [
+        Vec2::new(-(playfield_size.x + bar_size.x) / 2.0, 0.0),
+        Vec2::new((playfield_size.x + bar_size.x) / 2.0, 0.0),
+        Vec2::new(0.0, -(playfield_size.y + bar_size.y) / 2.0),
+        Vec2::new(0.0, (playfield_size.y + bar_size.y) / 2.0),
+    ];
+    for offset in offsets {
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: Color::BLACK,
+                    custom_size: Some(bar_size),
+                    ..default()
+                },
+                transform: Transform::from_translation(offset.extend(LETTERBOX_Z)),
+                ..default()
+            })
+            .insert(LetterboxMask);
+    }
+}
+
+/// Window pixels per scanline when the playfield is fitted into a `width` by `height` window.
+pub fn window_scale(settings: &DisplaySettings, width: f32, height: f32) -> f32 {
+    let scale = (width / (ATARI_RES_X as f32 * PIXEL_ASPECT)).min(height / ATARI_RES_Y as f32);
+    if settings.integer_scaling {
+        scale.floor().max(1.0)
+    } else {
+        scale
+    }
+}
+
+pub fn fit_to_window(
+    mut resize_event: EventReader<WindowResized>,
+    settings: Res<DisplaySettings>,
+    windows: Res<Windows>,
+    mut camera_query: Query<&mut OrthographicProjection, With<GameCamera>>,
+    mut mask_query: Query<&mut Visibility, With<LetterboxMask>>,
+) {
+    if resize_event.iter().count() == 0 && !settings.is_changed() {
+        return;
+    }
+
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    let scale = window_scale(&settings, window.width(), window.height());
+    let half_width = window.width() / (2.0 * scale * PIXEL_ASPECT);
+    let half_height = window.height() / (2.0 * scale);
+
+    for mut projection in camera_query.iter_mut() {
+        projection.left = -half_width;
+        projection.right = half_width;
+        projection.bottom = -half_height;
+        projection.top = half_height;
+    }
+
+    for mut visibility in mask_query.iter_mut() {
+        visibility.is_visible = settings.letterbox;
+    }
+}
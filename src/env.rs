@@ -0,0 +1,603 @@
+//! A reinforcement learning environment over the game, in the style of Gym.
+//!
+//! The game runs headless at a fixed step of one 2600 frame per update, with everything random
+//! drawn from the seed given to `reset`, so the same seed and actions always play out the same
+//! way. The agent plays whichever of Yar and the cannon the player would be controlling; the
+//! other replays its last life, as in the game.
+use crate::atlas::AtlasManifest;
+use crate::bot::{BotView, Playfield};
+use crate::checksum::{ChecksumPeriod, Desync, ReplayCheck};
+use crate::clock::{FixedStep, GameClock};
+use crate::control::{BotCommand, ControlTarget, GameRecording, Replay};
+use crate::destroyer_missile::DESTROYER_MISSILE_TEXTURE;
+use crate::difficulty::DifficultySwitches;
+use crate::framebuffer::{self, Framebuffer};
+use crate::level::{GameOverEvent, Level, NewGameEvent, DEFAULT_LEVEL, VARIATIONS};
+use crate::qotile::{QotileDiedEvent, SwirlState, QOTILE_TEXTURE};
+use crate::rng::GameRng;
+use crate::score::ScoreEvent;
+use crate::telemetry::TelemetryPlugin;
+use crate::yar::{Lives, YarCommandEvent, YarDiedEvent, YarDirection, YAR_LIVES};
+use crate::zorlon_cannon::{CannonCommandEvent, CannonDirection};
+use crate::{AppState, GamePlugin, GameState, PLAYFIELD_SIZE};
+use anyhow::{anyhow, bail};
+use bevy::asset::{AssetPlugin, AssetServerSettings, HandleId, LoadState};
+use bevy::core::CorePlugin;
+use bevy::ecs::event::{Events, ManualEventReader};
+use bevy::ecs::schedule::{ParallelSystemContainer, ParallelSystemExecutor};
+use bevy::ecs::system::SystemState;
+use bevy::prelude::*;
+use bevy::render::texture::ImageTextureLoader;
+use bevy::transform::TransformPlugin;
+use bevy::utils::Duration;
+use std::path::PathBuf;
+
+/// One 2600 frame; the console drew 60 a second.
+pub const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+/// Actions in the discrete action space: standing still or one of the eight directions, each
+/// with or without firing.
+pub const ACTION_COUNT: usize = 2 * (YarDirection::ALL.len() + 1);
+/// Length of `Observation::features`.
+pub const FEATURE_COUNT: usize = 20;
+/// Updates to wait for assets to load before giving up.
+const LOAD_ATTEMPTS: u32 = 2000;
+const LOAD_POLL: Duration = Duration::from_millis(5);
+/// Updates run once everything has loaded, so that anything reacting to the loads has done so
+/// before the game starts.
+const SETTLE_FRAMES: u32 = 3;
+/// Updates to wait for the first round to start before giving up.
+const START_FRAMES: u32 = 10;
+/// A second's worth of frames.
+pub const DEFAULT_CHECKSUM_PERIOD: u32 = 60;
+
+/// What the agent does for a step.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Action {
+    pub direction: Option<YarDirection>,
+    pub shoot: bool,
+}
+
+impl Action {
+    /// The action numbered `index`, counting standing still first and then the directions in
+    /// the order of `YarDirection::ALL`, each without and then with firing.
+    pub fn from_index(index: usize) -> Option<Self> {
+        if index >= ACTION_COUNT {
+            return None;
+        }
+        let direction = match index / 2 {
+            0 => None,
+            direction => Some(YarDirection::ALL[direction - 1]),
+        };
+        Some(Self {
+            direction,
+            shoot: index % 2 == 1,
+        })
+    }
+
+    pub fn index(&self) -> usize {
+        let direction = self.direction.map_or(0, |direction| {
+            YarDirection::ALL
+                .iter()
+                .position(|d| *d == direction)
+                .unwrap()
+                + 1
+        });
+        direction * 2 + usize::from(self.shoot)
+    }
+
+    fn yar_command(&self) -> YarCommandEvent {
+        YarCommandEvent {
+            direction: self.direction,
+            shoot: self.shoot,
+        }
+    }
+
+    /// The cannon only moves up and down, so it follows the vertical part of the direction, as
+    /// it does from the keyboard.
+    fn cannon_command(&self) -> CannonCommandEvent {
+        let dy = self
+            .direction
+            .map_or(0, |direction| IVec2::from(direction).y);
+        CannonCommandEvent {
+            direction: match dy {
+                1 => Some(CannonDirection::Up),
+                -1 => Some(CannonDirection::Down),
+                _ => None,
+            },
+            shoot: self.shoot,
+        }
+    }
+}
+
+/// What happened during a step, for working out its reward.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct StepEvents {
+    pub points: u32,
+    pub yar_deaths: u32,
+    pub qotile_deaths: u32,
+}
+
+/// The default reward: the points scored.
+pub fn points_reward(events: &StepEvents) -> f32 {
+    events.points as f32
+}
+
+#[derive(Debug, Clone)]
+pub struct EnvConfig {
+    /// Game variation, as numbered on the title screen.
+    pub variation: u8,
+    pub difficulty: DifficultySwitches,
+    /// Frames each action is held for.
+    pub frame_skip: u32,
+    /// Each side of the observed frame is this many times smaller than the playfield.
+    pub downsample: u32,
+    /// Turns what happened during a step into its reward.
+    pub reward: fn(&StepEvents) -> f32,
+    /// Directory the game's assets are loaded from.
+    pub asset_folder: String,
+    /// Frames between checksums in the replay of each game; see `checksum`.
+    pub checksum_period: Option<u32>,
+    /// File to write the game's events to; see `telemetry`.
+    pub telemetry: Option<PathBuf>,
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self {
+            variation: 1,
+            difficulty: DifficultySwitches::default(),
+            frame_skip: 1,
+            downsample: 1,
+            reward: points_reward,
+            asset_folder: AssetServerSettings::default().asset_folder,
+            checksum_period: Some(DEFAULT_CHECKSUM_PERIOD),
+            telemetry: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Observation {
+    /// A compact description of the playfield; see `features`.
+    pub features: Vec<f32>,
+    pub frame: Framebuffer,
+}
+
+#[derive(Default)]
+struct EventCursors {
+    score: ManualEventReader<ScoreEvent>,
+    yar_died: ManualEventReader<YarDiedEvent>,
+    qotile_died: ManualEventReader<QotileDiedEvent>,
+    game_over: ManualEventReader<GameOverEvent>,
+}
+
+/// A game in progress, and everything needed to step it.
+struct Episode {
+    app: App,
+    playfield: SystemState<Playfield<'static, 'static>>,
+    cursors: EventCursors,
+    done: bool,
+    /// Handles to everything the game loads, so it stays loaded.
+    _assets: Vec<HandleUntyped>,
+}
+
+pub struct YarsEnv {
+    config: EnvConfig,
+    episode: Option<Episode>,
+}
+
+impl YarsEnv {
+    pub fn new(config: EnvConfig) -> Self {
+        Self {
+            config,
+            episode: None,
+        }
+    }
+
+    pub fn config(&self) -> &EnvConfig {
+        &self.config
+    }
+
+    /// Start a new game, with everything random drawn from `seed`.
+    pub fn reset(&mut self, seed: u64) -> anyhow::Result<Observation> {
+        let (mut app, assets) = headless_app(&self.config, seed)?;
+        let playfield = start_game(&mut app, self.config.variation, None)?;
+        let mut episode = Episode {
+            app,
+            playfield,
+            cursors: EventCursors::default(),
+            done: false,
+            _assets: assets,
+        };
+        // Nothing that happened while starting up counts towards the first step.
+        episode.collect_events();
+        let observation = episode.observe(&self.config);
+        self.episode = Some(episode);
+        Ok(observation)
+    }
+
+    /// Play `action` for a step. Returns what the agent sees afterwards, its reward and whether
+    /// the game is over.
+    ///
+    /// Panics if the environment hasn't been reset.
+    pub fn step(&mut self, action: Action) -> (Observation, f32, bool) {
+        self.step_commands(action.yar_command(), action.cannon_command())
+    }
+
+    /// Step with commands for Yar and the cannon given directly. Only the one the player is
+    /// flying takes its command.
+    pub fn step_commands(
+        &mut self,
+        yar: YarCommandEvent,
+        cannon: CannonCommandEvent,
+    ) -> (Observation, f32, bool) {
+        let episode = self
+            .episode
+            .as_mut()
+            .expect("reset the environment before stepping it");
+
+        let mut events = StepEvents::default();
+        for _ in 0..self.config.frame_skip.max(1) {
+            if episode.done {
+                break;
+            }
+            let world = &mut episode.app.world;
+            world.insert_resource(BotCommand(Some(yar)));
+            world.insert_resource(BotCommand(Some(cannon)));
+            episode.app.update();
+
+            let frame_events = episode.collect_events();
+            events.points += frame_events.points;
+            events.yar_deaths += frame_events.yar_deaths;
+            events.qotile_deaths += frame_events.qotile_deaths;
+        }
+
+        let reward = (self.config.reward)(&events);
+        (episode.observe(&self.config), reward, episode.done)
+    }
+
+    /// The game so far as a replay, with checksums every `EnvConfig::checksum_period` frames.
+    pub fn replay(&mut self) -> Option<Replay> {
+        self.world()?.resource::<GameRecording>().replay.clone()
+    }
+
+    /// The game's world, once the environment has been reset.
+    pub fn world(&mut self) -> Option<&mut World> {
+        self.episode.as_mut().map(|episode| &mut episode.app.world)
+    }
+}
+
+impl Episode {
+    /// Events sent since the last call.
+    fn collect_events(&mut self) -> StepEvents {
+        let world = &self.app.world;
+        let cursors = &mut self.cursors;
+        let events = StepEvents {
+            points: cursors
+                .score
+                .iter(world.resource::<Events<ScoreEvent>>())
+                .map(|event| event.points)
+                .sum(),
+            yar_deaths: cursors
+                .yar_died
+                .iter(world.resource::<Events<YarDiedEvent>>())
+                .count() as u32,
+            qotile_deaths: cursors
+                .qotile_died
+                .iter(world.resource::<Events<QotileDiedEvent>>())
+                .count() as u32,
+        };
+        if cursors
+            .game_over
+            .iter(world.resource::<Events<GameOverEvent>>())
+            .next()
+            .is_some()
+        {
+            self.done = true;
+        }
+        events
+    }
+
+    fn observe(&mut self, config: &EnvConfig) -> Observation {
+        let world = &mut self.app.world;
+        let view = self.playfield.get_mut(world).view();
+        let controlling_cannon =
+            matches!(*world.resource::<ControlTarget>(), ControlTarget::Cannon);
+        let lives = world.resource::<Lives>().0;
+        Observation {
+            features: features(&view, controlling_cannon, lives),
+            frame: framebuffer::render(world).downsample(config.downsample),
+        }
+    }
+}
+
+/// The game, without a window, loaded and ready to start. Returns handles to everything it has
+/// loaded, which have to be kept to keep them loaded.
+fn headless_app(config: &EnvConfig, seed: u64) -> anyhow::Result<(App, Vec<HandleUntyped>)> {
+    let mut app = App::new();
+    app.insert_resource(AssetServerSettings {
+        asset_folder: config.asset_folder.clone(),
+        watch_for_changes: false,
+    })
+    .insert_resource(GameRng::seeded(seed))
+    .insert_resource(FixedStep(Some(FRAME)))
+    .insert_resource(ChecksumPeriod(config.checksum_period))
+    .insert_resource(config.difficulty)
+    .add_plugin(CorePlugin)
+    .add_plugin(TransformPlugin)
+    .add_plugin(AssetPlugin)
+    .init_resource::<Input<KeyCode>>()
+    .add_asset::<Image>()
+    .init_asset_loader::<ImageTextureLoader>()
+    .add_asset::<TextureAtlas>()
+    .add_plugin(GamePlugin);
+    if let Some(path) = &config.telemetry {
+        app.add_plugin(TelemetryPlugin { path: path.clone() });
+    }
+    order_systems(&mut app);
+    // Runs the startup systems, which start loading the sprites.
+    app.update();
+
+    let level_path = VARIATIONS
+        .get((config.variation as usize).saturating_sub(1))
+        .copied()
+        .unwrap_or(DEFAULT_LEVEL);
+    let asset_server = app.world.resource::<AssetServer>().clone();
+    let level: Handle<Level> = asset_server.load(level_path);
+    let game_state = app.world.resource::<GameState>();
+    let mut assets = vec![
+        level.clone_untyped(),
+        game_state.sprite_manifest.clone_untyped(),
+        game_state.sprite_atlas.clone_untyped(),
+        asset_server.load_untyped(QOTILE_TEXTURE),
+        asset_server.load_untyped(DESTROYER_MISSILE_TEXTURE),
+    ];
+    wait_for_assets(&mut app, &assets)?;
+
+    // Every shield the level will need, so that rounds start without waiting for one, and the
+    // sprite sheet, so that frames are drawn from the first.
+    let sprite_manifest = app.world.resource::<GameState>().sprite_manifest.clone();
+    let sprite_sheet = app
+        .world
+        .resource::<Assets<AtlasManifest>>()
+        .get(&sprite_manifest)
+        .ok_or_else(|| anyhow!("the sprite atlas did not load"))?
+        .texture
+        .clone();
+    let mut dependencies: Vec<HandleUntyped> = app
+        .world
+        .resource::<Assets<Level>>()
+        .get(&level)
+        .ok_or_else(|| anyhow!("level `{}` did not load", level_path))?
+        .rounds
+        .iter()
+        .map(|round| asset_server.load_untyped(round.shield_shape.as_str()))
+        .collect();
+    dependencies.push(asset_server.load_untyped(sprite_sheet.as_str()));
+    wait_for_assets(&mut app, &dependencies)?;
+    assets.extend(dependencies);
+    for _ in 0..SETTLE_FRAMES {
+        app.update();
+    }
+
+    // However many frames loading took, the game starts from the first.
+    *app.world.resource_mut::<GameClock>() = GameClock::default();
+    Ok((app, assets))
+}
+
+/// Start a game of `variation`, or play `replay`, and update `app` until its first round is
+/// under way.
+fn start_game(
+    app: &mut App,
+    variation: u8,
+    replay: Option<Handle<Replay>>,
+) -> anyhow::Result<SystemState<Playfield<'static, 'static>>> {
+    app.world
+        .resource_mut::<Events<NewGameEvent>>()
+        .send(NewGameEvent { variation, replay });
+    app.world
+        .resource_mut::<State<AppState>>()
+        .set(AppState::Playing)?;
+
+    // The game is under way once the first round has set up the playfield.
+    let mut playfield = SystemState::<Playfield>::new(&mut app.world);
+    for _ in 0..START_FRAMES {
+        app.update();
+        let view = playfield.get_mut(&mut app.world).view();
+        if view.qotile.is_some() && !view.shield.is_empty() {
+            return Ok(playfield);
+        }
+    }
+    bail!("the first round did not start")
+}
+
+/// How a replay played back against its checksums.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Frames played.
+    pub ticks: u64,
+    /// Checksums that matched.
+    pub matched: usize,
+    /// Checksums that weren't checked, because the game ended or desynced first.
+    pub unchecked: usize,
+    pub desync: Option<Desync>,
+}
+
+/// Play `replay` headless until every checksum in it has been checked, it desyncs or the game
+/// ends. The replay's own variation and difficulty are played rather than the config's.
+pub fn verify_replay(config: &EnvConfig, replay: Replay) -> anyhow::Result<ReplayReport> {
+    let config = EnvConfig {
+        variation: replay.header.variation.max(1),
+        difficulty: replay.header.difficulty,
+        ..config.clone()
+    };
+    let checksums = replay.checksums.len();
+    let last_tick = replay.checksums.last().map_or(0, |checksum| checksum.tick);
+
+    let (mut app, _assets) = headless_app(&config, 0)?;
+    let replay = app.world.resource_mut::<Assets<Replay>>().add(replay);
+    start_game(&mut app, config.variation, Some(replay))?;
+
+    let mut game_over = ManualEventReader::<GameOverEvent>::default();
+    loop {
+        let check = app.world.resource::<ReplayCheck>();
+        let ticks = check.tick(app.world.resource::<GameClock>());
+        let ended = game_over
+            .iter(app.world.resource::<Events<GameOverEvent>>())
+            .next()
+            .is_some();
+        if check.is_finished() || ended || ticks > last_tick {
+            return Ok(ReplayReport {
+                ticks,
+                matched: check.matched,
+                unchecked: checksums - check.matched - usize::from(check.desync.is_some()),
+                desync: check.desync,
+            });
+        }
+        app.update();
+    }
+}
+
+/// Run the systems of `app`'s stages with `OrderedExecutor`, as both the window and the
+/// environment do.
+///
+/// The game's systems are ordered wherever they share data (see `GameSystem`), so the window
+/// and the environment play a game the same way. This is a safety net for what those edges
+/// can't see: systems that only share `Commands` apply them in the stage's order, which
+/// decides the entity ids they spawn.
+pub(crate) fn order_systems(app: &mut App) {
+    for stage in [
+        CoreStage::First,
+        CoreStage::PreUpdate,
+        CoreStage::Update,
+        CoreStage::PostUpdate,
+        CoreStage::Last,
+    ] {
+        app.schedule.stage(stage, |stage: &mut SystemStage| {
+            stage.set_executor(Box::new(OrderedExecutor::default()));
+            stage
+        });
+    }
+}
+
+/// Runs a stage's systems one at a time in the same order every time: dependencies first, and
+/// otherwise by name.
+///
+/// A stage sorts its systems through a randomly seeded hash map, so two apps built the same way
+/// needn't agree on the order of systems with no edge between them. None of the game's systems
+/// that share data are left that way, but the order also decides which system's commands are
+/// applied first at the end of the stage, so the systems themselves are put in order.
+#[derive(Default)]
+struct OrderedExecutor {
+    sorted: bool,
+}
+
+impl ParallelSystemExecutor for OrderedExecutor {
+    fn rebuild_cached_data(&mut self, _: &[ParallelSystemContainer]) {
+        self.sorted = false;
+    }
+
+    fn run_systems(&mut self, systems: &mut [ParallelSystemContainer], world: &mut World) {
+        if !self.sorted {
+            sort_systems(systems);
+            self.sorted = true;
+        }
+        for system in systems {
+            if system.should_run() {
+                system.system_mut().run((), world);
+            }
+        }
+    }
+}
+
+/// Put `systems` in dependency order, breaking ties by name.
+fn sort_systems(systems: &mut [ParallelSystemContainer]) {
+    let mut order: Vec<usize> = Vec::with_capacity(systems.len());
+    let mut placed = vec![false; systems.len()];
+    while order.len() < systems.len() {
+        let next = (0..systems.len())
+            .filter(|&i| !placed[i])
+            .filter(|&i| systems[i].dependencies().iter().all(|&d| placed[d]))
+            .min_by_key(|&i| systems[i].name())
+            .expect("the stage has already checked its systems for cycles");
+        placed[next] = true;
+        order.push(next);
+    }
+
+    // Move each system to its place, keeping track of where the ones it displaces end up.
+    let mut position: Vec<usize> = (0..systems.len()).collect();
+    let mut original: Vec<usize> = (0..systems.len()).collect();
+    for (i, system) in order.into_iter().enumerate() {
+        let from = position[system];
+        systems.swap(i, from);
+        let displaced = original[i];
+        original.swap(i, from);
+        position[displaced] = from;
+        position[system] = i;
+    }
+}
+
+/// Update `app` until everything in `handles` has loaded.
+fn wait_for_assets(app: &mut App, handles: &[HandleUntyped]) -> anyhow::Result<()> {
+    for _ in 0..LOAD_ATTEMPTS {
+        app.update();
+        let ids: Vec<HandleId> = handles.iter().map(|handle| handle.id).collect();
+        match app
+            .world
+            .resource::<AssetServer>()
+            .get_group_load_state(ids)
+        {
+            LoadState::Loaded => return Ok(()),
+            LoadState::Failed => bail!("the game's assets failed to load"),
+            _ => std::thread::sleep(LOAD_POLL),
+        }
+    }
+    bail!("timed out loading the game's assets")
+}
+
+/// The playfield as `FEATURE_COUNT` numbers. Positions are scaled so that the playfield runs
+/// from -1 to 1 each way, and anything missing is all zeros.
+///
+/// | index  | feature                                                   |
+/// |--------|-----------------------------------------------------------|
+/// | 0..3   | Yar's position, and 1 if Yar is alive                      |
+/// | 3..6   | Qotile's position, and 1 if Qotile is there                |
+/// | 6..8   | 1 if Qotile is swirling in place, 1 if the swirl has launched |
+/// | 8..11  | the Destroyer Missile's position, and 1 if it is there     |
+/// | 11..15 | the Zorlon Cannon's position, 1 if it is there, 1 if fired |
+/// | 15..18 | offset from Yar to the nearest shield block, and 1 if any  |
+/// | 18     | 1 if the agent is controlling the cannon rather than Yar   |
+/// | 19     | the fraction of a game's lives left                        |
+pub fn features(view: &BotView, controlling_cannon: bool, lives: u32) -> Vec<f32> {
+    let half_size = (PLAYFIELD_SIZE / 2).as_vec2();
+    let position = |pos: Option<IVec2>| match pos {
+        Some(pos) => {
+            let pos = pos.as_vec2() / half_size;
+            [pos.x, pos.y, 1.0]
+        }
+        None => [0.0; 3],
+    };
+    let flag = |value: bool| if value { 1.0 } else { 0.0 };
+
+    let swirl = view.qotile.map(|(_, state)| state);
+    let nearest_block = view.yar.and_then(|yar| {
+        view.shield
+            .iter()
+            .map(|block| *block - yar)
+            .min_by_key(|offset| offset.abs().max_element())
+    });
+
+    let mut features = Vec::with_capacity(FEATURE_COUNT);
+    features.extend(position(view.yar));
+    features.extend(position(view.qotile.map(|(pos, _)| pos)));
+    features.push(flag(swirl == Some(SwirlState::SwirlIdle)));
+    features.push(flag(swirl == Some(SwirlState::SwirlFly)));
+    features.extend(position(view.missile));
+    features.extend(position(view.cannon.map(|(pos, _)| pos)));
+    features.push(flag(view.cannon.is_some_and(|(_, launched)| launched)));
+    features.extend(position(nearest_block));
+    features.push(flag(controlling_cannon));
+    features.push(lives as f32 / YAR_LIVES as f32);
+    debug_assert_eq!(features.len(), FEATURE_COUNT);
+    features
+}
@@ -0,0 +1,120 @@
+//! A tiny blocky font, drawn the way the 2600 drew everything: out of coloured blocks.
+use bevy::math::const_ivec2;
+use bevy::prelude::*;
+
+const GLYPH_WIDTH: i32 = 3;
+/// Size of a glyph, in font pixels.
+pub const GLYPH_SIZE: IVec2 = const_ivec2!([GLYPH_WIDTH, 5]);
+/// Distance from one glyph to the next, in font pixels.
+const GLYPH_ADVANCE: i32 = GLYPH_WIDTH + 1;
+/// A font pixel at scale 1, in Atari pixels. Two scanlines tall so that it looks square.
+const FONT_PIXEL: IVec2 = const_ivec2!([1, 2]);
+
+/// Rows of the glyph for `c` from the top, one bit per font pixel with the leftmost pixel in
+/// the highest bit. Characters without a glyph are drawn as blanks.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '?' => [0b110, 0b001, 0b010, 0b000, 0b010],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        _ => [0; 5],
+    }
+}
+
+/// Size of `text` drawn at the given scale, in Atari pixels.
+pub fn text_size(text: &str, scale: i32) -> IVec2 {
+    let glyphs = text.chars().count() as i32;
+    let width = (glyphs * GLYPH_ADVANCE - 1).max(0);
+    IVec2::new(width, GLYPH_SIZE.y) * FONT_PIXEL * scale
+}
+
+/// Spawn `text` centred on `center`, `scale` Atari pixels per font pixel across.
+///
+/// Returns the parent entity of the text's blocks; despawn it recursively to remove the text.
+pub fn spawn_text(
+    commands: &mut Commands,
+    text: &str,
+    center: IVec2,
+    z: f32,
+    scale: i32,
+    color: Color,
+) -> Entity {
+    let pixel = FONT_PIXEL * scale;
+    // Centre of the top left font pixel of the first glyph.
+    let half_size = text_size(text, scale) / 2;
+    let top_left = center + IVec2::new(pixel.x / 2 - half_size.x, half_size.y - pixel.y / 2);
+
+    commands
+        .spawn_bundle(TransformBundle::from_transform(Transform::from_xyz(
+            0.0, 0.0, z,
+        )))
+        .with_children(|parent| {
+            for (i, c) in text.chars().enumerate() {
+                let glyph_origin = top_left + IVec2::new(i as i32 * GLYPH_ADVANCE * pixel.x, 0);
+                for (row, bits) in glyph(c).iter().enumerate() {
+                    for column in 0..GLYPH_SIZE.x {
+                        if bits & (1 << (GLYPH_SIZE.x - 1 - column)) == 0 {
+                            continue;
+                        }
+                        let block = glyph_origin + IVec2::new(column, -(row as i32)) * pixel;
+                        parent.spawn_bundle(SpriteBundle {
+                            sprite: Sprite {
+                                color,
+                                custom_size: Some(pixel.as_vec2()),
+                                ..default()
+                            },
+                            transform: Transform::from_translation(block.as_vec2().extend(0.0)),
+                            ..default()
+                        });
+                    }
+                }
+            }
+        })
+        .id()
+}
@@ -0,0 +1,295 @@
+//! Frames drawn on the CPU, for running the game without a GPU. A frame covers the playfield one
+//! Atari pixel to a framebuffer pixel, so it is 160x192 like the 2600's picture.
+//!
+//! Sprites are drawn the way the Bevy renderer draws them: in order of depth, from the same atlas
+//! frames and images, tinted by their colour. Colours are blended as they are stored, in sRGB,
+//! rather than in linear light, so translucent sprites come out slightly darker than on screen.
+use crate::PLAYFIELD_SIZE;
+use anyhow::Context;
+use bevy::prelude::*;
+use bevy::sprite::Rect;
+use std::path::Path;
+
+/// An RGBA image, stored a row at a time from the top left.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl Framebuffer {
+    /// An opaque black frame.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0, 0, 0, 255]; (width * height) as usize],
+        }
+    }
+
+    /// A frame the size of the playfield.
+    pub fn playfield() -> Self {
+        Self::new(PLAYFIELD_SIZE.x as u32, PLAYFIELD_SIZE.y as u32)
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// The pixels one after another, four bytes each.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        self.pixels.iter().flatten().copied().collect()
+    }
+
+    /// Blend `color`, given as sRGB from 0 to 1, over the pixel at `(x, y)`.
+    fn blend(&mut self, x: u32, y: u32, [r, g, b, a]: [f32; 4]) {
+        let pixel = &mut self.pixels[(y * self.width + x) as usize];
+        for (channel, value) in pixel.iter_mut().zip([r, g, b]) {
+            let blended = *channel as f32 * (1.0 - a) + value.clamp(0.0, 1.0) * 255.0 * a;
+            *channel = blended.round() as u8;
+        }
+    }
+
+    /// Draw `quad` over whatever is already there.
+    fn draw(&mut self, quad: &Quad) {
+        let half_size = PLAYFIELD_SIZE.as_vec2() / 2.0;
+        // Pixels whose centres fall inside the quad, in framebuffer rows and columns.
+        let left = (quad.min.x + half_size.x - 0.5).ceil().max(0.0) as u32;
+        let right = (quad.max.x + half_size.x - 0.5)
+            .ceil()
+            .min(self.width as f32) as u32;
+        let top = (half_size.y - quad.max.y - 0.5).ceil().max(0.0) as u32;
+        let bottom = (half_size.y - quad.min.y - 0.5)
+            .ceil()
+            .min(self.height as f32) as u32;
+
+        let size = quad.max - quad.min;
+        for y in top..bottom {
+            for x in left..right {
+                let world = Vec2::new(x as f32 + 0.5 - half_size.x, half_size.y - y as f32 - 0.5);
+                let mut uv = (world - quad.min) / size;
+                // Textures run from the top down.
+                uv.y = 1.0 - uv.y;
+                if quad.flip_x {
+                    uv.x = 1.0 - uv.x;
+                }
+                if quad.flip_y {
+                    uv.y = 1.0 - uv.y;
+                }
+
+                let texel = match &quad.texture {
+                    Some(texture) => texture.sample(uv),
+                    None => [1.0; 4],
+                };
+                let color = [
+                    texel[0] * quad.color[0],
+                    texel[1] * quad.color[1],
+                    texel[2] * quad.color[2],
+                    texel[3] * quad.color[3],
+                ];
+                if color[3] > 0.0 {
+                    self.blend(x, y, color);
+                }
+            }
+        }
+    }
+
+    /// A frame `factor` times smaller on each side, each pixel the average of the pixels it
+    /// covers.
+    pub fn downsample(&self, factor: u32) -> Self {
+        let factor = factor.max(1);
+        let mut small = Self::new(self.width / factor, self.height / factor);
+        for y in 0..small.height {
+            for x in 0..small.width {
+                let mut sum = [0u32; 4];
+                for dy in 0..factor {
+                    for dx in 0..factor {
+                        let pixel = self.pixel(x * factor + dx, y * factor + dy);
+                        for (total, channel) in sum.iter_mut().zip(pixel) {
+                            *total += channel as u32;
+                        }
+                    }
+                }
+                let count = factor * factor;
+                small.pixels[(y * small.width + x) as usize] =
+                    sum.map(|total| (total / count) as u8);
+            }
+        }
+        small
+    }
+
+    pub fn load_png(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let image = image::open(path)
+            .with_context(|| format!("loading `{}`", path.display()))?
+            .to_rgba8();
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.pixels().map(|pixel| pixel.0).collect(),
+        })
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        image::save_buffer(
+            path,
+            &self.to_rgba(),
+            self.width,
+            self.height,
+            image::ColorType::Rgba8,
+        )
+        .with_context(|| format!("saving `{}`", path.display()))
+    }
+}
+
+/// The part of an image a sprite is drawn from.
+struct Texture<'a> {
+    image: &'a Image,
+    rect: Rect,
+}
+
+impl<'a> Texture<'a> {
+    /// The texel at `uv` across `rect`, as sRGB from 0 to 1, picking the nearest one the way
+    /// the game's pixel art is sampled on screen.
+    fn sample(&self, uv: Vec2) -> [f32; 4] {
+        let size = self.rect.max - self.rect.min;
+        let texel = (self.rect.min + uv * size).floor();
+        let width = self.image.texture_descriptor.size.width;
+        let height = self.image.texture_descriptor.size.height;
+        let x = (texel.x.max(0.0) as u32).min(width - 1);
+        let y = (texel.y.max(0.0) as u32).min(height - 1);
+        let i = ((y * width + x) * 4) as usize;
+        match self.image.data.get(i..i + 4) {
+            Some(bytes) => [0, 1, 2, 3].map(|c| bytes[c] as f32 / 255.0),
+            None => [0.0; 4],
+        }
+    }
+}
+
+/// A sprite, ready to draw.
+struct Quad<'a> {
+    z: f32,
+    /// Corners in Atari pixels, with y up.
+    min: Vec2,
+    max: Vec2,
+    color: [f32; 4],
+    flip_x: bool,
+    flip_y: bool,
+    /// Untextured quads are solid colour.
+    texture: Option<Texture<'a>>,
+}
+
+impl<'a> Quad<'a> {
+    fn new(
+        transform: &GlobalTransform,
+        size: Vec2,
+        anchor: Vec2,
+        color: Color,
+        flip: (bool, bool),
+        texture: Option<Texture<'a>>,
+    ) -> Self {
+        let size = size * transform.scale.truncate();
+        let center = transform.translation.truncate() - anchor * size;
+        Self {
+            z: transform.translation.z,
+            min: center - size / 2.0,
+            max: center + size / 2.0,
+            color: color.as_rgba_f32(),
+            flip_x: flip.0,
+            flip_y: flip.1,
+            texture,
+        }
+    }
+}
+
+fn is_visible(visibility: Option<&Visibility>) -> bool {
+    !matches!(visibility, Some(Visibility { is_visible: false }))
+}
+
+/// Draw every visible sprite over the playfield.
+///
+/// Sprites whose image hasn't loaded are left out, as they are on screen, except that sprites
+/// given a size of their own are drawn as solid colour; those are the ones drawn from Bevy's
+/// blank white image, which only exists once there is a GPU to put it on.
+pub fn render(world: &mut World) -> Framebuffer {
+    let mut sprites = world.query::<(
+        &GlobalTransform,
+        &Sprite,
+        &Handle<Image>,
+        Option<&Visibility>,
+    )>();
+    let mut atlas_sprites = world.query::<(
+        &GlobalTransform,
+        &TextureAtlasSprite,
+        &Handle<TextureAtlas>,
+        Option<&Visibility>,
+    )>();
+    let world = &*world;
+    let images = world.get_resource::<Assets<Image>>();
+    let atlases = world.get_resource::<Assets<TextureAtlas>>();
+
+    let mut quads = Vec::new();
+    for (transform, sprite, handle, visibility) in sprites.iter(world) {
+        if !is_visible(visibility) {
+            continue;
+        }
+        let image = images.and_then(|images| images.get(handle));
+        let (size, texture) = match (image, sprite.custom_size) {
+            (Some(image), size) => {
+                let rect = Rect {
+                    min: Vec2::ZERO,
+                    max: image.size(),
+                };
+                (
+                    size.unwrap_or_else(|| image.size()),
+                    Some(Texture { image, rect }),
+                )
+            }
+            (None, Some(size)) => (size, None),
+            (None, None) => continue,
+        };
+        quads.push(Quad::new(
+            transform,
+            size,
+            sprite.anchor.as_vec(),
+            sprite.color,
+            (sprite.flip_x, sprite.flip_y),
+            texture,
+        ));
+    }
+    for (transform, sprite, handle, visibility) in atlas_sprites.iter(world) {
+        if !is_visible(visibility) {
+            continue;
+        }
+        let atlas = match atlases.and_then(|atlases| atlases.get(handle)) {
+            Some(atlas) => atlas,
+            None => continue,
+        };
+        let (image, rect) = match (
+            images.and_then(|images| images.get(&atlas.texture)),
+            atlas.textures.get(sprite.index),
+        ) {
+            (Some(image), Some(rect)) => (image, *rect),
+            _ => continue,
+        };
+        quads.push(Quad::new(
+            transform,
+            sprite.custom_size.unwrap_or(rect.max - rect.min),
+            sprite.anchor.as_vec(),
+            sprite.color,
+            (sprite.flip_x, sprite.flip_y),
+            Some(Texture { image, rect }),
+        ));
+    }
+
+    // Back to front. The sort is stable, so sprites at the same depth keep the order the queries
+    // found them in.
+    quads.sort_by(|a, b| a.z.total_cmp(&b.z));
+    let mut frame = Framebuffer::playfield();
+    for quad in &quads {
+        frame.draw(quad);
+    }
+    frame
+}
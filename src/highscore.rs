@@ -0,0 +1,660 @@
+//! High score tables, one per game variation, kept between runs along with a replay of each
+//! game that made the table, and the game over screen that comes before them.
+use crate::control::{ControlTarget, GameRecording, Replay};
+use crate::font;
+use crate::level::{CurrentRound, GameOverEvent, NewGameEvent, VARIATIONS};
+use crate::score::Score;
+use crate::stats::SessionStats;
+use crate::storage;
+use crate::title::{self, TEXT_COLOR, TITLE_COLOR};
+use crate::{AppState, GameSystem};
+use bevy::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Storage key of the high score tables.
+pub const HIGH_SCORES: &str = "high_scores.ron";
+/// Entries kept in each table.
+const MAX_HIGH_SCORES: usize = 10;
+const INITIALS_LEN: usize = 3;
+/// In front of the playfield, behind the letterbox.
+const SCREEN_Z: f32 = 10.0;
+/// Scanlines from one row of the table to the next.
+const ROW_HEIGHT: i32 = 12;
+
+pub struct HighScorePlugin;
+
+impl Plugin for HighScorePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HighScoreView>()
+            .add_startup_system(load)
+            // After the game's statistics are in its replay and its last points are scored.
+            .add_system(
+                game_over
+                    .label(GameSystem::Bookkeeping)
+                    .after(crate::stats::gather)
+                    .after(crate::score::tally)
+                    .after(crate::qotile_death::finish),
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::GameOver)
+                    .label(GameSystem::Menu)
+                    .before(GameSystem::Start)
+                    .with_system(title::clear_playfield)
+                    .with_system(show_stats.after(title::clear_playfield)),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::GameOver)
+                    .label(GameSystem::Menu)
+                    .before(GameSystem::Start)
+                    .with_system(leave_stats),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::GameOver).with_system(hide))
+            .add_system_set(
+                SystemSet::on_enter(AppState::EnterInitials)
+                    .label(GameSystem::Menu)
+                    .before(GameSystem::Start)
+                    .with_system(title::clear_playfield)
+                    .with_system(show_entry.after(title::clear_playfield)),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::EnterInitials)
+                    .label(GameSystem::Menu)
+                    .before(GameSystem::Start)
+                    .with_system(enter_initials.after(show_entry)),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::EnterInitials).with_system(hide))
+            .add_system_set(
+                SystemSet::on_enter(AppState::HighScores)
+                    .label(GameSystem::Menu)
+                    .before(GameSystem::Start)
+                    .with_system(title::clear_playfield),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::HighScores)
+                    .label(GameSystem::Menu)
+                    .before(GameSystem::Start)
+                    .with_system(browse)
+                    .with_system(draw_table.after(browse)),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::HighScores).with_system(hide));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HighScore {
+    pub initials: String,
+    pub points: u32,
+    /// When the score was made, as `YYYY-MM-DD`.
+    pub date: String,
+    /// Storage key of the game's replay, if it was saved.
+    #[serde(default)]
+    pub replay: Option<String>,
+}
+
+/// The best scores in each game variation, highest first.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct HighScores {
+    pub tables: BTreeMap<u8, Vec<HighScore>>,
+}
+
+impl HighScores {
+    pub fn table(&self, variation: u8) -> &[HighScore] {
+        self.tables.get(&variation).map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether `points` would make it onto the variation's table.
+    pub fn qualifies(&self, variation: u8, points: u32) -> bool {
+        let table = self.table(variation);
+        points > 0 && (table.len() < MAX_HIGH_SCORES || points > table[MAX_HIGH_SCORES - 1].points)
+    }
+
+    /// Add a score to the variation's table.
+    ///
+    /// Later scores rank below earlier scores with the same points.
+    pub fn insert(&mut self, variation: u8, score: HighScore) -> Placing {
+        let table = self.tables.entry(variation).or_default();
+        let rank = table.partition_point(|entry| entry.points >= score.points);
+        table.insert(rank, score);
+        let dropped = (table.len() > MAX_HIGH_SCORES).then(|| table.remove(MAX_HIGH_SCORES));
+        Placing {
+            rank: (rank < MAX_HIGH_SCORES).then_some(rank),
+            dropped,
+        }
+    }
+}
+
+/// Where a score went in its table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placing {
+    /// The score's position, if it made the cut.
+    pub rank: Option<usize>,
+    /// The score that fell off the end of the table, which is the new score if it didn't make
+    /// the cut.
+    pub dropped: Option<HighScore>,
+}
+
+/// Which table the high score screen shows, and which entry is picked.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HighScoreView {
+    pub variation: u8,
+    pub selected: usize,
+}
+
+impl Default for HighScoreView {
+    fn default() -> Self {
+        Self {
+            variation: 1,
+            selected: 0,
+        }
+    }
+}
+
+/// A game that earned a high score, waiting for the player's initials.
+pub struct InitialsEntry {
+    pub variation: u8,
+    pub points: u32,
+    /// The game's replay, unless it couldn't be recorded.
+    pub replay: Option<Replay>,
+    /// Letters entered so far, counting from `A`.
+    letters: [u8; INITIALS_LEN],
+    /// The letter being changed.
+    cursor: usize,
+}
+
+impl InitialsEntry {
+    pub fn new(variation: u8, points: u32, replay: Option<Replay>) -> Self {
+        Self {
+            variation,
+            points,
+            replay,
+            letters: [0; INITIALS_LEN],
+            cursor: 0,
+        }
+    }
+
+    pub fn initials(&self) -> String {
+        self.letters
+            .iter()
+            .map(|letter| (b'A' + letter) as char)
+            .collect()
+    }
+}
+
+/// Part of the initials entry or high score screen.
+#[derive(Component)]
+pub struct HighScoreScreen;
+
+/// Shows the initials being entered.
+#[derive(Component)]
+pub struct InitialsLabel;
+
+pub fn load(mut commands: Commands) {
+    let high_scores = match storage::load(HIGH_SCORES) {
+        Some(text) => ron::de::from_str(&text).unwrap_or_else(|err| {
+            warn!("could not read high scores, starting afresh: {}", err);
+            HighScores::default()
+        }),
+        None => HighScores::default(),
+    };
+    commands.insert_resource(high_scores);
+}
+
+fn save_table(high_scores: &HighScores) {
+    let result = ron::ser::to_string_pretty(high_scores, PrettyConfig::new())
+        .map_err(anyhow::Error::from)
+        .and_then(|text| storage::save(HIGH_SCORES, &text));
+    if let Err(err) = result {
+        warn!("could not save high scores: {}", err);
+    }
+}
+
+/// `{stem}.replay`, or `{stem}-2.replay` and so on if a replay is already saved under it, so the
+/// same initials making the same score on the same day don't overwrite each other's replays.
+fn unused_replay_key(stem: &str) -> String {
+    (1..)
+        .map(|n| match n {
+            1 => format!("{}.replay", stem),
+            n => format!("{}-{}.replay", stem, n),
+        })
+        .find(|key| storage::load(key).is_none())
+        .unwrap()
+}
+
+/// Save a replay under a key of its own, returning the key if it was saved.
+fn save_replay(replay: &Replay, key: String) -> Option<String> {
+    let result = ron::ser::to_string_pretty(replay, PrettyConfig::new())
+        .map_err(anyhow::Error::from)
+        .and_then(|text| storage::save(&key, &text));
+    match result {
+        Ok(()) => Some(key),
+        Err(err) => {
+            warn!("could not save replay `{}`: {}", key, err);
+            None
+        }
+    }
+}
+
+/// Show how the game went. After that, a high score earns a place on the table; otherwise the
+/// table is shown as it is.
+#[allow(clippy::too_many_arguments)]
+pub fn game_over(
+    mut commands: Commands,
+    mut game_over_event: EventReader<GameOverEvent>,
+    mut state: ResMut<State<AppState>>,
+    mut view: ResMut<HighScoreView>,
+    control_target: Res<ControlTarget>,
+    score: Res<Score>,
+    round: Res<CurrentRound>,
+    high_scores: Res<HighScores>,
+    recording: Res<GameRecording>,
+) {
+    if game_over_event.iter().count() == 0 {
+        return;
+    }
+
+    // Replays are someone else's game.
+    if matches!(*control_target, ControlTarget::Demo) {
+        let _ = state.set(AppState::Title);
+        return;
+    }
+
+    if high_scores.qualifies(round.variation, score.points) {
+        commands.insert_resource(InitialsEntry::new(
+            round.variation,
+            score.points,
+            recording.replay.clone(),
+        ));
+    } else {
+        *view = HighScoreView {
+            variation: round.variation,
+            selected: 0,
+        };
+    }
+    let _ = state.set(AppState::GameOver);
+}
+
+fn stats_rows(stats: &SessionStats) -> Vec<(&'static str, String)> {
+    let seconds = |time: f32| format!("{:.0} SEC", time);
+    vec![
+        ("SHOTS HIT", format!("{} OF {}", stats.hits, stats.shots)),
+        ("CELLS EATEN", stats.cells_eaten.to_string()),
+        ("CELLS SHOT", stats.cells_shot.to_string()),
+        ("MISSILE DEATHS", stats.deaths.destroyer_missile.to_string()),
+        ("SWIRL DEATHS", stats.deaths.swirl.to_string()),
+        ("OWN CANNON DEATHS", stats.deaths.own_cannon.to_string()),
+        ("GHOST CANNON DEATHS", stats.deaths.ghost_cannon.to_string()),
+        (
+            "CANNON HITS",
+            format!("{} OF {}", stats.cannon_hits, stats.cannon_launches),
+        ),
+        ("NEUTRAL ZONE", seconds(stats.neutral_zone_time)),
+        ("TIME AS YAR", seconds(stats.yar_time)),
+        ("TIME AS CANNON", seconds(stats.cannon_time)),
+    ]
+}
+
+pub fn show_stats(mut commands: Commands, stats: Res<SessionStats>) {
+    let mut lines = vec![("GAME OVER".to_string(), IVec2::new(0, 80), 2, TITLE_COLOR)];
+    for (row, (label, value)) in stats_rows(&stats).into_iter().enumerate() {
+        lines.push((
+            format!("{:<19}{:>8}", label, value),
+            IVec2::new(0, 56 - row as i32 * ROW_HEIGHT),
+            1,
+            TEXT_COLOR,
+        ));
+    }
+    lines.push((
+        "SPACE CONTINUE".to_string(),
+        IVec2::new(0, -80),
+        1,
+        TEXT_COLOR,
+    ));
+
+    for (text, center, scale, color) in lines {
+        let e = font::spawn_text(&mut commands, &text, center, SCREEN_Z, scale, color);
+        commands.entity(e).insert(HighScoreScreen);
+    }
+}
+
+/// Go on to enter initials for a high score, or to the table.
+pub fn leave_stats(
+    mut keys: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<AppState>>,
+    entry: Option<Res<InitialsEntry>>,
+) {
+    if !keys.just_pressed(KeyCode::Space) {
+        return;
+    }
+    let next = if entry.is_some() {
+        AppState::EnterInitials
+    } else {
+        AppState::HighScores
+    };
+    let _ = state.set(next);
+    keys.clear();
+}
+
+fn spawn_initials_label(commands: &mut Commands, entry: &InitialsEntry) {
+    let cursor: String = (0..INITIALS_LEN)
+        .map(|i| if i == entry.cursor { '_' } else { ' ' })
+        .collect();
+    for (text, center) in [
+        (entry.initials(), IVec2::new(0, 0)),
+        (cursor, IVec2::new(0, -8)),
+    ] {
+        let e = font::spawn_text(commands, &text, center, SCREEN_Z, 2, TITLE_COLOR);
+        commands
+            .entity(e)
+            .insert(HighScoreScreen)
+            .insert(InitialsLabel);
+    }
+}
+
+pub fn show_entry(mut commands: Commands, entry: Res<InitialsEntry>) {
+    let lines = [
+        (
+            "NEW HIGH SCORE".to_string(),
+            IVec2::new(0, 64),
+            2,
+            TITLE_COLOR,
+        ),
+        (entry.points.to_string(), IVec2::new(0, 36), 1, TEXT_COLOR),
+        ("W S LETTER".to_string(), IVec2::new(0, -40), 1, TEXT_COLOR),
+        (
+            "A D SPACE MOVE".to_string(),
+            IVec2::new(0, -56),
+            1,
+            TEXT_COLOR,
+        ),
+    ];
+    for (text, center, scale, color) in lines {
+        let e = font::spawn_text(&mut commands, &text, center, SCREEN_Z, scale, color);
+        commands.entity(e).insert(HighScoreScreen);
+    }
+
+    spawn_initials_label(&mut commands, &entry);
+}
+
+/// Pick each letter with up and down, then move on to the next. Moving past the last letter
+/// saves the score.
+pub fn enter_initials(
+    mut commands: Commands,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut entry: ResMut<InitialsEntry>,
+    mut high_scores: ResMut<HighScores>,
+    mut view: ResMut<HighScoreView>,
+    mut state: ResMut<State<AppState>>,
+    label_query: Query<Entity, With<InitialsLabel>>,
+) {
+    let cursor = entry.cursor;
+    let letter_count = b'Z' - b'A' + 1;
+    let mut changed = true;
+    if keys.just_pressed(KeyCode::W) {
+        entry.letters[cursor] = (entry.letters[cursor] + 1) % letter_count;
+    } else if keys.just_pressed(KeyCode::S) {
+        entry.letters[cursor] = (entry.letters[cursor] + letter_count - 1) % letter_count;
+    } else if keys.just_pressed(KeyCode::A) {
+        entry.cursor = cursor.saturating_sub(1);
+    } else if keys.any_just_pressed([KeyCode::D, KeyCode::Space]) {
+        entry.cursor += 1;
+    } else {
+        changed = false;
+    }
+    if !changed {
+        return;
+    }
+
+    if entry.cursor < INITIALS_LEN {
+        for e in label_query.iter() {
+            commands.entity(e).despawn_recursive();
+        }
+        spawn_initials_label(&mut commands, &entry);
+        return;
+    }
+
+    let date = storage::today();
+    let initials = entry.initials();
+    let replay = entry.replay.as_ref().and_then(|replay| {
+        save_replay(
+            replay,
+            unused_replay_key(&format!(
+                "replays/{}-game{}-{}-{}",
+                date, entry.variation, initials, entry.points
+            )),
+        )
+    });
+    let placing = high_scores.insert(
+        entry.variation,
+        HighScore {
+            initials,
+            points: entry.points,
+            date,
+            replay,
+        },
+    );
+    save_table(&high_scores);
+    // Only the table's games are kept.
+    if let Some(key) = placing.dropped.and_then(|dropped| dropped.replay) {
+        if let Err(err) = storage::remove(&key) {
+            warn!("could not remove replay `{}`: {}", key, err);
+        }
+    }
+
+    *view = HighScoreView {
+        variation: entry.variation,
+        selected: placing.rank.unwrap_or(0),
+    };
+    commands.remove_resource::<InitialsEntry>();
+    let _ = state.set(AppState::HighScores);
+    keys.clear();
+}
+
+/// Move through the table with up and down, switch tables with Game Select and watch the
+/// picked game with the fire button.
+pub fn browse(
+    mut keys: ResMut<Input<KeyCode>>,
+    mut view: ResMut<HighScoreView>,
+    mut state: ResMut<State<AppState>>,
+    mut new_game_event: EventWriter<NewGameEvent>,
+    mut replays: ResMut<Assets<Replay>>,
+    high_scores: Res<HighScores>,
+) {
+    let table = high_scores.table(view.variation);
+
+    if keys.just_pressed(KeyCode::W) && view.selected > 0 {
+        view.selected -= 1;
+    }
+    if keys.just_pressed(KeyCode::S) && view.selected + 1 < table.len() {
+        view.selected += 1;
+    }
+    if keys.just_pressed(KeyCode::F1) {
+        *view = HighScoreView {
+            variation: view.variation % VARIATIONS.len() as u8 + 1,
+            selected: 0,
+        };
+    }
+
+    if keys.any_just_pressed([KeyCode::F2, KeyCode::Escape]) {
+        let _ = state.set(AppState::Title);
+        keys.clear();
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::Space) {
+        let key = match table
+            .get(view.selected)
+            .and_then(|entry| entry.replay.as_ref())
+        {
+            Some(key) => key,
+            None => return,
+        };
+        let replay: Replay = match storage::load(key).map(|text| ron::de::from_str(&text)) {
+            Some(Ok(replay)) => replay,
+            Some(Err(err)) => {
+                warn!("could not read replay `{}`: {}", key, err);
+                return;
+            }
+            None => {
+                warn!("replay `{}` is missing", key);
+                return;
+            }
+        };
+
+        new_game_event.send(NewGameEvent {
+            variation: view.variation,
+            replay: Some(replays.add(replay)),
+        });
+        let _ = state.set(AppState::Playing);
+        keys.clear();
+    }
+}
+
+fn row_text(rank: usize, entry: &HighScore, selected: bool) -> String {
+    format!(
+        "{}{:>2} {} {:>6} {}{}",
+        if selected { '>' } else { ' ' },
+        rank + 1,
+        entry.initials,
+        entry.points,
+        entry.date,
+        if entry.replay.is_some() { " R" } else { "  " },
+    )
+}
+
+/// Redraw the table whenever the view changes.
+pub fn draw_table(
+    mut commands: Commands,
+    view: Res<HighScoreView>,
+    high_scores: Res<HighScores>,
+    query: Query<Entity, With<HighScoreScreen>>,
+) {
+    if !view.is_changed() {
+        return;
+    }
+    for e in query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+
+    let mut lines = vec![(
+        format!("GAME {} HIGH SCORES", view.variation),
+        IVec2::new(0, 80),
+        TITLE_COLOR,
+    )];
+    let table = high_scores.table(view.variation);
+    if table.is_empty() {
+        lines.push(("NO SCORES YET".to_string(), IVec2::ZERO, TEXT_COLOR));
+    }
+    for (rank, entry) in table.iter().enumerate() {
+        let selected = rank == view.selected;
+        lines.push((
+            row_text(rank, entry, selected),
+            IVec2::new(0, 60 - rank as i32 * ROW_HEIGHT),
+            if selected { TITLE_COLOR } else { TEXT_COLOR },
+        ));
+    }
+    lines.push((
+        "F1 GAME  SPACE REPLAY  F2 BACK".to_string(),
+        IVec2::new(0, -80),
+        TEXT_COLOR,
+    ));
+
+    for (text, center, color) in lines {
+        let e = font::spawn_text(&mut commands, &text, center, SCREEN_Z, 1, color);
+        commands.entity(e).insert(HighScoreScreen);
+    }
+}
+
+pub fn hide(mut commands: Commands, query: Query<Entity, With<HighScoreScreen>>) {
+    for e in query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(initials: &str, points: u32) -> HighScore {
+        HighScore {
+            initials: initials.to_string(),
+            points,
+            date: "2026-01-01".to_string(),
+            replay: None,
+        }
+    }
+
+    /// A full table of variation 1, scoring 1000 down to 100.
+    fn full_table() -> HighScores {
+        let mut high_scores = HighScores::default();
+        for rank in 0..MAX_HIGH_SCORES as u32 {
+            high_scores.insert(1, score("AAA", (10 - rank) * 100));
+        }
+        high_scores
+    }
+
+    #[test]
+    fn nothing_scores_nothing() {
+        assert!(!HighScores::default().qualifies(1, 0));
+        assert!(HighScores::default().qualifies(1, 1));
+    }
+
+    #[test]
+    fn full_table_takes_only_better_scores() {
+        let mut high_scores = full_table();
+        assert_eq!(high_scores.table(1).len(), MAX_HIGH_SCORES);
+        assert!(!high_scores.qualifies(1, 100));
+        assert!(high_scores.qualifies(1, 101));
+        assert!(high_scores.qualifies(2, 100));
+
+        assert_eq!(high_scores.insert(1, score("LOW", 50)).rank, None);
+        assert_eq!(high_scores.insert(1, score("NEW", 550)).rank, Some(5));
+        let table = high_scores.table(1);
+        assert_eq!(table.len(), MAX_HIGH_SCORES);
+        assert_eq!(table[5].initials, "NEW");
+        assert_eq!(table[MAX_HIGH_SCORES - 1].points, 200);
+    }
+
+    #[test]
+    fn ties_rank_below_earlier_scores() {
+        let mut high_scores = HighScores::default();
+        assert_eq!(high_scores.insert(1, score("ONE", 500)).rank, Some(0));
+        assert_eq!(high_scores.insert(1, score("TWO", 500)).rank, Some(1));
+        assert_eq!(high_scores.insert(1, score("TOP", 900)).rank, Some(0));
+        let initials: Vec<_> = high_scores
+            .table(1)
+            .iter()
+            .map(|entry| entry.initials.as_str())
+            .collect();
+        assert_eq!(initials, ["TOP", "ONE", "TWO"]);
+
+        // A tie with the last entry of a full table doesn't make it.
+        let mut high_scores = full_table();
+        assert_eq!(high_scores.insert(1, score("TIE", 100)).rank, None);
+        assert_eq!(high_scores.table(1)[MAX_HIGH_SCORES - 1].initials, "AAA");
+    }
+
+    #[test]
+    fn scores_that_fall_off_the_table_are_handed_back() {
+        let mut high_scores = HighScores::default();
+        let placing = high_scores.insert(1, score("ONE", 500));
+        assert_eq!(placing.dropped, None);
+
+        // The last score of a full table makes room for a better one, with the key of its
+        // replay so that the replay can go too.
+        let mut high_scores = full_table();
+        let last = HighScore {
+            replay: Some("replays/last.replay".to_string()),
+            ..score("LST", 100)
+        };
+        high_scores.tables.get_mut(&1).unwrap()[MAX_HIGH_SCORES - 1] = last.clone();
+        let placing = high_scores.insert(1, score("NEW", 550));
+        assert_eq!(placing.dropped, Some(last));
+
+        // A score that doesn't make the cut is the one dropped.
+        let low = score("LOW", 50);
+        let placing = high_scores.insert(1, low.clone());
+        assert_eq!(placing.dropped, Some(low));
+    }
+}
@@ -0,0 +1,355 @@
+//! Level definitions. A level is a sequence of rounds, each of which sets up the playfield and
+//! tunes the enemies. Destroying Qotile advances to the next round once the explosion is over.
+use crate::console::{ConsoleAppExt, ConsoleVar};
+use crate::control::Replay;
+use crate::shield::DEFAULT_SHIELD_SHAPE;
+use crate::{AppState, GameSystem};
+use anyhow::{anyhow, bail};
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use serde::Deserialize;
+use std::ops::RangeInclusive;
+
+/// Level played when the game starts.
+pub const DEFAULT_LEVEL: &str = "levels/default.level";
+/// Level played in each game variation. Variation `n` on the title screen plays `VARIATIONS[n - 1]`.
+pub const VARIATIONS: &[&str] = &[DEFAULT_LEVEL, "levels/ultimate.level"];
+/// Delays the console can set, in seconds.
+const CONSOLE_DELAYS: RangeInclusive<f32> = 0.0..=60.0;
+/// Speeds the console can set, in Atari pixels a frame. Faster things would skip past what
+/// they should hit.
+const CONSOLE_SPEEDS: RangeInclusive<f32> = 0.01..=8.0;
+
+/// Start a new game of the given variation from the first round.
+pub struct NewGameEvent {
+    pub variation: u8,
+    /// Play the game back from this replay instead of taking input.
+    pub replay: Option<Handle<Replay>>,
+}
+/// The player's last Yar is gone.
+pub struct GameOverEvent;
+/// A new round has begun; `CurrentRound` holds its parameters.
+pub struct RoundStartEvent;
+
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<NewGameEvent>()
+            .add_event::<GameOverEvent>()
+            .add_event::<RoundStartEvent>()
+            .add_asset::<Level>()
+            .init_asset_loader::<LevelLoader>()
+            .init_resource::<CurrentRound>()
+            .add_startup_system(setup)
+            .add_system_set(
+                SystemSet::new()
+                    .label(GameSystem::Start)
+                    .with_system(new_game.after(advance_round))
+                    .with_system(level_loaded.after(new_game)),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::QotileDeathSequence)
+                    .label(GameSystem::Start)
+                    .with_system(advance_round),
+            )
+            // Tuning for the round being played; the next round starts from its level again.
+            .add_console_var(ConsoleVar::new(
+                "round.swirl_speed",
+                "Speed of the Swirl in flight.",
+                CONSOLE_SPEEDS,
+                |world| &mut params(world).swirl_speed,
+            ))
+            .add_console_var(ConsoleVar::new(
+                "round.swirl_delay_base",
+                "Least time before Qotile swirls.",
+                CONSOLE_DELAYS,
+                |world| &mut params(world).swirl_delay_base,
+            ))
+            .add_console_var(ConsoleVar::new(
+                "round.swirl_delay_variance",
+                "Most extra time before Qotile swirls.",
+                CONSOLE_DELAYS,
+                |world| &mut params(world).swirl_delay_variance,
+            ))
+            .add_console_var(ConsoleVar::new(
+                "round.launch_delay_base",
+                "Least time the Swirl spins before launching.",
+                CONSOLE_DELAYS,
+                |world| &mut params(world).launch_delay_base,
+            ))
+            .add_console_var(ConsoleVar::new(
+                "round.launch_delay_variance",
+                "Most extra time the Swirl spins.",
+                CONSOLE_DELAYS,
+                |world| &mut params(world).launch_delay_variance,
+            ))
+            .add_console_var(ConsoleVar::new(
+                "round.destroyer_missile_speed",
+                "Speed of the Destroyer Missile.",
+                CONSOLE_SPEEDS,
+                |world| &mut params(world).destroyer_missile_speed,
+            ));
+    }
+}
+
+fn params(world: &mut World) -> &mut RoundParams {
+    &mut world.resource_mut::<CurrentRound>().into_inner().params
+}
+
+/// Everything that changes from one round to the next.
+///
+/// Positions are in Atari pixels, speeds in scanlines per frame and delays in seconds.
+/// Fields left out of a level file keep these defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RoundParams {
+    /// Gap between Qotile and the right edge of the screen.
+    pub qotile_inset: i32,
+    pub swirl_speed: f32,
+    /// Time Qotile waits before turning into the Swirl.
+    pub swirl_delay_base: f32,
+    pub swirl_delay_variance: f32,
+    /// Time the Swirl spins in place before launching at Yar.
+    pub launch_delay_base: f32,
+    pub launch_delay_variance: f32,
+    pub destroyer_missile_speed: f32,
+    /// Horizontal offset of the Neutral Zone from the centre of the screen.
+    pub neutral_zone_x: i32,
+    /// Gap between the shield and the right edge of the screen.
+    pub shield_inset: i32,
+    /// Vertical offset of the shield's centre from the centre of the screen.
+    pub shield_y: i32,
+    /// Path of the `.shield` asset to build the shield from.
+    pub shield_shape: String,
+}
+
+impl Default for RoundParams {
+    fn default() -> Self {
+        Self {
+            qotile_inset: 2,
+            swirl_speed: 1.5,
+            swirl_delay_base: 3.0,
+            swirl_delay_variance: 5.0,
+            launch_delay_base: 1.0,
+            launch_delay_variance: 3.0,
+            destroyer_missile_speed: 0.125,
+            neutral_zone_x: -10,
+            shield_inset: 0,
+            shield_y: 0,
+            shield_shape: DEFAULT_SHIELD_SHAPE.to_string(),
+        }
+    }
+}
+
+impl RoundParams {
+    /// Delays can't be negative and speeds must be positive.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let delays = [
+            ("swirl_delay_base", self.swirl_delay_base),
+            ("swirl_delay_variance", self.swirl_delay_variance),
+            ("launch_delay_base", self.launch_delay_base),
+            ("launch_delay_variance", self.launch_delay_variance),
+        ];
+        for (name, delay) in delays {
+            if !(delay.is_finite() && delay >= 0.0) {
+                bail!("`{}` must be 0 or more, not {}", name, delay);
+            }
+        }
+        let speeds = [
+            ("swirl_speed", self.swirl_speed),
+            ("destroyer_missile_speed", self.destroyer_missile_speed),
+        ];
+        for (name, speed) in speeds {
+            if !(speed.is_finite() && speed > 0.0) {
+                bail!("`{}` must be more than 0, not {}", name, speed);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A level, loaded from a RON `.level` asset.
+#[derive(Debug, Clone, Deserialize, TypeUuid)]
+#[uuid = "5a3c8e21-7d4f-4b0e-8f1a-2c6e9b4d7a13"]
+pub struct Level {
+    /// Rounds in the order they are played. The last round repeats forever.
+    pub rounds: Vec<RoundParams>,
+}
+
+impl Level {
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        let level: Level = ron::de::from_bytes(bytes)?;
+        if level.rounds.is_empty() {
+            bail!("level has no rounds");
+        }
+        for (number, round) in level.rounds.iter().enumerate() {
+            round
+                .validate()
+                .map_err(|error| anyhow!("round {}: {}", number + 1, error))?;
+        }
+        Ok(level)
+    }
+
+    /// Parameters for the given round, counting from 0.
+    pub fn round(&self, number: usize) -> RoundParams {
+        self.rounds
+            .get(number)
+            .or_else(|| self.rounds.last())
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Default)]
+pub struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let level = Level::parse(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(level));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level"]
+    }
+}
+
+/// The level being played.
+pub struct ActiveLevel(pub Handle<Level>);
+
+/// The round being played and its parameters.
+#[derive(Debug, Default)]
+pub struct CurrentRound {
+    /// Game variation being played.
+    pub variation: u8,
+    /// Rounds completed so far.
+    pub number: usize,
+    pub params: RoundParams,
+}
+
+pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(ActiveLevel(asset_server.load(DEFAULT_LEVEL)));
+}
+
+/// Switch to the variation's level and start its first round, or wait for it to load.
+pub fn new_game(
+    mut new_game_event: EventReader<NewGameEvent>,
+    mut round_start_event: EventWriter<RoundStartEvent>,
+    mut current_round: ResMut<CurrentRound>,
+    mut active_level: ResMut<ActiveLevel>,
+    asset_server: Res<AssetServer>,
+    levels: Res<Assets<Level>>,
+) {
+    for NewGameEvent { variation, .. } in new_game_event.iter() {
+        let path = VARIATIONS
+            .get((*variation as usize).saturating_sub(1))
+            .copied()
+            .unwrap_or(DEFAULT_LEVEL);
+        active_level.0 = asset_server.load(path);
+        current_round.variation = *variation;
+        current_round.number = 0;
+
+        if let Some(level) = levels.get(&active_level.0) {
+            current_round.params = level.round(0);
+            round_start_event.send(RoundStartEvent);
+        }
+    }
+}
+
+/// Start the current round once the level loads, and again whenever its file is edited.
+pub fn level_loaded(
+    mut level_event: EventReader<AssetEvent<Level>>,
+    mut round_start_event: EventWriter<RoundStartEvent>,
+    mut current_round: ResMut<CurrentRound>,
+    state: Res<State<AppState>>,
+    active_level: Option<Res<ActiveLevel>>,
+    levels: Res<Assets<Level>>,
+) {
+    let active_level = match active_level {
+        Some(active_level) => active_level,
+        None => return,
+    };
+    // The title screen has no rounds to start; the level is only being loaded ahead of time.
+    if *state.current() == AppState::Title {
+        return;
+    }
+
+    for event in level_event.iter() {
+        let handle = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+        if *handle != active_level.0 {
+            continue;
+        }
+
+        if let Some(level) = levels.get(handle) {
+            current_round.params = level.round(current_round.number);
+            round_start_event.send(RoundStartEvent);
+        }
+    }
+}
+
+/// Destroying Qotile moves on to the next, harder, round.
+pub fn advance_round(
+    mut round_start_event: EventWriter<RoundStartEvent>,
+    mut current_round: ResMut<CurrentRound>,
+    active_level: Option<Res<ActiveLevel>>,
+    levels: Res<Assets<Level>>,
+) {
+    current_round.number += 1;
+    if let Some(level) = active_level.and_then(|active_level| levels.get(&active_level.0)) {
+        current_round.params = level.round(current_round.number);
+    }
+    round_start_event.send(RoundStartEvent);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_variations() {
+        let default = Level::parse(include_bytes!("../assets/levels/default.level")).unwrap();
+        let ultimate = Level::parse(include_bytes!("../assets/levels/ultimate.level")).unwrap();
+        assert!(default.rounds.len() > 1);
+        assert_eq!(default.round(0).neutral_zone_x, -10);
+        assert!(!ultimate.rounds.is_empty());
+    }
+
+    #[test]
+    fn fills_in_defaults_and_repeats_the_last_round() {
+        let level = Level::parse(b"(rounds: [(swirl_speed: 2.0), (shield_y: 4)])").unwrap();
+        assert_eq!(level.round(0).swirl_speed, 2.0);
+        assert_eq!(level.round(0).shield_shape, DEFAULT_SHIELD_SHAPE);
+        assert_eq!(
+            level.round(1).swirl_speed,
+            RoundParams::default().swirl_speed
+        );
+        assert_eq!(level.round(5).shield_y, 4);
+    }
+
+    #[test]
+    fn rejects_bad_rounds() {
+        let error = |text: &str| Level::parse(text.as_bytes()).unwrap_err().to_string();
+        assert_eq!(error("(rounds: [])"), "level has no rounds");
+        assert_eq!(
+            error("(rounds: [(), (launch_delay_base: -1.0)])"),
+            "round 2: `launch_delay_base` must be 0 or more, not -1"
+        );
+        assert_eq!(
+            error("(rounds: [(destroyer_missile_speed: 0.0)])"),
+            "round 1: `destroyer_missile_speed` must be more than 0, not 0"
+        );
+        assert!(Level::parse(b"(rounds: [(swirl_speed: \"fast\")])").is_err());
+    }
+}
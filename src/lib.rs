@@ -1,72 +1,187 @@
 #![allow(clippy::type_complexity)]
 
-use bevy::math::const_vec2;
+use crate::atlas::{AtlasManifest, ATLAS_LABEL, DEFAULT_ATLAS};
+use bevy::asset::AssetServerSettings;
+use bevy::ecs::schedule::ShouldRun;
+use bevy::math::{const_ivec2, const_vec3};
 use bevy::prelude::*;
 
-mod bullet;
-mod control;
-mod destroyer_missile;
-mod neutral_zone;
-mod qotile;
-mod shield;
-mod util;
-mod yar;
-mod zorlon_cannon;
+pub mod animation;
+pub mod atlas;
+pub mod bot;
+pub mod bullet;
+pub mod checksum;
+pub mod clock;
+pub mod console;
+pub mod control;
+pub mod destroyer_missile;
+pub mod difficulty;
+pub mod display;
+pub mod env;
+pub mod event_log;
+pub mod font;
+pub mod framebuffer;
+pub mod highscore;
+pub mod level;
+pub mod motion;
+pub mod neutral_zone;
+pub mod pause;
+pub mod qotile;
+pub mod qotile_death;
+pub mod rng;
+pub mod score;
+pub mod shield;
+pub mod sound;
+pub mod stats;
+pub mod storage;
+pub mod telemetry;
+pub mod tia;
+pub mod title;
+pub mod util;
+pub mod yar;
+pub mod zorlon_cannon;
 
-// Not really, but close enough for this project.
-// We have no concept of playfield memory and sprite memory so...
-const ATARI_RES_X: f32 = 160.0 * 2.0;
-const ATARI_RES_Y: f32 = 192.0;
-const SCREEN_SCALE: f32 = 4.0;
-const SCREEN_SIZE: Vec2 = const_vec2!([ATARI_RES_X * SCREEN_SCALE, ATARI_RES_Y * SCREEN_SCALE]);
-const SPRITE_SIZE: Vec2 = const_vec2!([16.0, 16.0]);
+// Gameplay happens in the Atari's own pixels, with the origin at the centre of the playfield.
+// `display` takes care of getting them onto the window.
+pub const ATARI_RES_X: i32 = 160;
+pub const ATARI_RES_Y: i32 = 192;
+pub const PLAYFIELD_SIZE: IVec2 = const_ivec2!([ATARI_RES_X, ATARI_RES_Y]);
+/// Atari pixels are twice as wide as they are tall.
+pub const PIXEL_ASPECT: f32 = 2.0;
+/// A square pixel measured in Atari pixels.
+///
+/// Sprites are drawn with square pixels, and speeds along a direction vector are given in
+/// scanlines, so both are scaled by this.
+const SQUARE_PIXEL: Vec3 = const_vec3!([1.0 / PIXEL_ASPECT, 1.0, 1.0]);
+/// Window size, in window pixels per scanline, before the player resizes it.
+const INITIAL_WINDOW_SCALE: f32 = 4.0;
+
+/// What the game as a whole is doing. Gameplay systems only run while `Playing`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AppState {
+    /// Waiting for the player to start a game.
+    Title,
+    Playing,
+    /// The game is paused, with `Playing` underneath on the state stack.
+    Paused,
+    /// The developer console is open over whatever the game was doing.
+    Console,
+    /// Qotile has been destroyed and the screen is exploding.
+    QotileDeathSequence,
+    /// The game is over and its statistics are showing.
+    GameOver,
+    /// The game is over and the player is entering initials for a high score.
+    EnterInitials,
+    /// Showing the high score table.
+    HighScores,
+}
+
+/// The phases of a frame of the game, in the order they run.
+///
+/// Every system that plays a part in the game runs in one of them, and systems in the same phase
+/// that depend on each other say so. Otherwise they would run in whatever order the executor
+/// picked, and a game recorded in the window could play back differently.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum GameSystem {
+    /// The title screen, menus and console take the keyboard, and may start or leave a game.
+    Menu,
+    /// Games and rounds start, and assets they need finish loading.
+    Start,
+    /// Commands for Yar and the cannon come in from the keyboard, bots and replays.
+    Input,
+    /// Everything moves.
+    Movement,
+    /// Collisions are found.
+    Collision,
+    /// Deaths and damage take effect.
+    Reaction,
+    /// Objects leave the playfield and new ones take their place.
+    Spawn,
+    /// The score, statistics and the game's recording catch up with the frame.
+    Bookkeeping,
+}
+
+/// Run criteria for systems that run in every state but must stand still while the game is
+/// paused or the console is open, such as the clocks.
+pub fn not_paused(state: Res<State<AppState>>) -> ShouldRun {
+    if matches!(state.current(), AppState::Paused | AppState::Console) {
+        ShouldRun::No
+    } else {
+        ShouldRun::Yes
+    }
+}
 
 #[derive(Component, Default)]
 pub struct GameState {
     pub sprite_atlas: Handle<TextureAtlas>,
+    /// Names of the frames in `sprite_atlas`.
+    pub sprite_manifest: Handle<AtlasManifest>,
 }
 
-pub fn setup_camera(mut commands: Commands) {
-    commands.spawn_bundle(OrthographicCameraBundle::new_2d());
+pub fn setup_sprites(mut game_state: ResMut<GameState>, asset_server: Res<AssetServer>) {
+    game_state.sprite_manifest = asset_server.load(DEFAULT_ATLAS);
+    game_state.sprite_atlas = asset_server.load(&format!("{}#{}", DEFAULT_ATLAS, ATLAS_LABEL));
 }
 
-pub fn setup_sprites(
-    mut game_state: ResMut<GameState>,
-    asset_server: Res<AssetServer>,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-) {
-    let texture_handle = asset_server.load("yar_sprites.png");
-    let texture_atlas = TextureAtlas::from_grid_with_padding(
-        texture_handle,
-        SPRITE_SIZE,
-        8,
-        4,
-        Vec2::new(2.0, 2.0),
-    );
+/// The game itself, without a window, sound or menus. `run` adds those around it; the headless
+/// environment in `env` runs it alone.
+pub struct GamePlugin;
 
-    game_state.sprite_atlas = texture_atlases.add(texture_atlas);
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameState>()
+            .add_state(AppState::Title)
+            .add_plugin(rng::RngPlugin)
+            .add_plugin(clock::ClockPlugin)
+            .add_plugin(motion::MotionPlugin)
+            .add_plugin(atlas::AtlasPlugin)
+            .add_plugin(animation::SpriteAnimationPlugin)
+            .add_plugin(difficulty::DifficultyPlugin)
+            .add_plugin(level::LevelPlugin)
+            .add_plugin(yar::YarPlugin)
+            .add_plugin(control::ReplayControlPlugin)
+            .add_plugin(checksum::ChecksumPlugin)
+            .add_plugin(bot::BotPlugin)
+            .add_plugin(bullet::BulletPlugin)
+            .add_plugin(zorlon_cannon::ZorlonCannonPlugin)
+            .add_plugin(destroyer_missile::DestroyerMissilePlugin)
+            .add_plugin(qotile::QotilePlugin)
+            .add_plugin(qotile_death::QotileDeathPlugin)
+            .add_plugin(score::ScorePlugin)
+            .add_plugin(stats::StatsPlugin)
+            .add_plugin(neutral_zone::NeutralZonePlugin)
+            .add_plugin(shield::ShieldPlugin)
+            .add_startup_system(setup_sprites);
+    }
 }
 
 pub fn run() {
-    App::new()
-        .insert_resource(WindowDescriptor {
-            title: "ya_rs' revenge".to_string(),
-            width: SCREEN_SIZE.x,
-            height: SCREEN_SIZE.y,
-            ..default()
-        })
-        .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
-        .init_resource::<GameState>()
-        .add_plugins(DefaultPlugins)
-        .add_plugin(yar::YarPlugin)
-        .add_plugin(control::ReplayControlPlugin)
-        .add_plugin(bullet::BulletPlugin)
-        .add_plugin(zorlon_cannon::ZorlonCannonPlugin)
-        .add_plugin(destroyer_missile::DestroyerMissilePlugin)
-        .add_plugin(qotile::QotilePlugin)
-        // .add_plugin(neutral_zone::NeutralZonePlugin)
-        .add_plugin(shield::ShieldPlugin)
-        .add_startup_system(setup_camera)
-        .add_startup_system(setup_sprites)
-        .run();
+    let mut app = App::new();
+    app.insert_resource(WindowDescriptor {
+        title: "ya_rs' revenge".to_string(),
+        width: ATARI_RES_X as f32 * PIXEL_ASPECT * INITIAL_WINDOW_SCALE,
+        height: ATARI_RES_Y as f32 * INITIAL_WINDOW_SCALE,
+        ..default()
+    })
+    // Hot-reload assets such as shield shapes while the game runs.
+    .insert_resource(AssetServerSettings {
+        watch_for_changes: true,
+        ..default()
+    })
+    .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
+    // Checksums in the replays saved from the window, so they can join the replay corpus.
+    .insert_resource(checksum::ChecksumPeriod(Some(env::DEFAULT_CHECKSUM_PERIOD)))
+    .add_plugins(DefaultPlugins)
+    .add_plugin(GamePlugin)
+    .add_plugin(display::DisplayPlugin)
+    .add_plugin(sound::SoundPlugin)
+    .add_plugin(title::TitlePlugin)
+    .add_plugin(highscore::HighScorePlugin)
+    .add_plugin(pause::PausePlugin)
+    .add_plugin(console::ConsolePlugin);
+    if let Some(path) = std::env::var_os(telemetry::TELEMETRY_VAR) {
+        app.add_plugin(telemetry::TelemetryPlugin { path: path.into() });
+    }
+    env::order_systems(&mut app);
+    app.run();
 }
@@ -0,0 +1,113 @@
+//! Motion in whole Atari pixels.
+//!
+//! Positions live in `GamePos`, and `Transform` is only updated from it for rendering. Speeds
+//! that aren't a whole number of pixels per frame are made of whole steps spread over several
+//! frames, the way the 2600 skipped frames to move things slowly.
+use crate::clock::GameClock;
+use crate::{AppState, GameSystem, SQUARE_PIXEL};
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+
+/// Frames `StepRate::from_speed` spreads its steps over.
+const SUBSTEPS: u32 = 64;
+
+pub struct MotionPlugin;
+
+impl Plugin for MotionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(AppState::Playing)
+                .label(GameSystem::Movement)
+                .after(GameSystem::Input)
+                // Steering comes first, so velocities set this frame move things this frame.
+                .with_system(apply_velocity.after(crate::zorlon_cannon::pulse)),
+        )
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            sync_transforms.before(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+/// Position in Atari pixels, from the centre of the playfield.
+#[derive(Component, Debug, Default, Copy, Clone, PartialEq, Eq, Deref, DerefMut)]
+pub struct GamePos(pub IVec2);
+
+/// Speed along one axis: `pixels` every `frames` frames, with the steps spread evenly.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct StepRate {
+    pub pixels: i32,
+    pub frames: u32,
+}
+
+impl StepRate {
+    pub const ZERO: Self = Self::new(0, 1);
+
+    pub const fn new(pixels: i32, frames: u32) -> Self {
+        Self { pixels, frames }
+    }
+
+    /// The rate closest to `speed` pixels per frame.
+    pub fn from_speed(speed: f32) -> Self {
+        Self::new((speed * SUBSTEPS as f32).round() as i32, SUBSTEPS)
+    }
+
+    /// This rate sped up by `factor`; exactly this rate when `factor` is 1.
+    pub fn scaled(self, factor: f32) -> Self {
+        if factor == 1.0 {
+            self
+        } else {
+            Self::from_speed(self.pixels as f32 / self.frames.max(1) as f32 * factor)
+        }
+    }
+
+    pub fn reversed(self) -> Self {
+        Self::new(-self.pixels, self.frames)
+    }
+
+    /// Pixels to move on the given frame.
+    pub fn step(&self, tick: u64) -> i32 {
+        let frames = self.frames.max(1) as i64;
+        let phase = (tick % frames as u64) as i64;
+        let pixels = self.pixels as i64;
+        ((phase + 1) * pixels / frames - phase * pixels / frames) as i32
+    }
+}
+
+/// Moves the entity's `GamePos` every frame.
+#[derive(Component, Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Velocity {
+    pub x: StepRate,
+    pub y: StepRate,
+}
+
+impl Velocity {
+    /// Move along `direction` at `speed` scanlines per frame, going half as many pixels
+    /// horizontally since Atari pixels are twice as wide.
+    pub fn from_direction(direction: Vec2, speed: f32) -> Self {
+        let velocity = direction * speed * SQUARE_PIXEL.truncate();
+        Self {
+            x: StepRate::from_speed(velocity.x),
+            y: StepRate::from_speed(velocity.y),
+        }
+    }
+
+    /// Pixels to move on the given frame.
+    pub fn step(&self, tick: u64) -> IVec2 {
+        IVec2::new(self.x.step(tick), self.y.step(tick))
+    }
+}
+
+pub fn apply_velocity(clock: Res<GameClock>, mut query: Query<(&mut GamePos, &Velocity)>) {
+    for (mut pos, velocity) in query.iter_mut() {
+        pos.0 += velocity.step(clock.tick);
+    }
+}
+
+/// Draw everything where the game thinks it is.
+pub fn sync_transforms(mut query: Query<(&GamePos, &mut Transform), Changed<GamePos>>) {
+    for (pos, mut transform) in query.iter_mut() {
+        transform.translation.x = pos.x as f32;
+        transform.translation.y = pos.y as f32;
+    }
+}
@@ -1,16 +1,16 @@
-use crate::SCREEN_SCALE;
-use crate::SCREEN_SIZE;
-use bevy::math::const_vec2;
+use crate::clock::GameClock;
+use crate::level::{CurrentRound, RoundStartEvent};
+use crate::motion::GamePos;
+use crate::rng::GameRng;
+use crate::{GameSystem, PLAYFIELD_SIZE};
+use bevy::math::{const_ivec2, const_vec2};
 use bevy::prelude::*;
 use rand::prelude::*;
 
 const NEUTRAL_ZONE_COLS: i32 = 7;
 const NEUTRAL_ZONE_ROWS: i32 = 192;
-const NEUTRAL_ZONE_SPRITE_SIZE: Vec2 = const_vec2!([8.0 * SCREEN_SCALE, 1.0 * SCREEN_SCALE]);
-pub const NEUTRAL_ZONE_BOUNDS: Vec2 = const_vec2!([
-    SCREEN_SCALE * 8.0 * NEUTRAL_ZONE_COLS as f32,
-    SCREEN_SCALE * NEUTRAL_ZONE_ROWS as f32
-]);
+const NEUTRAL_ZONE_SPRITE_SIZE: Vec2 = const_vec2!([4.0, 1.0]);
+pub const NEUTRAL_ZONE_BOUNDS: IVec2 = const_ivec2!([4 * NEUTRAL_ZONE_COLS, NEUTRAL_ZONE_ROWS]);
 const NEUTRAL_ZONE_SHIFT_TIME: f32 = 0.05;
 
 #[derive(Component)]
@@ -64,9 +64,15 @@ pub struct NeutralZonePlugin;
 impl Plugin for NeutralZonePlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<ColorPalette>()
-            .add_startup_system(spawn)
+            .add_system(spawn.label(GameSystem::Spawn).after(GameSystem::Reaction))
             //.add_system(despawn)
-            .add_system(color_shift);
+            .add_system(
+                color_shift
+                    .with_run_criteria(crate::not_paused)
+                    .label(GameSystem::Movement)
+                    .after(GameSystem::Input)
+                    .after(crate::qotile_death::flash),
+            );
     }
 }
 
@@ -79,14 +85,26 @@ pub struct NeutralZoneChunk;
 #[derive(Component, Deref, DerefMut)]
 pub struct ChunkShiftTimer(pub Timer);
 
-pub fn spawn(mut commands: Commands) {
-    let mut zone_origin = Transform::identity();
-    zone_origin.translation.x -= 20.0 * SCREEN_SCALE;
+/// Each round places the Neutral Zone where the round asks for it.
+pub fn spawn(
+    mut commands: Commands,
+    mut round_start_event: EventReader<RoundStartEvent>,
+    round: Res<CurrentRound>,
+    nz_query: Query<Entity, Or<(With<NeutralZone>, With<NeutralZoneChunk>)>>,
+) {
+    if round_start_event.iter().count() == 0 {
+        return;
+    }
+
+    for e in nz_query.iter() {
+        commands.entity(e).despawn();
+    }
+
+    let zone_origin = IVec2::new(round.params.neutral_zone_x, 0);
 
     let mut zone_transform = Transform::identity();
-    zone_transform.translation += zone_origin.translation;
-    zone_transform.translation.x -= NEUTRAL_ZONE_BOUNDS.x / 2.0;
-    zone_transform.translation.y -= SCREEN_SIZE.y / 2.0;
+    zone_transform.translation.x = (zone_origin.x - NEUTRAL_ZONE_BOUNDS.x / 2) as f32;
+    zone_transform.translation.y = (zone_origin.y - PLAYFIELD_SIZE.y / 2) as f32;
 
     let mut chunk_offset = Transform::identity();
 
@@ -119,7 +137,7 @@ pub fn spawn(mut commands: Commands) {
             NEUTRAL_ZONE_SHIFT_TIME,
             true,
         )))
-        .insert(zone_origin)
+        .insert(GamePos(zone_origin))
         .insert(NeutralZone);
 }
 /*
@@ -144,19 +162,18 @@ pub fn despawn(
 */
 pub fn color_shift(
     color_palette: Res<ColorPalette>,
-    time: Res<Time>,
+    clock: Res<GameClock>,
+    mut rng: ResMut<GameRng>,
     mut chunk_query: Query<&mut Sprite, With<NeutralZoneChunk>>,
     mut nz_query: Query<&mut ChunkShiftTimer, With<NeutralZone>>,
 ) {
-    if nz_query.is_empty() {
-        return;
-    }
+    for mut timer in nz_query.iter_mut() {
+        timer.tick(clock.delta);
+        if !timer.just_finished() {
+            continue;
+        }
 
-    let mut timer = nz_query.single_mut();
-    timer.tick(time.delta());
-    if timer.just_finished() {
         for mut sprite in chunk_query.iter_mut() {
-            let mut rng = thread_rng();
             let black_chance: f32 = rng.gen();
 
             if black_chance < 0.35 {
@@ -164,7 +181,7 @@ pub fn color_shift(
             } else {
                 sprite.color = *color_palette
                     .neutral_zone_colors
-                    .choose(&mut thread_rng())
+                    .choose(&mut rng.0)
                     .unwrap();
             }
         }
@@ -0,0 +1,244 @@
+//! Pausing the game, and the menu shown while it is paused.
+//!
+//! `AppState::Paused` is pushed on top of `Playing`, so everything that only runs while
+//! `Playing` stops where it is. Systems that run in every state stop too; see
+//! [`crate::not_paused`].
+use crate::bullet::Bullet;
+use crate::control::{ControlTarget, GameRecording};
+use crate::destroyer_missile::DestroyerMissile;
+use crate::font;
+use crate::level::{CurrentRound, RoundStartEvent, VARIATIONS};
+use crate::title::{StartGame, TEXT_COLOR, TITLE_COLOR};
+use crate::yar::{self, Yar};
+use crate::zorlon_cannon::{SpawnZorlonCannonEvent, ZorlonCannon};
+use crate::{AppState, GameSystem, PLAYFIELD_SIZE};
+use bevy::prelude::*;
+
+/// In front of the playfield and the title text, behind the letterbox.
+const MENU_Z: f32 = 20.0;
+const ROW_HEIGHT: i32 = 16;
+/// Dims the frozen playfield behind the menu.
+const OVERLAY_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.7);
+
+pub struct PausePlugin;
+
+impl Plugin for PausePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PauseMenu>()
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .label(GameSystem::Menu)
+                    .before(GameSystem::Start)
+                    .with_system(pause),
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::Paused)
+                    .label(GameSystem::Menu)
+                    .before(GameSystem::Start)
+                    .with_system(open_menu),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Paused)
+                    .label(GameSystem::Menu)
+                    .before(GameSystem::Start)
+                    .with_system(menu.after(open_menu))
+                    .with_system(draw_menu.after(menu)),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::Paused).with_system(hide));
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MenuItem {
+    Resume,
+    RestartRound,
+    /// Start a new game of the picked variation.
+    Variation,
+    Quit,
+}
+
+const MENU_ITEMS: [MenuItem; 4] = [
+    MenuItem::Resume,
+    MenuItem::RestartRound,
+    MenuItem::Variation,
+    MenuItem::Quit,
+];
+
+/// State of the pause menu.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PauseMenu {
+    /// Index into `MENU_ITEMS`.
+    pub selected: usize,
+    /// Variation a new game would be started with.
+    pub variation: u8,
+}
+
+/// Part of the pause menu.
+#[derive(Component)]
+pub struct PauseScreen;
+
+/// Escape or P pauses the game. Replays can't be paused; any key ends them.
+pub fn pause(
+    mut keys: ResMut<Input<KeyCode>>,
+    control_target: Res<ControlTarget>,
+    mut state: ResMut<State<AppState>>,
+) {
+    if matches!(*control_target, ControlTarget::Demo) {
+        return;
+    }
+    if keys.any_just_pressed([KeyCode::Escape, KeyCode::P]) {
+        let _ = state.push(AppState::Paused);
+        keys.clear();
+    }
+}
+
+pub fn open_menu(mut menu: ResMut<PauseMenu>, round: Res<CurrentRound>) {
+    *menu = PauseMenu {
+        selected: 0,
+        variation: round.variation,
+    };
+}
+
+fn item_text(item: MenuItem, menu: &PauseMenu) -> String {
+    match item {
+        MenuItem::Resume => "RESUME".to_string(),
+        MenuItem::RestartRound => "RESTART ROUND".to_string(),
+        MenuItem::Variation => format!("NEW GAME {}", menu.variation),
+        MenuItem::Quit => "QUIT".to_string(),
+    }
+}
+
+/// Up and down pick an item, left and right pick a variation and the fire button chooses.
+/// Escape or P resumes.
+#[allow(clippy::too_many_arguments)]
+pub fn menu(
+    mut commands: Commands,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut menu: ResMut<PauseMenu>,
+    mut state: ResMut<State<AppState>>,
+    mut round_start_event: EventWriter<RoundStartEvent>,
+    mut spawn_cannon_event: EventWriter<SpawnZorlonCannonEvent>,
+    mut recording: ResMut<GameRecording>,
+    game_state: Res<crate::GameState>,
+    query: Query<
+        (Entity, Option<&ZorlonCannon>),
+        Or<(
+            With<Yar>,
+            With<Bullet>,
+            With<ZorlonCannon>,
+            With<DestroyerMissile>,
+        )>,
+    >,
+) {
+    let variations = VARIATIONS.len() as u8;
+    if keys.just_pressed(KeyCode::W) && menu.selected > 0 {
+        menu.selected -= 1;
+    }
+    if keys.just_pressed(KeyCode::S) && menu.selected + 1 < MENU_ITEMS.len() {
+        menu.selected += 1;
+    }
+    if MENU_ITEMS[menu.selected] == MenuItem::Variation {
+        if keys.just_pressed(KeyCode::A) {
+            menu.variation = (menu.variation + variations - 2) % variations + 1;
+        }
+        if keys.just_pressed(KeyCode::D) {
+            menu.variation = menu.variation % variations + 1;
+        }
+    }
+
+    let item = if keys.any_just_pressed([KeyCode::Escape, KeyCode::P]) {
+        MenuItem::Resume
+    } else if keys.any_just_pressed([KeyCode::Space, KeyCode::Return]) {
+        MENU_ITEMS[menu.selected]
+    } else {
+        return;
+    };
+
+    match item {
+        MenuItem::Resume => {
+            let _ = state.pop();
+        }
+        MenuItem::RestartRound => {
+            // Yar starts over with the round, along with the cannon if it was in play; lives
+            // and score stay as they are.
+            let mut had_cannon = false;
+            for (e, cannon) in query.iter() {
+                had_cannon |= cannon.is_some();
+                commands.entity(e).despawn();
+            }
+            let owner = yar::spawn(&mut commands, &game_state);
+            if had_cannon {
+                spawn_cannon_event.send(SpawnZorlonCannonEvent { owner });
+            }
+            round_start_event.send(RoundStartEvent);
+            // A replay has no way to restart a round.
+            recording.discard();
+            let _ = state.pop();
+        }
+        MenuItem::Variation => {
+            commands.insert_resource(StartGame(menu.variation));
+            let _ = state.replace(AppState::Title);
+        }
+        MenuItem::Quit => {
+            let _ = state.replace(AppState::Title);
+        }
+    }
+    // The key has been used; don't let the next screen see it too.
+    keys.clear();
+}
+
+/// Redraw the menu whenever it changes.
+pub fn draw_menu(
+    mut commands: Commands,
+    menu: Res<PauseMenu>,
+    query: Query<Entity, With<PauseScreen>>,
+) {
+    if !menu.is_changed() {
+        return;
+    }
+    for e in query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+
+    let overlay = commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: OVERLAY_COLOR,
+                custom_size: Some(PLAYFIELD_SIZE.as_vec2()),
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 0.0, MENU_Z - 1.0),
+            ..default()
+        })
+        .id();
+    commands.entity(overlay).insert(PauseScreen);
+
+    let title = font::spawn_text(
+        &mut commands,
+        "PAUSED",
+        IVec2::new(0, 48),
+        MENU_Z,
+        2,
+        TITLE_COLOR,
+    );
+    commands.entity(title).insert(PauseScreen);
+
+    for (i, item) in MENU_ITEMS.iter().enumerate() {
+        let selected = i == menu.selected;
+        let text = format!(
+            "{}{}",
+            if selected { "> " } else { "  " },
+            item_text(*item, &menu)
+        );
+        let center = IVec2::new(0, 8 - i as i32 * ROW_HEIGHT);
+        let color = if selected { TITLE_COLOR } else { TEXT_COLOR };
+        let e = font::spawn_text(&mut commands, &text, center, MENU_Z, 1, color);
+        commands.entity(e).insert(PauseScreen);
+    }
+}
+
+pub fn hide(mut commands: Commands, query: Query<Entity, With<PauseScreen>>) {
+    for e in query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
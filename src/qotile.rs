@@ -1,27 +1,43 @@
+use crate::animation::SpriteAnimation;
+use crate::atlas::SpriteFrames;
+use crate::clock::GameClock;
+use crate::console::{self, ConsoleAppExt, ConsoleCommand};
+use crate::difficulty::{Difficulty, DifficultySwitches};
+use crate::level::{CurrentRound, RoundParams, RoundStartEvent};
+use crate::motion::{GamePos, Velocity};
+use crate::rng::GameRng;
 use crate::util;
+use crate::util::DespawnReason;
 use crate::yar::Yar;
-use bevy::math::const_vec2;
+use anyhow::bail;
+use bevy::math::const_ivec2;
 use bevy::prelude::*;
+use bevy::utils::HashMap;
 use rand::prelude::*;
 use std::time::Duration;
 
-use crate::SCREEN_SCALE;
-use crate::SCREEN_SIZE;
+use crate::AppState;
+use crate::GameSystem;
+use crate::PLAYFIELD_SIZE;
+use crate::SQUARE_PIXEL;
 
-const QOTILE_SPRITE_SIZE: Vec2 = const_vec2!([16.0, 18.0]);
-const QOTILE_INSET: f32 = 16.0;
-pub const QOTILE_BOUNDS: Vec2 = const_vec2!([16.0 * SCREEN_SCALE, 18.0 * SCREEN_SCALE]);
-const SWIRL_SPEED: f32 = 6.0;
+pub const QOTILE_BOUNDS: IVec2 = const_ivec2!([8, 18]);
+pub const QOTILE_TEXTURE: &str = "qotile.png";
 
-const SWIRL_DELAY_BASE: f32 = 3.0;
-const SWIRL_DELAY_VARIANCE: f32 = 5.0;
+const SWIRL_CLIP: &str = "swirl";
 
-const LAUNCH_DELAY_BASE: f32 = 1.0;
-const LAUNCH_DELAY_VARIANCE: f32 = 3.0;
-
-pub struct QotileDiedEvent;
+/// The given Qotile was destroyed.
+pub struct QotileDiedEvent {
+    pub qotile: Entity,
+    /// Where Qotile was when it was destroyed.
+    pub pos: IVec2,
+}
 pub struct SpawnQotileEvent;
-pub struct DespawnQotileEvent;
+/// Despawn the given Qotile entity.
+pub struct DespawnQotileEvent {
+    pub entity: Entity,
+    pub reason: DespawnReason,
+}
 
 pub struct QotilePlugin;
 
@@ -30,51 +46,105 @@ impl Plugin for QotilePlugin {
         app.add_event::<QotileDiedEvent>()
             .add_event::<SpawnQotileEvent>()
             .add_event::<DespawnQotileEvent>()
-            .add_startup_system(setup)
-            .add_system(spawn)
-            .add_system(despawn)
-            .add_system(animate)
-            .add_system(timer)
-            .add_system(fly)
-            .add_system(leave_world)
-            .add_system(died);
+            .add_system(
+                died.label(GameSystem::Reaction)
+                    .after(GameSystem::Collision),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .label(GameSystem::Spawn)
+                    .after(GameSystem::Reaction)
+                    .with_system(round_started.before(despawn))
+                    .with_system(despawn.before(spawn))
+                    .with_system(spawn),
+            )
+            .add_console_command(ConsoleCommand {
+                name: "spawn swirl",
+                usage: "",
+                help: "Turn Qotile into the Swirl now.",
+                run: spawn_swirl,
+            })
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .label(GameSystem::Movement)
+                    .after(GameSystem::Input)
+                    .with_system(timer.after(crate::destroyer_missile::track)),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .label(GameSystem::Collision)
+                    .after(GameSystem::Movement)
+                    .with_system(leave_world.after(crate::yar::collide_qotile)),
+            );
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SwirlState {
     NotSwirl,
     SwirlIdle,
     SwirlFly,
 }
 
-pub enum QotileAnim {
-    Idle,
-    Swirl,
-}
-
 #[derive(Component)]
 pub struct Qotile {
     pub swirl_state: SwirlState,
-    anim: QotileAnim,
-    anim_frame: usize,
-    flight_vector: Vec3,
 }
 
-#[derive(Component, Deref, DerefMut)]
-struct AnimationTimer(pub Timer);
-
 #[derive(Component, Deref, DerefMut)]
 pub struct SwirlTimer(pub Timer);
 
-fn swirl_delay() -> f32 {
-    SWIRL_DELAY_BASE + SWIRL_DELAY_VARIANCE * thread_rng().gen::<f32>()
+fn swirl_delay(params: &RoundParams, rng: &mut GameRng) -> f32 {
+    params.swirl_delay_base + params.swirl_delay_variance * rng.gen::<f32>()
 }
 
-fn launch_delay() -> f32 {
-    LAUNCH_DELAY_BASE + LAUNCH_DELAY_VARIANCE * thread_rng().gen::<f32>()
+fn launch_delay(params: &RoundParams, difficulty: Difficulty, rng: &mut GameRng) -> f32 {
+    let delay = params.launch_delay_base + params.launch_delay_variance * rng.gen::<f32>();
+    // On A the Swirl gives Yar half as long to get out of the way.
+    match difficulty {
+        Difficulty::A => delay / 2.0,
+        Difficulty::B => delay,
+    }
 }
 
-fn setup(mut spawn_event: EventWriter<SpawnQotileEvent>) {
+fn spawn_swirl(world: &mut World, args: &[&str]) -> anyhow::Result<String> {
+    if !args.is_empty() {
+        bail!("usage: spawn swirl");
+    }
+    let mut swirled = false;
+    let mut query = world.query::<(&Qotile, &mut SwirlTimer)>();
+    for (qotile, mut timer) in query.iter_mut(world) {
+        if qotile.swirl_state == SwirlState::NotSwirl {
+            // Runs out on the next frame.
+            timer.set_duration(Duration::ZERO);
+            timer.reset();
+            swirled = true;
+        }
+    }
+    if !swirled {
+        bail!("there is no Qotile to swirl");
+    }
+    console::discard_replay(world);
+    Ok("Qotile swirls".to_string())
+}
+
+/// Each round starts with a fresh Qotile.
+fn round_started(
+    mut round_start_event: EventReader<RoundStartEvent>,
+    mut spawn_event: EventWriter<SpawnQotileEvent>,
+    mut despawn_event: EventWriter<DespawnQotileEvent>,
+    query: Query<Entity, With<Qotile>>,
+) {
+    if round_start_event.iter().count() == 0 {
+        return;
+    }
+
+    for e in query.iter() {
+        despawn_event.send(DespawnQotileEvent {
+            entity: e,
+            reason: DespawnReason::RoundEnd,
+        });
+    }
     spawn_event.send(SpawnQotileEvent);
 }
 
@@ -82,29 +152,32 @@ fn spawn(
     mut commands: Commands,
     mut spawn_event: EventReader<SpawnQotileEvent>,
     asset_server: Res<AssetServer>,
+    round: Res<CurrentRound>,
+    mut rng: ResMut<GameRng>,
 ) {
-    if spawn_event.iter().next().is_none() {
-        return;
+    for _ in spawn_event.iter() {
+        let pos = IVec2::new(
+            (PLAYFIELD_SIZE.x / 2) - (QOTILE_BOUNDS.x / 2) - round.params.qotile_inset,
+            0,
+        );
+        let mut transform = Transform::from_scale(SQUARE_PIXEL);
+        transform.translation = pos.as_vec2().extend(0.0);
+
+        commands
+            .spawn_bundle(SpriteBundle {
+                texture: asset_server.load(QOTILE_TEXTURE),
+                transform,
+                ..default()
+            })
+            .insert(SwirlTimer(Timer::from_seconds(
+                swirl_delay(&round.params, &mut rng),
+                false,
+            )))
+            .insert(GamePos(pos))
+            .insert(Qotile {
+                swirl_state: SwirlState::NotSwirl,
+            });
     }
-
-    let mut transform = Transform::from_scale(Vec3::splat(crate::SCREEN_SCALE));
-    transform.translation.x +=
-        (SCREEN_SIZE.x / 2.0) - (QOTILE_SPRITE_SIZE.x * crate::SCREEN_SCALE / 2.0) - QOTILE_INSET;
-
-    commands
-        .spawn_bundle(SpriteBundle {
-            texture: asset_server.load("qotile.png"),
-            transform,
-            ..default()
-        })
-        .insert(SwirlTimer(Timer::from_seconds(swirl_delay(), false)))
-        .insert(AnimationTimer(Timer::from_seconds(0.05, true)))
-        .insert(Qotile {
-            swirl_state: SwirlState::NotSwirl,
-            anim: QotileAnim::Idle,
-            anim_frame: 0,
-            flight_vector: Vec3::default(),
-        });
 }
 
 pub fn despawn(
@@ -114,65 +187,56 @@ pub fn despawn(
     mut spawn_event: EventWriter<SpawnQotileEvent>,
     query: Query<Entity, With<Qotile>>,
 ) {
-    if despawn_event.iter().next().is_none() || query.is_empty() {
-        return;
-    }
-    /*
-        if (despawn_event.iter().next().is_none() && death_event.iter().next().is_none()) || query.is_empty() {
-            return;
-        }
-    */
-    let e = query.single();
-    commands.entity(e).despawn();
-
-    spawn_event.send(SpawnQotileEvent);
-}
-
-fn animate(
-    time: Res<Time>,
-    mut query: Query<(&mut Qotile, &mut AnimationTimer, &mut TextureAtlasSprite)>,
-) {
-    if query.is_empty() {
-        return;
-    }
-
-    let (mut qotile, mut timer, mut sprite) = query.single_mut();
-
-    timer.tick(time.delta());
-    if timer.just_finished() {
-        match qotile.anim {
-            QotileAnim::Idle => {}
-            QotileAnim::Swirl => {
-                let anim_length = 3;
-                qotile.anim_frame = (qotile.anim_frame + 1) % anim_length;
-
-                let sprite_base = 25;
-                sprite.index = sprite_base + qotile.anim_frame;
+    let targets: HashMap<Entity, DespawnReason> = despawn_event
+        .iter()
+        .map(|event| (event.entity, event.reason))
+        .collect();
+
+    for e in query.iter() {
+        if let Some(reason) = targets.get(&e) {
+            debug!("despawning Qotile {:?}: {:?}", e, reason);
+            commands.entity(e).despawn();
+
+            // A Swirl that missed or hit Yar turns back into Qotile. A dead Qotile waits for the
+            // next round.
+            if matches!(reason, DespawnReason::Offscreen | DespawnReason::Collision) {
+                spawn_event.send(SpawnQotileEvent);
             }
         }
     }
 }
 
-fn timer(
+#[allow(clippy::too_many_arguments)]
+pub fn timer(
     mut commands: Commands,
     game_state: Res<crate::GameState>,
-    time: Res<Time>,
-    mut qotile_query: Query<(Entity, &Transform, &mut SwirlTimer, &mut Qotile), Without<Yar>>,
-    yar_query: Query<&Transform, With<Yar>>,
+    sprite_frames: Res<SpriteFrames>,
+    round: Res<CurrentRound>,
+    switches: Res<DifficultySwitches>,
+    clock: Res<GameClock>,
+    mut rng: ResMut<GameRng>,
+    mut qotile_query: Query<
+        (Entity, &GamePos, &Transform, &mut SwirlTimer, &mut Qotile),
+        Without<Yar>,
+    >,
+    yar_query: Query<&GamePos, With<Yar>>,
 ) {
-    if qotile_query.is_empty() || yar_query.is_empty() {
-        return;
-    }
-
-    let (e, transform, mut timer, mut qotile) = qotile_query.single_mut();
+    for (e, pos, transform, mut timer, mut qotile) in qotile_query.iter_mut() {
+        timer.tick(clock.delta);
+        // Not just the frame it finishes: an idle Swirl with no Yar to launch at tries again
+        // every frame after.
+        if !timer.finished() {
+            continue;
+        }
 
-    timer.tick(time.delta());
-    if timer.just_finished() {
         match qotile.swirl_state {
             SwirlState::NotSwirl => {
-                qotile.anim = QotileAnim::Swirl;
                 qotile.swirl_state = SwirlState::SwirlIdle;
-                timer.set_duration(Duration::from_secs_f32(launch_delay()));
+                timer.set_duration(Duration::from_secs_f32(launch_delay(
+                    &round.params,
+                    switches.active(),
+                    &mut rng,
+                )));
                 timer.reset();
 
                 commands
@@ -182,49 +246,53 @@ fn timer(
                         texture_atlas: game_state.sprite_atlas.clone(),
                         transform: *transform,
                         sprite: TextureAtlasSprite {
-                            index: 25,
+                            index: sprite_frames.index("swirl_0"),
                             ..default()
                         },
                         ..default()
-                    });
+                    })
+                    .insert(SpriteAnimation::new(SWIRL_CLIP));
             }
             SwirlState::SwirlIdle => {
+                // Launch at the closest Yar. Keep idling until there is one to launch at.
+                let target = yar_query
+                    .iter()
+                    .map(|yar_pos| yar_pos.0)
+                    .min_by_key(|yar_pos| {
+                        let offset = *yar_pos - pos.0;
+                        offset.dot(offset)
+                    });
+                let target = match target {
+                    Some(target) => target,
+                    None => continue,
+                };
+
                 qotile.swirl_state = SwirlState::SwirlFly;
-                let yar_transform = yar_query.single();
-                qotile.flight_vector =
-                    (yar_transform.translation - transform.translation).normalize();
-                qotile.flight_vector.z = 0.0;
-                commands.entity(e).remove::<SwirlTimer>();
+                let direction = (target - pos.0).as_vec2().normalize_or_zero();
+                commands
+                    .entity(e)
+                    .remove::<SwirlTimer>()
+                    .insert(Velocity::from_direction(
+                        direction,
+                        round.params.swirl_speed,
+                    ));
             }
             SwirlState::SwirlFly => {}
         }
     }
 }
 
-fn fly(mut query: Query<(&mut Transform, &Qotile)>) {
-    if query.is_empty() {
-        return;
-    }
-
-    let (mut transform, qotile) = query.single_mut();
-
-    if matches!(qotile.swirl_state, SwirlState::SwirlFly) {
-        transform.translation += qotile.flight_vector * SWIRL_SPEED;
-    }
-}
-
 pub fn leave_world(
     mut despawn_event: EventWriter<DespawnQotileEvent>,
-    mut query: Query<&Transform, With<Qotile>>,
+    query: Query<(Entity, &GamePos), With<Qotile>>,
 ) {
-    if query.is_empty() {
-        return;
-    }
-
-    let transform = query.single_mut();
-
-    if util::is_offscreen(transform.translation) {
-        despawn_event.send(DespawnQotileEvent);
+    for (e, pos) in query.iter() {
+        if util::is_offscreen(pos.0) {
+            despawn_event.send(DespawnQotileEvent {
+                entity: e,
+                reason: DespawnReason::Offscreen,
+            });
+        }
     }
 }
 
@@ -232,13 +300,10 @@ pub fn died(
     mut death_event: EventReader<QotileDiedEvent>,
     mut despawn_event: EventWriter<DespawnQotileEvent>,
 ) {
-    if death_event.iter().next().is_none() {
-        return;
+    for QotileDiedEvent { qotile, .. } in death_event.iter() {
+        despawn_event.send(DespawnQotileEvent {
+            entity: *qotile,
+            reason: DespawnReason::Death,
+        });
     }
-
-    // todo: victory stuffs
-
-    println!("QOTILE DEAD, YOU WIN");
-
-    despawn_event.send(DespawnQotileEvent);
 }
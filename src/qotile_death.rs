@@ -0,0 +1,162 @@
+//! The explosion that follows Qotile's destruction. Everything stops except Yar, who can dance
+//! around the screen and earns a bonus for getting clear of the blast.
+use crate::clock::GameClock;
+use crate::motion::GamePos;
+use crate::qotile::QotileDiedEvent;
+use crate::rng::GameRng;
+use crate::score::ScoreEvent;
+use crate::util;
+use crate::yar::{Yar, YAR_BOUNDS};
+use crate::AppState;
+use crate::GameSystem;
+use crate::ATARI_RES_X;
+use bevy::math::const_ivec2;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use rand::prelude::*;
+
+const EXPLOSION_DURATION: f32 = 3.0;
+/// The band spans the playfield, this many scanlines high.
+pub const EXPLOSION_BAND_BOUNDS: IVec2 = const_ivec2!([ATARI_RES_X, 48]);
+const BAND_ESCAPE_POINTS: u32 = 1000;
+
+pub struct QotileDeathPlugin;
+
+impl Plugin for QotileDeathPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(
+            start
+                .label(GameSystem::Reaction)
+                .after(GameSystem::Collision),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::QotileDeathSequence)
+                .label(GameSystem::Movement)
+                .after(GameSystem::Input)
+                .with_system(flash.after(crate::qotile::timer)),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::QotileDeathSequence)
+                .label(GameSystem::Collision)
+                .after(GameSystem::Movement)
+                .with_system(escape.after(crate::yar::collide_shield)),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::QotileDeathSequence)
+                .label(GameSystem::Bookkeeping)
+                .after(GameSystem::Spawn)
+                .with_system(finish.after(crate::stats::gather)),
+        )
+        .add_system_set(
+            SystemSet::on_exit(AppState::QotileDeathSequence)
+                .label(GameSystem::Start)
+                .with_system(cleanup),
+        );
+    }
+}
+
+/// The flashing band the explosion fills.
+#[derive(Component)]
+pub struct ExplosionBand;
+
+/// Progress of the explosion.
+pub struct QotileDeathSequence {
+    pub timer: Timer,
+    /// Yars that were caught in the band and haven't left it yet.
+    pub caught: HashSet<Entity>,
+}
+
+/// Blow up the screen where Qotile died. Qotile may be gone by now, so the event says where.
+pub fn start(
+    mut commands: Commands,
+    mut death_event: EventReader<QotileDiedEvent>,
+    mut state: ResMut<State<AppState>>,
+    yar_query: Query<(Entity, &GamePos), With<Yar>>,
+) {
+    // Several cannons may hit Qotile in the same frame; one explosion is enough.
+    let qotile_pos = match death_event.iter().next() {
+        Some(event) => event.pos,
+        None => return,
+    };
+    if *state.current() != AppState::Playing {
+        return;
+    }
+
+    let band_center = IVec2::new(0, qotile_pos.y);
+
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                custom_size: Some(EXPLOSION_BAND_BOUNDS.as_vec2()),
+                ..default()
+            },
+            transform: Transform::from_translation(band_center.as_vec2().extend(0.0)),
+            ..default()
+        })
+        .insert(GamePos(band_center))
+        .insert(ExplosionBand);
+
+    let caught = yar_query
+        .iter()
+        .filter(|(_, yar_pos)| {
+            util::intersect_rect(yar_pos, &YAR_BOUNDS, &band_center, &EXPLOSION_BAND_BOUNDS)
+        })
+        .map(|(e, _)| e)
+        .collect();
+
+    commands.insert_resource(QotileDeathSequence {
+        timer: Timer::from_seconds(EXPLOSION_DURATION, false),
+        caught,
+    });
+
+    let _ = state.set(AppState::QotileDeathSequence);
+}
+
+pub fn flash(mut rng: ResMut<GameRng>, mut query: Query<&mut Sprite, With<ExplosionBand>>) {
+    for mut sprite in query.iter_mut() {
+        sprite.color = Color::hsl(rng.gen_range(0.0..360.0), 1.0, 0.5);
+    }
+}
+
+/// Yar scores for getting out of the band before the explosion ends.
+pub fn escape(
+    mut score_event: EventWriter<ScoreEvent>,
+    mut sequence: ResMut<QotileDeathSequence>,
+    band_query: Query<&GamePos, With<ExplosionBand>>,
+    yar_query: Query<(Entity, &GamePos, &Yar), Without<ExplosionBand>>,
+) {
+    for (e, yar_pos, yar) in yar_query.iter() {
+        if yar.is_dead() || !sequence.caught.contains(&e) {
+            continue;
+        }
+
+        let in_band = band_query.iter().any(|band_pos| {
+            util::intersect_rect(yar_pos, &YAR_BOUNDS, band_pos, &EXPLOSION_BAND_BOUNDS)
+        });
+
+        if !in_band {
+            sequence.caught.remove(&e);
+            score_event.send(ScoreEvent {
+                points: BAND_ESCAPE_POINTS,
+            });
+        }
+    }
+}
+
+/// Once the explosion dies down the next round begins.
+pub fn finish(
+    clock: Res<GameClock>,
+    mut sequence: ResMut<QotileDeathSequence>,
+    mut state: ResMut<State<AppState>>,
+) {
+    if sequence.timer.tick(clock.delta).just_finished() {
+        let _ = state.set(AppState::Playing);
+    }
+}
+
+pub fn cleanup(mut commands: Commands, query: Query<Entity, With<ExplosionBand>>) {
+    for e in query.iter() {
+        commands.entity(e).despawn();
+    }
+    commands.remove_resource::<QotileDeathSequence>();
+}
@@ -0,0 +1,49 @@
+//! The game's random numbers. Everything random in the game draws from one generator, so a game
+//! seeded the same way plays out the same way.
+use crate::console::{self, ConsoleAppExt, ConsoleCommand};
+use anyhow::{bail, Context};
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+pub struct RngPlugin;
+
+impl Plugin for RngPlugin {
+    fn build(&self, app: &mut App) {
+        // Keep a generator that was seeded before the plugin was added.
+        app.init_resource::<GameRng>()
+            .add_console_command(ConsoleCommand {
+                name: "seed",
+                usage: "N",
+                help: "Draw random numbers from seed N from now on.",
+                run: seed,
+            });
+    }
+}
+
+fn seed(world: &mut World, args: &[&str]) -> anyhow::Result<String> {
+    let seed = match args {
+        [seed] => seed
+            .parse()
+            .with_context(|| format!("`{}` is not a seed", seed))?,
+        _ => bail!("usage: seed N"),
+    };
+    world.insert_resource(GameRng::seeded(seed));
+    console::discard_replay(world);
+    Ok(format!("seeded with {}", seed))
+}
+
+#[derive(Deref, DerefMut)]
+pub struct GameRng(pub StdRng);
+
+impl GameRng {
+    pub fn seeded(seed: u64) -> Self {
+        Self(StdRng::seed_from_u64(seed))
+    }
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        Self(StdRng::from_entropy())
+    }
+}
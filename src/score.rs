@@ -0,0 +1,42 @@
+use crate::level::NewGameEvent;
+use crate::GameSystem;
+use bevy::prelude::*;
+
+/// Award the player points.
+pub struct ScoreEvent {
+    pub points: u32,
+}
+
+pub struct ScorePlugin;
+
+impl Plugin for ScorePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ScoreEvent>()
+            .init_resource::<Score>()
+            .add_system(new_game.label(GameSystem::Start))
+            .add_system(
+                tally
+                    .label(GameSystem::Bookkeeping)
+                    .after(GameSystem::Spawn),
+            );
+    }
+}
+
+/// The player's score so far.
+#[derive(Debug, Default)]
+pub struct Score {
+    pub points: u32,
+}
+
+pub fn new_game(mut new_game_event: EventReader<NewGameEvent>, mut score: ResMut<Score>) {
+    if new_game_event.iter().count() > 0 {
+        *score = Score::default();
+    }
+}
+
+pub fn tally(mut score_event: EventReader<ScoreEvent>, mut score: ResMut<Score>) {
+    for event in score_event.iter() {
+        score.points += event.points;
+        debug!("scored {} points, {} total", event.points, score.points);
+    }
+}
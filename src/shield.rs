@@ -1,17 +1,70 @@
-use crate::SCREEN_SCALE;
-use crate::SCREEN_SIZE;
-use bevy::math::const_vec2;
+use crate::console::{self, ConsoleAppExt, ConsoleCommand};
+use crate::level::{CurrentRound, RoundStartEvent};
+use crate::motion::GamePos;
+use crate::GameSystem;
+use crate::PLAYFIELD_SIZE;
+use anyhow::{anyhow, bail};
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::ecs::event::Events;
+use bevy::math::const_ivec2;
 use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use serde::{Deserialize, Serialize};
 
 pub struct SpawnShieldEvent;
+
+/// Damage the given shield block.
+pub struct ShieldDamageEvent {
+    pub block: Entity,
+    pub damage: i32,
+    pub source: ShieldDamageSource,
+}
+
+/// The given shield block's health ran out.
+pub struct ShieldBlockDestroyedEvent {
+    pub block: Entity,
+    /// What dealt the last of the damage.
+    pub source: ShieldDamageSource,
+}
+
+/// What damaged a shield block.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShieldDamageSource {
+    /// Yar took a bite out of it.
+    Eaten,
+    Bullet,
+    ZorlonCannon,
+}
+
 pub struct ShieldPlugin;
 
 impl Plugin for ShieldPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<SpawnShieldEvent>()
+            .add_event::<ShieldDamageEvent>()
+            .add_event::<ShieldBlockDestroyedEvent>()
+            .add_asset::<ShieldShape>()
+            .init_asset_loader::<ShieldShapeLoader>()
             .add_startup_system(setup)
-            .add_system(spawn)
-            .add_system(monitor_health);
+            .add_system(
+                damage
+                    .label(GameSystem::Reaction)
+                    .after(GameSystem::Collision),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .label(GameSystem::Spawn)
+                    .after(GameSystem::Reaction)
+                    .with_system(round_started)
+                    .with_system(spawn.after(round_started))
+                    .with_system(monitor_health),
+            )
+            .add_console_command(ConsoleCommand {
+                name: "shield reset",
+                usage: "[SHAPE]",
+                help: "Rebuild the shield whole, in the round's shape or `shields/SHAPE.shield`.",
+                run: reset_shield,
+            });
     }
 }
 
@@ -28,74 +81,254 @@ pub struct ShieldHealth {
     pub health: i32,
 }
 
-static SHIELD_SHAPE_CURVED: [usize; 128] = [
-    0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 1, 1, 1, 1, 1, 0, 0, 1, 1, 1, 1, 1, 1, 0, 1, 1, 1, 1, 1, 1, 0,
-    1, 1, 1, 1, 1, 1, 0, 0, 1, 1, 1, 1, 1, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 0,
-    1, 1, 1, 1, 0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 0, 1, 1, 1, 1, 1, 0, 0, 0, 1, 1, 1, 1, 1, 1, 0, 0,
-    0, 1, 1, 1, 1, 1, 1, 0, 0, 0, 1, 1, 1, 1, 1, 1, 0, 0, 0, 1, 1, 1, 1, 1, 0, 0, 0, 0, 1, 1, 1, 1,
-];
+/// Shape used until something picks another one.
+pub const DEFAULT_SHIELD_SHAPE: &str = "shields/curved.shield";
 
-const SHIELD_HEIGHT_IN_BLOCKS: u32 = 16;
-const SHIELD_WIDTH_IN_BLOCKS: u32 = 8;
-pub const SHIELD_BLOCK_SPRITE_SIZE: Vec2 = const_vec2!([8.0 * SCREEN_SCALE, 8.0 * SCREEN_SCALE]);
-const SHIELD_HEIGHT: f32 = SHIELD_HEIGHT_IN_BLOCKS as f32 * 8.0 * SCREEN_SCALE;
-const SHIELD_WIDTH: f32 = SHIELD_WIDTH_IN_BLOCKS as f32 * 8.0 * SCREEN_SCALE;
+pub const SHIELD_BLOCK_SPRITE_SIZE: IVec2 = const_ivec2!([4, 8]);
 const SHIELD_BLOCK_INITIAL_HEALTH: i32 = 5;
 
-pub fn setup(mut spawn_event: EventWriter<SpawnShieldEvent>) {
+/// The layout of a shield, loaded from a `.shield` text asset.
+///
+/// The file is a grid of cells, one line per row from top to bottom, preceded by optional
+/// `key: value` settings:
+///
+/// ```text
+/// ; Comment
+/// color: 0.34 0.18 0.05
+/// health: 5
+/// ..##
+/// .#9#
+/// ```
+///
+/// `.` is an empty cell, `#` is a block with the default `health` and a digit `1`-`9` is a block
+/// with that much health.
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "0f5d2c9a-5b4e-4a47-9a43-6d1b7f3c2e81"]
+pub struct ShieldShape {
+    pub width: u32,
+    pub height: u32,
+    /// Block health for each cell, row-major starting from the bottom row. `None` if empty.
+    pub cells: Vec<Option<i32>>,
+    pub color: Color,
+}
+
+impl ShieldShape {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut color = Color::rgb(0.34, 0.18, 0.05);
+        let mut health = SHIELD_BLOCK_INITIAL_HEALTH;
+        let mut rows: Vec<&str> = Vec::new();
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some((key, value)) = line.split_once(':') {
+                if !rows.is_empty() {
+                    bail!(
+                        "line {}: settings must come before the grid",
+                        line_number + 1
+                    );
+                }
+                match key.trim() {
+                    "color" => {
+                        let channels = value
+                            .split_whitespace()
+                            .map(str::parse::<f32>)
+                            .collect::<Result<Vec<_>, _>>()?;
+                        color = match channels[..] {
+                            [r, g, b] => Color::rgb(r, g, b),
+                            _ => bail!("line {}: color needs 3 channels", line_number + 1),
+                        };
+                    }
+                    "health" => {
+                        health = match value.trim().parse() {
+                            Ok(health) if health >= 1 => health,
+                            _ => bail!("line {}: health must be 1 or more", line_number + 1),
+                        };
+                    }
+                    other => bail!("line {}: unknown setting `{}`", line_number + 1, other),
+                }
+                continue;
+            }
+
+            rows.push(line);
+        }
+
+        let width = rows.first().map_or(0, |row| row.chars().count());
+        if width == 0 {
+            bail!("shield has no cells");
+        }
+
+        let mut cells = Vec::with_capacity(width * rows.len());
+        // The grid is written top to bottom but blocks are laid out bottom to top.
+        for row in rows.iter().rev() {
+            if row.chars().count() != width {
+                bail!("every row must be {} cells wide: `{}`", width, row);
+            }
+            for cell in row.chars() {
+                cells.push(match cell {
+                    '.' => None,
+                    '#' => Some(health),
+                    '1'..='9' => cell.to_digit(10).map(|digit| digit as i32),
+                    _ => return Err(anyhow!("unknown cell `{}` in `{}`", cell, row)),
+                });
+            }
+        }
+
+        Ok(Self {
+            width: width as u32,
+            height: rows.len() as u32,
+            cells,
+            color,
+        })
+    }
+
+    /// Health of the block at `(x, y)`, counting up from the bottom row.
+    pub fn cell(&self, x: u32, y: u32) -> Option<i32> {
+        self.cells[(x + y * self.width) as usize]
+    }
+}
+
+#[derive(Default)]
+pub struct ShieldShapeLoader;
+
+impl AssetLoader for ShieldShapeLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let shape = ShieldShape::parse(std::str::from_utf8(bytes)?)?;
+            load_context.set_default_asset(LoadedAsset::new(shape));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["shield"]
+    }
+}
+
+/// The shape used whenever a shield is spawned.
+///
+/// Swap the handle to pick a different shape for the next round or game variation.
+pub struct ActiveShieldShape(pub Handle<ShieldShape>);
+
+pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(ActiveShieldShape(asset_server.load(DEFAULT_SHIELD_SHAPE)));
+}
+
+fn reset_shield(world: &mut World, args: &[&str]) -> anyhow::Result<String> {
+    let path = match args {
+        [] => world.resource::<CurrentRound>().params.shield_shape.clone(),
+        [shape] => format!("shields/{}.shield", shape),
+        _ => bail!("usage: shield reset [SHAPE]"),
+    };
+    let shape = world.resource::<AssetServer>().load(path.as_str());
+    world.insert_resource(ActiveShieldShape(shape));
+    world
+        .resource_mut::<Events<SpawnShieldEvent>>()
+        .send(SpawnShieldEvent);
+    console::discard_replay(world);
+    Ok(format!("rebuilding the shield from {}", path))
+}
+
+/// Each round rebuilds the shield in the shape the round asks for.
+pub fn round_started(
+    mut round_start_event: EventReader<RoundStartEvent>,
+    mut spawn_event: EventWriter<SpawnShieldEvent>,
+    mut active_shape: ResMut<ActiveShieldShape>,
+    round: Res<CurrentRound>,
+    asset_server: Res<AssetServer>,
+) {
+    if round_start_event.iter().count() == 0 {
+        return;
+    }
+
+    active_shape.0 = asset_server.load(round.params.shield_shape.as_str());
     spawn_event.send(SpawnShieldEvent);
 }
 
+/// Spawn the active shield shape, once it has loaded.
+///
+/// Editing the active shape's file while the game runs rebuilds the shield.
+#[allow(clippy::too_many_arguments)]
 pub fn spawn(
     mut commands: Commands,
     mut spawn_event: EventReader<SpawnShieldEvent>,
+    mut shape_event: EventReader<AssetEvent<ShieldShape>>,
+    mut pending: Local<bool>,
+    active_shape: Option<Res<ActiveShieldShape>>,
+    shapes: Res<Assets<ShieldShape>>,
+    round: Res<CurrentRound>,
     blocks_query: Query<Entity, With<ShieldBlock>>,
 ) {
-    if spawn_event.iter().next().is_none() {
+    if spawn_event.iter().count() > 0 {
+        *pending = true;
+    }
+
+    let active_shape = match active_shape {
+        Some(active_shape) => active_shape,
+        None => return,
+    };
+
+    for event in shape_event.iter() {
+        if let AssetEvent::Modified { handle } = event {
+            if *handle == active_shape.0 {
+                *pending = true;
+            }
+        }
+    }
+
+    if !*pending {
         return;
     }
 
+    let shape = match shapes.get(&active_shape.0) {
+        Some(shape) => shape,
+        None => return,
+    };
+    *pending = false;
+
     // Remove any existing blocks
     for e in blocks_query.iter() {
         commands.entity(e).despawn()
     }
 
-    let mut shield_origin = Transform::identity();
-    shield_origin.translation.x = SCREEN_SIZE.x / 2.0 - SHIELD_WIDTH;
-    shield_origin.translation.y -= SHIELD_HEIGHT / 2.0;
+    let shield_width = shape.width as i32 * SHIELD_BLOCK_SPRITE_SIZE.x;
+    let shield_height = shape.height as i32 * SHIELD_BLOCK_SPRITE_SIZE.y;
 
-    let mut block_offset = Transform::identity();
+    let shield_origin = IVec2::new(
+        PLAYFIELD_SIZE.x / 2 - shield_width - round.params.shield_inset,
+        round.params.shield_y - shield_height / 2,
+    );
 
-    for y in 0..SHIELD_HEIGHT_IN_BLOCKS {
-        for x in 0..SHIELD_WIDTH_IN_BLOCKS {
-            let block_index = (x + y * SHIELD_WIDTH_IN_BLOCKS) as usize;
-            if SHIELD_SHAPE_CURVED[block_index] == 1 {
-                let block_transform = Transform::from_translation(
-                    shield_origin.translation + block_offset.translation,
-                );
+    for y in 0..shape.height {
+        for x in 0..shape.width {
+            if let Some(health) = shape.cell(x, y) {
+                let block_pos =
+                    shield_origin + IVec2::new(x as i32, y as i32) * SHIELD_BLOCK_SPRITE_SIZE;
 
                 commands
                     .spawn_bundle(SpriteBundle {
                         sprite: Sprite {
-                            color: Color::rgb(0.34, 0.18, 0.05),
-                            custom_size: Some(SHIELD_BLOCK_SPRITE_SIZE),
+                            color: shape.color,
+                            custom_size: Some(SHIELD_BLOCK_SPRITE_SIZE.as_vec2()),
                             ..default()
                         },
-                        transform: block_transform,
+                        transform: Transform::from_translation(block_pos.as_vec2().extend(0.0)),
                         ..default()
                     })
+                    .insert(GamePos(block_pos))
                     .insert(ShieldBlock {
                         position: Vec2::new(x as f32, y as f32),
                     })
-                    .insert(ShieldHealth {
-                        health: SHIELD_BLOCK_INITIAL_HEALTH,
-                    });
+                    .insert(ShieldHealth { health });
             }
-
-            block_offset.translation.x += SHIELD_BLOCK_SPRITE_SIZE.x;
         }
-        block_offset.translation.x = 0.0;
-        block_offset.translation.y += SHIELD_BLOCK_SPRITE_SIZE.y;
     }
 }
 
@@ -115,6 +348,25 @@ pub fn despawn(
 }
  */
 
+pub fn damage(
+    mut damage_event: EventReader<ShieldDamageEvent>,
+    mut destroyed_event: EventWriter<ShieldBlockDestroyedEvent>,
+    mut query: Query<&mut ShieldHealth>,
+) {
+    for event in damage_event.iter() {
+        if let Ok(mut shield_health) = query.get_mut(event.block) {
+            let was_intact = shield_health.health > 0;
+            shield_health.health -= event.damage;
+            if was_intact && shield_health.health <= 0 {
+                destroyed_event.send(ShieldBlockDestroyedEvent {
+                    block: event.block,
+                    source: event.source,
+                });
+            }
+        }
+    }
+}
+
 pub fn monitor_health(mut commands: Commands, query: Query<(Entity, &ShieldHealth)>) {
     for (e, shield_health) in query.iter() {
         if shield_health.health <= 0 {
@@ -122,3 +374,50 @@ pub fn monitor_health(mut commands: Commands, query: Query<(Entity, &ShieldHealt
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cells_bottom_row_first() {
+        let shape = ShieldShape::parse("; Comment\n#.\n.3\n").unwrap();
+        assert_eq!((shape.width, shape.height), (2, 2));
+        assert_eq!(shape.cell(0, 0), None);
+        assert_eq!(shape.cell(1, 0), Some(3));
+        assert_eq!(shape.cell(0, 1), Some(SHIELD_BLOCK_INITIAL_HEALTH));
+        assert_eq!(shape.cell(1, 1), None);
+    }
+
+    #[test]
+    fn parses_settings() {
+        let shape = ShieldShape::parse("color: 0.5 0.25 1\nhealth: 2\n#9\n").unwrap();
+        assert_eq!(shape.color, Color::rgb(0.5, 0.25, 1.0));
+        assert_eq!(shape.cell(0, 0), Some(2));
+        assert_eq!(shape.cell(1, 0), Some(9));
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        let error = |text| ShieldShape::parse(text).unwrap_err().to_string();
+        assert_eq!(error("color: 1 1\n#\n"), "line 1: color needs 3 channels");
+        assert_eq!(
+            error("\nhealth: 0\n#\n"),
+            "line 2: health must be 1 or more"
+        );
+        assert_eq!(error("health: -3\n#\n"), "line 1: health must be 1 or more");
+        assert_eq!(error("size: 3\n#\n"), "line 1: unknown setting `size`");
+        assert_eq!(
+            error("#\nhealth: 2\n"),
+            "line 2: settings must come before the grid"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_grids() {
+        assert!(ShieldShape::parse("; Nothing\n").is_err());
+        assert!(ShieldShape::parse("#.\n#\n").is_err());
+        assert!(ShieldShape::parse("#0\n").is_err());
+        assert!(ShieldShape::parse("#x\n").is_err());
+    }
+}
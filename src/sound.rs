@@ -0,0 +1,274 @@
+use crate::bullet::Bullet;
+use crate::motion::GamePos;
+use crate::neutral_zone::{NeutralZone, NEUTRAL_ZONE_BOUNDS};
+use crate::qotile::QotileDiedEvent;
+use crate::shield::{ShieldDamageEvent, ShieldDamageSource};
+use crate::tia;
+use crate::tia::Step;
+use crate::util;
+use crate::yar::{Yar, YarDiedEvent, YAR_BOUNDS};
+use crate::zorlon_cannon::ZorlonCannonLaunchEvent;
+use bevy::prelude::*;
+use std::sync::Arc;
+
+/// How often the Neutral Zone hum restarts while Yar is inside it.
+const NEUTRAL_ZONE_HUM_PERIOD: f64 = 0.5;
+
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup)
+            .add_system(shoot)
+            .add_system(eat)
+            .add_system(neutral_zone_hum.with_run_criteria(crate::not_paused))
+            .add_system(yar_died)
+            .add_system(qotile_died)
+            .add_system(cannon_launched);
+    }
+}
+
+/// Handles to every synthesized sound effect.
+pub struct SoundEffects {
+    pub shoot: Handle<AudioSource>,
+    pub eat: Handle<AudioSource>,
+    pub neutral_zone_hum: Handle<AudioSource>,
+    pub yar_died: Handle<AudioSource>,
+    pub qotile_died: Handle<AudioSource>,
+    pub cannon_launch: Handle<AudioSource>,
+}
+
+/// Hold each frame of a linear sweep between two register settings.
+fn sweep(frames: u32, audc: u8, audf: (u8, u8), audv: (u8, u8)) -> Vec<Step> {
+    let lerp = |(from, to): (u8, u8), frame: u32| {
+        let t = frame as f32 / (frames - 1).max(1) as f32;
+        (from as f32 + (to as f32 - from as f32) * t).round() as u8
+    };
+
+    (0..frames)
+        .map(|frame| Step::new(1, audc, lerp(audf, frame), lerp(audv, frame)))
+        .collect()
+}
+
+pub fn shoot_steps() -> Vec<Step> {
+    sweep(6, 4, (2, 12), (10, 4))
+}
+
+pub fn eat_steps() -> Vec<Step> {
+    vec![Step::new(2, 6, 8, 10), Step::new(2, 6, 14, 8)]
+}
+
+pub fn neutral_zone_hum_steps() -> Vec<Step> {
+    vec![Step::new(30, 7, 31, 4)]
+}
+
+pub fn yar_died_steps() -> Vec<Step> {
+    sweep(60, 8, (0, 31), (15, 0))
+}
+
+pub fn qotile_died_steps() -> Vec<Step> {
+    let mut steps = vec![Step::new(10, 8, 2, 15)];
+    steps.extend(sweep(120, 8, (4, 31), (15, 0)));
+    steps
+}
+
+pub fn cannon_launch_steps() -> Vec<Step> {
+    sweep(20, 12, (0, 20), (12, 6))
+}
+
+/// Wrap mono 16-bit samples in a WAV file.
+pub fn encode_wav(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // Bytes per second
+    wav.extend_from_slice(&2u16.to_le_bytes()); // Bytes per sample
+    wav.extend_from_slice(&16u16.to_le_bytes()); // Bits per sample
+
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    wav
+}
+
+pub fn setup(mut commands: Commands, mut audio_sources: ResMut<Assets<AudioSource>>) {
+    let mut synthesize = |steps: Vec<Step>| {
+        let wav = encode_wav(&tia::render(&steps), tia::SAMPLE_RATE);
+        audio_sources.add(AudioSource {
+            bytes: Arc::from(wav),
+        })
+    };
+
+    commands.insert_resource(SoundEffects {
+        shoot: synthesize(shoot_steps()),
+        eat: synthesize(eat_steps()),
+        neutral_zone_hum: synthesize(neutral_zone_hum_steps()),
+        yar_died: synthesize(yar_died_steps()),
+        qotile_died: synthesize(qotile_died_steps()),
+        cannon_launch: synthesize(cannon_launch_steps()),
+    });
+}
+
+/// Only bullets that actually leave the ship make a sound, not every press of the fire button.
+pub fn shoot(audio: Res<Audio>, sounds: Res<SoundEffects>, query: Query<&Bullet, Added<Bullet>>) {
+    if !query.is_empty() {
+        audio.play(sounds.shoot.clone());
+    }
+}
+
+pub fn eat(
+    audio: Res<Audio>,
+    sounds: Res<SoundEffects>,
+    mut damage_event: EventReader<ShieldDamageEvent>,
+) {
+    if damage_event
+        .iter()
+        .any(|event| event.source == ShieldDamageSource::Eaten)
+    {
+        audio.play(sounds.eat.clone());
+    }
+}
+
+/// Whether the hum should start again at `now`, given when it last started while Yar was in the
+/// Neutral Zone.
+fn hum_due(last_played: Option<f64>, now: f64) -> bool {
+    match last_played {
+        Some(last) => now - last >= NEUTRAL_ZONE_HUM_PERIOD,
+        None => true,
+    }
+}
+
+pub fn neutral_zone_hum(
+    audio: Res<Audio>,
+    sounds: Res<SoundEffects>,
+    time: Res<Time>,
+    mut last_played: Local<Option<f64>>,
+    yar_query: Query<&GamePos, With<Yar>>,
+    nz_query: Query<&GamePos, With<NeutralZone>>,
+) {
+    let in_neutral_zone = yar_query.iter().any(|yar_pos| {
+        nz_query
+            .iter()
+            .any(|nz_pos| util::intersect_rect(yar_pos, &YAR_BOUNDS, nz_pos, &NEUTRAL_ZONE_BOUNDS))
+    });
+
+    if !in_neutral_zone {
+        *last_played = None;
+        return;
+    }
+
+    let now = time.seconds_since_startup();
+    if hum_due(*last_played, now) {
+        *last_played = Some(now);
+        audio.play(sounds.neutral_zone_hum.clone());
+    }
+}
+
+pub fn yar_died(
+    audio: Res<Audio>,
+    sounds: Res<SoundEffects>,
+    mut death_event: EventReader<YarDiedEvent>,
+) {
+    for _ in death_event.iter() {
+        audio.play(sounds.yar_died.clone());
+    }
+}
+
+pub fn qotile_died(
+    audio: Res<Audio>,
+    sounds: Res<SoundEffects>,
+    mut death_event: EventReader<QotileDiedEvent>,
+) {
+    for _ in death_event.iter() {
+        audio.play(sounds.qotile_died.clone());
+    }
+}
+
+pub fn cannon_launched(
+    audio: Res<Audio>,
+    sounds: Res<SoundEffects>,
+    mut launch_event: EventReader<ZorlonCannonLaunchEvent>,
+) {
+    for ZorlonCannonLaunchEvent(cannon) in launch_event.iter() {
+        debug!("Zorlon Cannon {:?} launched", cannon);
+        audio.play(sounds.cannon_launch.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn effects_last_as_many_frames_as_their_steps() {
+        let effects = [
+            shoot_steps(),
+            eat_steps(),
+            neutral_zone_hum_steps(),
+            yar_died_steps(),
+            qotile_died_steps(),
+            cannon_launch_steps(),
+        ];
+        for steps in effects {
+            let frames: u32 = steps.iter().map(|step| step.frames).sum();
+            let samples = tia::render(&steps);
+            assert_eq!(
+                samples.len() as u32,
+                frames * tia::SAMPLE_RATE / tia::FRAME_RATE
+            );
+            assert!(samples.iter().any(|&sample| sample != 0));
+        }
+    }
+
+    #[test]
+    fn hum_restarts_each_period_while_yar_stays_in_the_zone() {
+        assert!(hum_due(None, 12.0));
+        assert!(!hum_due(Some(12.0), 12.0 + NEUTRAL_ZONE_HUM_PERIOD / 2.0));
+        assert!(hum_due(Some(12.0), 12.0 + NEUTRAL_ZONE_HUM_PERIOD));
+        // The hum lasts about as long as it waits to restart, so it sounds unbroken.
+        let frames: u32 = neutral_zone_hum_steps()
+            .iter()
+            .map(|step| step.frames)
+            .sum();
+        assert_eq!(
+            f64::from(frames) / f64::from(tia::FRAME_RATE),
+            NEUTRAL_ZONE_HUM_PERIOD
+        );
+    }
+
+    #[test]
+    fn sweeps_run_from_one_setting_to_the_other() {
+        let steps = sweep(6, 4, (2, 12), (10, 4));
+        assert_eq!(steps.len(), 6);
+        assert_eq!(steps[0], Step::new(1, 4, 2, 10));
+        assert_eq!(steps[5], Step::new(1, 4, 12, 4));
+        assert_eq!(sweep(1, 4, (2, 12), (10, 4)), vec![Step::new(1, 4, 2, 10)]);
+    }
+
+    #[test]
+    fn wav_header_describes_the_samples() {
+        let wav = encode_wav(&[0, 1, -1], tia::SAMPLE_RATE);
+        assert_eq!(wav.len(), 44 + 6);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 36 + 6);
+        assert_eq!(&wav[8..12], b"WAVE");
+        assert_eq!(
+            u32::from_le_bytes(wav[24..28].try_into().unwrap()),
+            tia::SAMPLE_RATE
+        );
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 6);
+        assert_eq!(&wav[44..], &[0, 0, 1, 0, 0xff, 0xff]);
+    }
+}
@@ -0,0 +1,208 @@
+//! Statistics of the game being played, gathered from gameplay events as they happen. They are
+//! shown on the game over screen and saved in the game's replay.
+use crate::bullet::{Bullet, DespawnBulletEvent};
+use crate::clock::GameClock;
+use crate::control::{ControlTarget, GameRecording};
+use crate::level::{GameOverEvent, NewGameEvent};
+use crate::motion::GamePos;
+use crate::neutral_zone::{NeutralZone, NEUTRAL_ZONE_BOUNDS};
+use crate::qotile::QotileDiedEvent;
+use crate::shield::{ShieldBlockDestroyedEvent, ShieldDamageSource};
+use crate::util::{self, DespawnReason};
+use crate::yar::{DeathCause, Yar, YarDiedEvent, YAR_BOUNDS};
+use crate::zorlon_cannon::ZorlonCannonLaunchEvent;
+use crate::{AppState, GameSystem};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use serde::{Deserialize, Serialize};
+
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SessionStats>()
+            // In the frame the game ends, so the replay has its statistics by the time anything
+            // else sees the game is over.
+            .add_system(
+                gather
+                    .label(GameSystem::Bookkeeping)
+                    .after(GameSystem::Spawn)
+                    .after(crate::control::record_game),
+            );
+    }
+}
+
+/// Deaths, by what caused them.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeathCounts {
+    pub destroyer_missile: u32,
+    pub swirl: u32,
+    pub own_cannon: u32,
+    pub ghost_cannon: u32,
+}
+
+impl DeathCounts {
+    pub fn count(&mut self, cause: DeathCause) {
+        match cause {
+            DeathCause::DestroyerMissile => self.destroyer_missile += 1,
+            DeathCause::Swirl => self.swirl += 1,
+            DeathCause::OwnCannon => self.own_cannon += 1,
+            DeathCause::GhostCannon => self.ghost_cannon += 1,
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.destroyer_missile + self.swirl + self.own_cannon + self.ghost_cannon
+    }
+}
+
+/// How a game went.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionStats {
+    /// Bullets the player fired.
+    pub shots: u32,
+    /// The player's bullets that struck the shield.
+    pub hits: u32,
+    /// Shield cells the player ate through.
+    pub cells_eaten: u32,
+    /// Shield cells destroyed by the player's bullets or cannon.
+    pub cells_shot: u32,
+    pub deaths: DeathCounts,
+    /// Times the player fired the cannon.
+    pub cannon_launches: u32,
+    /// The player's cannon shots that destroyed Qotile.
+    pub cannon_hits: u32,
+    /// Seconds the player's Yar spent in the neutral zone.
+    pub neutral_zone_time: f32,
+    /// Seconds the player spent flying Yar.
+    pub yar_time: f32,
+    /// Seconds the player spent at the cannon.
+    pub cannon_time: f32,
+}
+
+impl SessionStats {
+    /// Share of the player's bullets that hit something.
+    pub fn accuracy(&self) -> Option<f32> {
+        (self.shots > 0).then(|| self.hits as f32 / self.shots as f32)
+    }
+
+    /// Share of the player's cannon shots that destroyed Qotile.
+    pub fn cannon_accuracy(&self) -> Option<f32> {
+        (self.cannon_launches > 0).then(|| self.cannon_hits as f32 / self.cannon_launches as f32)
+    }
+}
+
+/// The events statistics are gathered from.
+#[derive(SystemParam)]
+pub struct StatsEvents<'w, 's> {
+    new_game: EventReader<'w, 's, NewGameEvent>,
+    game_over: EventReader<'w, 's, GameOverEvent>,
+    despawn_bullet: EventReader<'w, 's, DespawnBulletEvent>,
+    block_destroyed: EventReader<'w, 's, ShieldBlockDestroyedEvent>,
+    yar_died: EventReader<'w, 's, YarDiedEvent>,
+    cannon_launch: EventReader<'w, 's, ZorlonCannonLaunchEvent>,
+    qotile_died: EventReader<'w, 's, QotileDiedEvent>,
+}
+
+/// Entities already counted this game. Deaths and despawns can be sent again on the frames
+/// after, until they take effect.
+#[derive(Default)]
+pub struct Counted {
+    bullets: HashSet<Entity>,
+    yars: HashSet<Entity>,
+    qotiles: HashSet<Entity>,
+}
+
+/// Add this frame to the game's statistics, starting afresh with each game. The finished
+/// game's statistics go into its replay.
+///
+/// Only what the player does counts, not what the ghost of their last life does while they are
+/// at the cannon or vice versa; but every death costs a life, whoever was flying.
+///
+/// Shots are the bullets that came into play since the last frame: holding fire asks for a shot
+/// every frame, but Yar only has one bullet in the air at a time.
+#[allow(clippy::too_many_arguments)]
+pub fn gather(
+    mut stats: ResMut<SessionStats>,
+    mut counted: Local<Counted>,
+    mut events: StatsEvents,
+    mut recording: ResMut<GameRecording>,
+    clock: Res<GameClock>,
+    state: Res<State<AppState>>,
+    control_target: Res<ControlTarget>,
+    bullet_query: Query<(), Added<Bullet>>,
+    yar_query: Query<(&GamePos, &Yar)>,
+    nz_query: Query<&GamePos, With<NeutralZone>>,
+) {
+    if events.new_game.iter().count() > 0 {
+        *stats = SessionStats::default();
+        *counted = Counted::default();
+    }
+    let player_yar = matches!(*control_target, ControlTarget::Yar);
+    let player_cannon = matches!(*control_target, ControlTarget::Cannon);
+
+    if player_yar {
+        stats.shots += bullet_query.iter().count() as u32;
+    }
+    for event in events.despawn_bullet.iter() {
+        if event.reason == DespawnReason::Collision
+            && counted.bullets.insert(event.entity)
+            && player_yar
+        {
+            stats.hits += 1;
+        }
+    }
+    for event in events.block_destroyed.iter() {
+        match event.source {
+            ShieldDamageSource::Eaten if player_yar => stats.cells_eaten += 1,
+            ShieldDamageSource::Bullet if player_yar => stats.cells_shot += 1,
+            ShieldDamageSource::ZorlonCannon if player_cannon => stats.cells_shot += 1,
+            _ => {}
+        }
+    }
+    for event in events.yar_died.iter() {
+        if counted.yars.insert(event.yar) {
+            stats.deaths.count(event.cause);
+        }
+    }
+    for _ in events.cannon_launch.iter() {
+        if player_cannon {
+            stats.cannon_launches += 1;
+        }
+    }
+    for QotileDiedEvent { qotile, .. } in events.qotile_died.iter() {
+        if counted.qotiles.insert(*qotile) && player_cannon {
+            stats.cannon_hits += 1;
+        }
+    }
+
+    if *state.current() == AppState::Playing {
+        let delta = clock.delta_seconds();
+        match *control_target {
+            ControlTarget::Yar => stats.yar_time += delta,
+            ControlTarget::Cannon => stats.cannon_time += delta,
+            ControlTarget::Demo => {}
+        }
+        let in_neutral_zone = player_yar
+            && yar_query
+                .iter()
+                .filter(|(_, yar)| !yar.is_dead())
+                .any(|(yar_pos, _)| {
+                    nz_query.iter().any(|nz_pos| {
+                        util::intersect_rect(yar_pos, &YAR_BOUNDS, nz_pos, &NEUTRAL_ZONE_BOUNDS)
+                    })
+                });
+        if in_neutral_zone {
+            stats.neutral_zone_time += delta;
+        }
+    }
+
+    if events.game_over.iter().count() > 0 {
+        if let Some(replay) = recording.replay.as_mut() {
+            replay.stats = Some(stats.clone());
+        }
+    }
+}
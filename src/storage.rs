@@ -0,0 +1,118 @@
+//! Somewhere to keep things between runs: files under `saves/` on native builds and
+//! `localStorage` on the web. Keys are relative paths such as `replays/1.replay`.
+
+#[cfg(not(target_arch = "wasm32"))]
+mod platform {
+    use std::path::PathBuf;
+
+    /// Directory saved files go in, relative to the working directory.
+    const SAVE_DIR: &str = "saves";
+
+    fn path(key: &str) -> PathBuf {
+        PathBuf::from(SAVE_DIR).join(key)
+    }
+
+    pub fn load(key: &str) -> Option<String> {
+        std::fs::read_to_string(path(key)).ok()
+    }
+
+    pub fn save(key: &str, contents: &str) -> anyhow::Result<()> {
+        let path = path(key);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    pub fn remove(key: &str) -> anyhow::Result<()> {
+        std::fs::remove_file(path(key))?;
+        Ok(())
+    }
+
+    /// Days since 1970-01-01.
+    fn days_since_epoch() -> i64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| (elapsed.as_secs() / 86_400) as i64)
+    }
+
+    /// Today's (UTC) year, month and day of month.
+    pub fn today() -> (i64, u32, u32) {
+        // Howard Hinnant's `civil_from_days`.
+        let z = days_since_epoch() + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        (year, month, day)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod platform {
+    use anyhow::anyhow;
+
+    /// Prefix keeping our `localStorage` keys apart from anything else on the page.
+    const KEY_PREFIX: &str = "ya_rs/";
+
+    fn local_storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok()?
+    }
+
+    pub fn load(key: &str) -> Option<String> {
+        local_storage()?
+            .get_item(&format!("{}{}", KEY_PREFIX, key))
+            .ok()?
+    }
+
+    pub fn save(key: &str, contents: &str) -> anyhow::Result<()> {
+        local_storage()
+            .ok_or_else(|| anyhow!("localStorage is unavailable"))?
+            .set_item(&format!("{}{}", KEY_PREFIX, key), contents)
+            .map_err(|err| anyhow!("could not write `{}` to localStorage: {:?}", key, err))
+    }
+
+    pub fn remove(key: &str) -> anyhow::Result<()> {
+        local_storage()
+            .ok_or_else(|| anyhow!("localStorage is unavailable"))?
+            .remove_item(&format!("{}{}", KEY_PREFIX, key))
+            .map_err(|err| anyhow!("could not remove `{}` from localStorage: {:?}", key, err))
+    }
+
+    /// Today's (local) year, month and day of month.
+    pub fn today() -> (i64, u32, u32) {
+        let date = js_sys::Date::new_0();
+        (
+            date.get_full_year() as i64,
+            date.get_month() + 1,
+            date.get_date(),
+        )
+    }
+}
+
+/// The contents stored under `key`, if there are any.
+pub fn load(key: &str) -> Option<String> {
+    platform::load(key)
+}
+
+/// Store `contents` under `key`, replacing whatever was there.
+pub fn save(key: &str, contents: &str) -> anyhow::Result<()> {
+    platform::save(key, contents)
+}
+
+/// Forget whatever is stored under `key`.
+pub fn remove(key: &str) -> anyhow::Result<()> {
+    platform::remove(key)
+}
+
+/// Today's date, as `YYYY-MM-DD`.
+pub fn today() -> String {
+    let (year, month, day) = platform::today();
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -0,0 +1,289 @@
+//! Telemetry: every gameplay event written to a JSON Lines file as it happens, for working out
+//! how the game plays. See `event_log` for the format and for reading logs back.
+//!
+//! Not part of `GamePlugin`; `run` adds it when `YA_RS_TELEMETRY` names a file to write, and the
+//! headless environment when `EnvConfig::telemetry` does.
+use crate::bullet::DespawnBulletEvent;
+use crate::clock::GameClock;
+use crate::control::ControlTarget;
+use crate::destroyer_missile::{DespawnDestroyerMissileEvent, DestroyerMissile};
+use crate::event_log::{EntityKind, GameplayEvent, LoggedEvent};
+use crate::level::{GameOverEvent, NewGameEvent};
+use crate::motion::GamePos;
+use crate::qotile::{DespawnQotileEvent, Qotile, QotileDiedEvent, SpawnQotileEvent};
+use crate::shield::{ShieldDamageEvent, SpawnShieldEvent};
+use crate::yar::{Yar, YarDiedEvent, YarRespawnEvent, YarShootEvent};
+use crate::zorlon_cannon::{
+    DespawnZorlonCannonEvent, SpawnZorlonCannonEvent, ZorlonCannon, ZorlonCannonLaunchEvent,
+};
+use crate::GameSystem;
+use bevy::app::AppExit;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// Environment variable naming the file `run` writes telemetry to.
+pub const TELEMETRY_VAR: &str = "YA_RS_TELEMETRY";
+
+pub struct TelemetryPlugin {
+    /// File to write; it is replaced if it exists.
+    pub path: PathBuf,
+}
+
+impl Plugin for TelemetryPlugin {
+    fn build(&self, app: &mut App) {
+        let file = self
+            .path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| File::create(&self.path));
+        let file = match file {
+            Ok(file) => file,
+            Err(err) => {
+                warn!(
+                    "not writing telemetry to `{}`: {}",
+                    self.path.display(),
+                    err
+                );
+                return;
+            }
+        };
+        // Last, once everything that happens in a frame has happened and entities despawned
+        // during it are gone; so where they were is noted while the frame's systems still run.
+        app.insert_resource(Telemetry {
+            writer: BufWriter::new(file),
+            last_seen: HashMap::default(),
+            died: HashSet::default(),
+            despawned: HashSet::default(),
+        })
+        .add_system(note_positions.after(GameSystem::Spawn))
+        .add_system_to_stage(CoreStage::Last, log_events);
+    }
+}
+
+pub struct Telemetry {
+    /// Flushed when a game ends and when the app exits.
+    writer: BufWriter<File>,
+    /// Where the entities this frame's despawns and shield damage are about were, in case they
+    /// are gone by the time the frame is logged.
+    last_seen: HashMap<Entity, IVec2>,
+    // Deaths and despawns can be sent again on the frames after, until they take effect; each
+    // is only logged the first time. Cleared each game.
+    died: HashSet<Entity>,
+    despawned: HashSet<Entity>,
+}
+
+/// Every gameplay event that goes into the log.
+#[derive(SystemParam)]
+pub struct GameplayEvents<'w, 's> {
+    new_game: EventReader<'w, 's, NewGameEvent>,
+    game_over: EventReader<'w, 's, GameOverEvent>,
+    yar_shoot: EventReader<'w, 's, YarShootEvent>,
+    yar_died: EventReader<'w, 's, YarDiedEvent>,
+    yar_respawn: EventReader<'w, 's, YarRespawnEvent>,
+    qotile_died: EventReader<'w, 's, QotileDiedEvent>,
+    spawn_qotile: EventReader<'w, 's, SpawnQotileEvent>,
+    spawn_shield: EventReader<'w, 's, SpawnShieldEvent>,
+    spawn_cannon: EventReader<'w, 's, SpawnZorlonCannonEvent>,
+    cannon_launch: EventReader<'w, 's, ZorlonCannonLaunchEvent>,
+    despawn_bullet: EventReader<'w, 's, DespawnBulletEvent>,
+    despawn_qotile: EventReader<'w, 's, DespawnQotileEvent>,
+    despawn_cannon: EventReader<'w, 's, DespawnZorlonCannonEvent>,
+    despawn_missile: EventReader<'w, 's, DespawnDestroyerMissileEvent>,
+    shield_damage: EventReader<'w, 's, ShieldDamageEvent>,
+}
+
+/// Entity events that can be about an entity that's gone by the end of the frame.
+#[derive(SystemParam)]
+pub struct PositionedEvents<'w, 's> {
+    despawn_bullet: EventReader<'w, 's, DespawnBulletEvent>,
+    despawn_qotile: EventReader<'w, 's, DespawnQotileEvent>,
+    despawn_cannon: EventReader<'w, 's, DespawnZorlonCannonEvent>,
+    despawn_missile: EventReader<'w, 's, DespawnDestroyerMissileEvent>,
+    shield_damage: EventReader<'w, 's, ShieldDamageEvent>,
+}
+
+/// Note where the entities in this frame's despawns and shield damage are, before despawning
+/// them takes effect at the end of the stage.
+pub fn note_positions(
+    mut telemetry: ResMut<Telemetry>,
+    mut events: PositionedEvents,
+    positions: Query<&GamePos>,
+) {
+    let entities = events
+        .despawn_bullet
+        .iter()
+        .map(|event| event.entity)
+        .chain(events.despawn_qotile.iter().map(|event| event.entity))
+        .chain(events.despawn_cannon.iter().map(|event| event.entity))
+        .chain(events.despawn_missile.iter().map(|event| event.entity))
+        .chain(events.shield_damage.iter().map(|event| event.block));
+    for entity in entities {
+        if let Ok(pos) = positions.get(entity) {
+            telemetry.last_seen.insert(entity, pos.0);
+        }
+    }
+}
+
+/// Write this frame's events to the log.
+#[allow(clippy::too_many_arguments)]
+pub fn log_events(
+    mut telemetry: ResMut<Telemetry>,
+    mut events: GameplayEvents,
+    mut last_target: Local<Option<ControlTarget>>,
+    mut app_exit: EventReader<AppExit>,
+    clock: Res<GameClock>,
+    control_target: Res<ControlTarget>,
+    positions: Query<&GamePos>,
+    yars: Query<(&GamePos, &Yar)>,
+    new_yars: Query<&GamePos, Added<Yar>>,
+    new_missiles: Query<&GamePos, Added<DestroyerMissile>>,
+    qotiles: Query<&GamePos, With<Qotile>>,
+    cannons: Query<&GamePos, With<ZorlonCannon>>,
+) {
+    let telemetry = &mut *telemetry;
+    let last_seen = &telemetry.last_seen;
+    let pos = |entity| {
+        positions
+            .get(entity)
+            .map(|pos| pos.0)
+            .ok()
+            .or_else(|| last_seen.get(&entity).copied())
+            .map(|pos| pos.to_array())
+    };
+    let live_yar = yars
+        .iter()
+        .find(|(_, yar)| !yar.is_dead())
+        .map(|(pos, _)| pos.0.to_array());
+    let qotile = qotiles.iter().next().map(|pos| pos.0.to_array());
+    let cannon = cannons.iter().next().map(|pos| pos.0.to_array());
+
+    let mut logged = Vec::new();
+    for event in events.new_game.iter() {
+        telemetry.died.clear();
+        telemetry.despawned.clear();
+        logged.push(GameplayEvent::NewGame {
+            variation: event.variation,
+        });
+    }
+    if *last_target != Some(*control_target) {
+        *last_target = Some(*control_target);
+        logged.push(GameplayEvent::ControlTarget {
+            target: *control_target,
+        });
+    }
+    for _ in events.spawn_qotile.iter() {
+        logged.push(GameplayEvent::Spawn {
+            kind: EntityKind::Qotile,
+            pos: qotile,
+        });
+    }
+    for _ in events.spawn_shield.iter() {
+        logged.push(GameplayEvent::Spawn {
+            kind: EntityKind::Shield,
+            pos: None,
+        });
+    }
+    for _ in events.spawn_cannon.iter() {
+        logged.push(GameplayEvent::Spawn {
+            kind: EntityKind::ZorlonCannon,
+            pos: cannon,
+        });
+    }
+    for pos in new_yars.iter() {
+        logged.push(GameplayEvent::Spawn {
+            kind: EntityKind::Yar,
+            pos: Some(pos.0.to_array()),
+        });
+    }
+    for pos in new_missiles.iter() {
+        logged.push(GameplayEvent::Spawn {
+            kind: EntityKind::DestroyerMissile,
+            pos: Some(pos.0.to_array()),
+        });
+    }
+    for event in events.yar_shoot.iter() {
+        logged.push(GameplayEvent::YarShoot { pos: pos(event.0) });
+    }
+    for event in events.cannon_launch.iter() {
+        logged.push(GameplayEvent::CannonLaunch { pos: pos(event.0) });
+    }
+    for event in events.shield_damage.iter() {
+        logged.push(GameplayEvent::ShieldDamage {
+            source: event.source,
+            damage: event.damage,
+            pos: pos(event.block),
+        });
+    }
+    let despawned = &mut telemetry.despawned;
+    let mut despawn = |kind, entity, reason| {
+        despawned.insert(entity).then(|| GameplayEvent::Despawn {
+            kind,
+            reason,
+            pos: pos(entity),
+        })
+    };
+    for event in events.despawn_bullet.iter() {
+        logged.extend(despawn(EntityKind::Bullet, event.entity, event.reason));
+    }
+    for event in events.despawn_qotile.iter() {
+        logged.extend(despawn(EntityKind::Qotile, event.entity, event.reason));
+    }
+    for event in events.despawn_cannon.iter() {
+        logged.extend(despawn(
+            EntityKind::ZorlonCannon,
+            event.entity,
+            event.reason,
+        ));
+    }
+    for event in events.despawn_missile.iter() {
+        logged.extend(despawn(
+            EntityKind::DestroyerMissile,
+            event.entity,
+            event.reason,
+        ));
+    }
+    for event in events.qotile_died.iter() {
+        logged.push(GameplayEvent::QotileDied {
+            pos: Some(event.pos.to_array()),
+        });
+    }
+    for event in events.yar_died.iter() {
+        if telemetry.died.insert(event.yar) {
+            logged.push(GameplayEvent::YarDied {
+                cause: event.cause,
+                pos: Some(event.pos.to_array()),
+            });
+        }
+    }
+    for _ in events.yar_respawn.iter() {
+        logged.push(GameplayEvent::YarRespawn { pos: live_yar });
+    }
+    let game_over = events.game_over.iter().count() > 0;
+    if game_over {
+        logged.push(GameplayEvent::GameOver);
+    }
+
+    for event in logged {
+        let line = LoggedEvent {
+            tick: clock.tick,
+            event,
+        }
+        .to_line();
+        let written = line.and_then(|line| Ok(writeln!(telemetry.writer, "{}", line)?));
+        if let Err(err) = written {
+            warn!("failed to write telemetry: {}", err);
+        }
+    }
+    // The window can be closed without the writer being dropped.
+    if game_over || app_exit.iter().count() > 0 {
+        if let Err(err) = telemetry.writer.flush() {
+            warn!("failed to write telemetry: {}", err);
+        }
+    }
+    telemetry.last_seen.clear();
+}
@@ -0,0 +1,273 @@
+//! Emulation of the 2600's TIA sound generator.
+//!
+//! Each of the TIA's two audio channels is driven by three registers:
+//! - `AUDC` (4 bits) picks the waveform, built from pure-tone dividers and polynomial counters.
+//! - `AUDF` (5 bits) divides the 31.4kHz audio clock, so higher values give lower pitches.
+//! - `AUDV` (4 bits) sets the volume.
+//!
+//! Games changed the registers once per frame to play sound effects, so an effect here is a list
+//! of register settings each held for a number of frames.
+
+/// Rate at which the TIA produces audio samples (NTSC colour clock / 114).
+pub const SAMPLE_RATE: u32 = 31_400;
+
+/// Rate at which sound effects update the registers.
+pub const FRAME_RATE: u32 = 60;
+
+/// Loudest sample produced at full volume.
+const MAX_AMPLITUDE: i16 = i16::MAX / 2;
+
+/// The TIA audio registers.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Registers {
+    /// Waveform, 0-15.
+    pub audc: u8,
+    /// Frequency divider, 0-31.
+    pub audf: u8,
+    /// Volume, 0-15.
+    pub audv: u8,
+}
+
+impl Registers {
+    pub const fn new(audc: u8, audf: u8, audv: u8) -> Self {
+        Self {
+            audc: audc & 0x0f,
+            audf: audf & 0x1f,
+            audv: audv & 0x0f,
+        }
+    }
+}
+
+/// One audio channel, including the state of its counters.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub registers: Registers,
+    /// Counts audio clocks up to `AUDF`.
+    frequency_counter: u8,
+    /// Counts channel clocks for the pure-tone dividers.
+    divider_counter: u8,
+    poly4: u8,
+    poly5: u8,
+    poly9: u16,
+    output: bool,
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        // The polynomial counters must never be all zeros or they lock up.
+        Self {
+            registers: Registers::default(),
+            frequency_counter: 0,
+            divider_counter: 0,
+            poly4: 0x0f,
+            poly5: 0x1f,
+            poly9: 0x1ff,
+            output: false,
+        }
+    }
+}
+
+impl Channel {
+    /// Produce the next sample.
+    pub fn sample(&mut self) -> i16 {
+        self.frequency_counter += 1;
+        if self.frequency_counter > self.registers.audf {
+            self.frequency_counter = 0;
+            self.clock();
+        }
+
+        // Waveforms 0 and 11 hold the output high, which is silent once it reaches a speaker.
+        if matches!(self.registers.audc, 0 | 11) {
+            return 0;
+        }
+
+        let amplitude = MAX_AMPLITUDE / 15 * self.registers.audv as i16;
+        if self.output {
+            amplitude
+        } else {
+            -amplitude
+        }
+    }
+
+    /// Advance the waveform by one step of the divided clock.
+    fn clock(&mut self) {
+        let poly5_bit = step_poly5(&mut self.poly5);
+
+        // These waveforms only advance when the 5-bit counter lets them.
+        if matches!(self.registers.audc, 3 | 7 | 15) && !poly5_bit {
+            return;
+        }
+
+        self.output = match self.registers.audc {
+            0 | 11 => true,
+            1 | 3 => step_poly4(&mut self.poly4),
+            2 => {
+                if self.divide(15) {
+                    step_poly4(&mut self.poly4)
+                } else {
+                    self.output
+                }
+            }
+            4 | 5 | 7 => !self.output,
+            6 | 10 => self.square(31),
+            8 => step_poly9(&mut self.poly9),
+            9 => poly5_bit,
+            12 | 13 | 15 => self.square(6),
+            14 => self.square(93),
+            _ => unreachable!("AUDC is 4 bits"),
+        }
+    }
+
+    /// Count up to `n` clocks, returning true as the count wraps.
+    fn divide(&mut self, n: u8) -> bool {
+        self.divider_counter += 1;
+        if self.divider_counter >= n {
+            self.divider_counter = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// A square wave with a period of `n` clocks.
+    fn square(&mut self, n: u8) -> bool {
+        self.divide(n);
+        self.divider_counter < n / 2
+    }
+}
+
+fn step_poly4(poly: &mut u8) -> bool {
+    let bit = (*poly ^ (*poly >> 1)) & 1;
+    *poly = (*poly >> 1) | (bit << 3);
+    *poly & 1 == 1
+}
+
+fn step_poly5(poly: &mut u8) -> bool {
+    let bit = (*poly ^ (*poly >> 2)) & 1;
+    *poly = (*poly >> 1) | (bit << 4);
+    *poly & 1 == 1
+}
+
+fn step_poly9(poly: &mut u16) -> bool {
+    let bit = (*poly ^ (*poly >> 4)) & 1;
+    *poly = (*poly >> 1) | (bit << 8);
+    *poly & 1 == 1
+}
+
+/// Register settings held for a number of frames.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub frames: u32,
+    pub registers: Registers,
+}
+
+impl Step {
+    pub const fn new(frames: u32, audc: u8, audf: u8, audv: u8) -> Self {
+        Self {
+            frames,
+            registers: Registers::new(audc, audf, audv),
+        }
+    }
+}
+
+/// Play the steps on a fresh channel, returning mono samples at `SAMPLE_RATE`.
+pub fn render(steps: &[Step]) -> Vec<i16> {
+    let mut channel = Channel::default();
+    let mut samples = Vec::new();
+    // Frames don't divide the sample rate evenly, so carry the remainder between steps.
+    let mut frame_samples = 0;
+
+    for step in steps {
+        channel.registers = step.registers;
+        frame_samples += step.frames * SAMPLE_RATE;
+        while frame_samples >= FRAME_RATE {
+            frame_samples -= FRAME_RATE;
+            samples.push(channel.sample());
+        }
+    }
+
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Steps until the counter comes back to where it started.
+    fn period<T: Copy + PartialEq>(start: T, step: fn(&mut T) -> bool) -> usize {
+        let mut poly = start;
+        for steps in 1..=1024 {
+            step(&mut poly);
+            if poly == start {
+                return steps;
+            }
+        }
+        panic!("the counter never repeated");
+    }
+
+    #[test]
+    fn polynomial_counters_have_maximal_periods() {
+        let channel = Channel::default();
+        assert_eq!(period(channel.poly4, step_poly4), 15);
+        assert_eq!(period(channel.poly5, step_poly5), 31);
+        assert_eq!(period(channel.poly9, step_poly9), 511);
+    }
+
+    /// The loudest sample a second of the given registers plays.
+    fn peak(registers: Registers) -> i16 {
+        let mut channel = Channel {
+            registers,
+            ..Channel::default()
+        };
+        (0..SAMPLE_RATE)
+            .map(|_| channel.sample().saturating_abs())
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn volume_scales_amplitude() {
+        let unit = peak(Registers::new(4, 0, 1));
+        assert!(unit > 0);
+        for audv in 0..16 {
+            assert_eq!(peak(Registers::new(4, 0, audv)), unit * audv as i16);
+        }
+        assert!(peak(Registers::new(4, 0, 15)) <= MAX_AMPLITUDE);
+    }
+
+    #[test]
+    fn held_high_waveforms_are_silent() {
+        assert_eq!(peak(Registers::new(0, 0, 15)), 0);
+        assert_eq!(peak(Registers::new(11, 0, 15)), 0);
+    }
+
+    #[test]
+    fn registers_keep_their_width() {
+        assert_eq!(Registers::new(0xff, 0xff, 0xff), Registers::new(15, 31, 15));
+    }
+
+    #[test]
+    fn render_carries_partial_samples_between_steps() {
+        // A frame is 523 and a third samples long.
+        assert_eq!(render(&[Step::new(1, 4, 0, 8)]).len(), 523);
+        let steps = [Step::new(1, 4, 0, 8); 3];
+        assert_eq!(render(&steps).len(), 1570);
+        assert_eq!(
+            render(&[Step::new(60, 4, 0, 8)]).len(),
+            SAMPLE_RATE as usize
+        );
+        assert!(render(&[]).is_empty());
+    }
+
+    #[test]
+    fn higher_audf_lowers_the_pitch() {
+        // Pure tone: the output flips on every divided clock.
+        let flips = |audf| {
+            let samples = render(&[Step::new(60, 4, audf, 8)]);
+            samples.windows(2).filter(|pair| pair[0] != pair[1]).count()
+        };
+        assert_eq!(flips(0), SAMPLE_RATE as usize - 1);
+        assert!(flips(31) < flips(1));
+        assert_eq!(flips(1), SAMPLE_RATE as usize / 2);
+    }
+}
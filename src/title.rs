@@ -0,0 +1,321 @@
+//! The title screen, where the player picks a game variation, and the attract mode that plays a
+//! demo replay when nobody has touched the keyboard for a while.
+use crate::bot::Bots;
+use crate::bullet::Bullet;
+use crate::control::{ControlTarget, Record, Replay};
+use crate::destroyer_missile::DestroyerMissile;
+use crate::font;
+use crate::highscore::HighScoreView;
+use crate::level::{NewGameEvent, RoundStartEvent, VARIATIONS};
+use crate::neutral_zone::{NeutralZone, NeutralZoneChunk};
+use crate::qotile::{Qotile, SpawnQotileEvent};
+use crate::qotile_death::ExplosionBand;
+use crate::shield::{ShieldBlock, SpawnShieldEvent};
+use crate::yar::{Yar, YarCommandEvent, YarRespawnEvent};
+use crate::zorlon_cannon::{CannonCommandEvent, SpawnZorlonCannonEvent, ZorlonCannon};
+use crate::{AppState, GameSystem};
+use bevy::ecs::event::Events;
+use bevy::prelude::*;
+
+/// Recording played in attract mode.
+pub const DEMO_REPLAY: &str = "demo.replay";
+/// Seconds without a key press before the title screen starts attract mode.
+const ATTRACT_IDLE_TIME: f32 = 10.0;
+/// Seconds attract mode keeps running after the demo runs out of input.
+const ATTRACT_TAIL_TIME: f64 = 3.0;
+/// In front of the playfield, behind the letterbox.
+const TITLE_Z: f32 = 10.0;
+pub const TITLE_COLOR: Color = Color::rgb(1.0, 0.85, 0.4);
+pub const TEXT_COLOR: Color = Color::rgb(0.7, 0.7, 0.7);
+
+pub struct TitlePlugin;
+
+impl Plugin for TitlePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SelectedVariation>()
+            .insert_resource(AttractTimer(Timer::from_seconds(ATTRACT_IDLE_TIME, false)))
+            .add_startup_system(setup)
+            .add_system(
+                attract
+                    .after(crate::console::toggle)
+                    .before(GameSystem::Menu),
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::Title)
+                    .label(GameSystem::Menu)
+                    .before(GameSystem::Start)
+                    .with_system(clear_playfield)
+                    .with_system(show.after(clear_playfield)),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Title)
+                    .label(GameSystem::Menu)
+                    .before(GameSystem::Start)
+                    .with_system(select.after(show))
+                    .with_system(idle.after(select)),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::Title).with_system(hide));
+    }
+}
+
+/// Game variation picked on the title screen, counting from 1.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SelectedVariation(pub u8);
+
+impl Default for SelectedVariation {
+    fn default() -> Self {
+        Self(1)
+    }
+}
+
+/// Start a game of the given variation as soon as the title screen comes up, without showing
+/// it; for starting a new game from elsewhere.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StartGame(pub u8);
+
+/// The replay attract mode plays.
+pub struct AttractDemo(pub Handle<Replay>);
+
+/// Time left on the title screen before attract mode starts.
+#[derive(Deref, DerefMut)]
+pub struct AttractTimer(pub Timer);
+
+/// Part of the title screen.
+#[derive(Component)]
+pub struct TitleScreen;
+
+/// Shows the selected variation.
+#[derive(Component)]
+pub struct VariationLabel;
+
+/// Shows which control targets the computer plays.
+#[derive(Component)]
+pub struct BotLabel;
+
+pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(AttractDemo(asset_server.load(DEMO_REPLAY)));
+}
+
+/// Whatever was being played is over.
+pub fn clear_playfield(
+    mut commands: Commands,
+    query: Query<
+        Entity,
+        Or<(
+            With<Yar>,
+            With<Bullet>,
+            With<ZorlonCannon>,
+            With<DestroyerMissile>,
+            With<Qotile>,
+            With<ShieldBlock>,
+            With<NeutralZone>,
+            With<NeutralZoneChunk>,
+            With<ExplosionBand>,
+        )>,
+    >,
+    mut round_start_events: ResMut<Events<RoundStartEvent>>,
+    mut yar_respawn_events: ResMut<Events<YarRespawnEvent>>,
+    mut cannon_spawn_events: ResMut<Events<SpawnZorlonCannonEvent>>,
+    mut qotile_spawn_events: ResMut<Events<SpawnQotileEvent>>,
+    mut shield_spawn_events: ResMut<Events<SpawnShieldEvent>>,
+) {
+    for e in query.iter() {
+        commands.entity(e).despawn();
+    }
+
+    // Leaving in the middle of a round may have left things waiting to spawn.
+    round_start_events.clear();
+    yar_respawn_events.clear();
+    cannon_spawn_events.clear();
+    qotile_spawn_events.clear();
+    shield_spawn_events.clear();
+}
+
+fn variation_text(variation: SelectedVariation) -> String {
+    format!("GAME {}", variation.0)
+}
+
+pub fn show(
+    mut commands: Commands,
+    variation: Res<SelectedVariation>,
+    bots: Res<Bots>,
+    mut attract_timer: ResMut<AttractTimer>,
+    start_game: Option<Res<StartGame>>,
+) {
+    attract_timer.reset();
+    if start_game.is_some() {
+        return;
+    }
+
+    let lines = [
+        ("YARS' REVENGE", IVec2::new(0, 48), 2, TITLE_COLOR),
+        ("F1 SELECT", IVec2::new(0, -32), 1, TEXT_COLOR),
+        ("F2 START", IVec2::new(0, -48), 1, TEXT_COLOR),
+        ("F3 HIGH SCORES", IVec2::new(0, -64), 1, TEXT_COLOR),
+    ];
+    for (text, center, scale, color) in lines {
+        let e = font::spawn_text(&mut commands, text, center, TITLE_Z, scale, color);
+        commands.entity(e).insert(TitleScreen);
+    }
+
+    spawn_variation_label(&mut commands, *variation);
+    spawn_bot_label(&mut commands, &bots);
+}
+
+fn spawn_bot_label(commands: &mut Commands, bots: &Bots) {
+    let e = font::spawn_text(
+        commands,
+        &format!("F4 CPU {}", bots.label()),
+        IVec2::new(0, -80),
+        TITLE_Z,
+        1,
+        TEXT_COLOR,
+    );
+    commands.entity(e).insert(TitleScreen).insert(BotLabel);
+}
+
+fn spawn_variation_label(commands: &mut Commands, variation: SelectedVariation) {
+    let e = font::spawn_text(
+        commands,
+        &variation_text(variation),
+        IVec2::ZERO,
+        TITLE_Z,
+        1,
+        TITLE_COLOR,
+    );
+    commands
+        .entity(e)
+        .insert(TitleScreen)
+        .insert(VariationLabel);
+}
+
+/// Game Select cycles through the variations and Game Reset, or the fire button, starts one.
+/// The keys are the same as the Stella emulator's. F4 picks what the computer plays.
+#[allow(clippy::too_many_arguments)]
+pub fn select(
+    mut commands: Commands,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut variation: ResMut<SelectedVariation>,
+    mut high_score_view: ResMut<HighScoreView>,
+    mut state: ResMut<State<AppState>>,
+    mut new_game_event: EventWriter<NewGameEvent>,
+    mut bots: ResMut<Bots>,
+    label_query: Query<Entity, With<VariationLabel>>,
+    bot_label_query: Query<Entity, With<BotLabel>>,
+    start_game: Option<Res<StartGame>>,
+) {
+    if let Some(start_game) = start_game {
+        commands.remove_resource::<StartGame>();
+        variation.0 = start_game.0;
+        new_game_event.send(NewGameEvent {
+            variation: variation.0,
+            replay: None,
+        });
+        let _ = state.set(AppState::Playing);
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::F1) {
+        variation.0 = variation.0 % VARIATIONS.len() as u8 + 1;
+        for e in label_query.iter() {
+            commands.entity(e).despawn_recursive();
+        }
+        spawn_variation_label(&mut commands, *variation);
+    }
+    if keys.just_pressed(KeyCode::F4) {
+        bots.cycle();
+        for e in bot_label_query.iter() {
+            commands.entity(e).despawn_recursive();
+        }
+        spawn_bot_label(&mut commands, &bots);
+    }
+
+    if keys.any_just_pressed([KeyCode::F2, KeyCode::Space]) {
+        new_game_event.send(NewGameEvent {
+            variation: variation.0,
+            replay: None,
+        });
+        let _ = state.set(AppState::Playing);
+        keys.clear();
+    } else if keys.just_pressed(KeyCode::F3) {
+        *high_score_view = HighScoreView {
+            variation: variation.0,
+            selected: 0,
+        };
+        let _ = state.set(AppState::HighScores);
+        keys.clear();
+    }
+}
+
+/// Start attract mode once the title screen has been left alone for long enough.
+pub fn idle(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mut attract_timer: ResMut<AttractTimer>,
+    mut state: ResMut<State<AppState>>,
+    mut new_game_event: EventWriter<NewGameEvent>,
+    demo: Option<Res<AttractDemo>>,
+    replays: Res<Assets<Replay>>,
+) {
+    if keys.get_just_pressed().next().is_some() {
+        attract_timer.reset();
+        return;
+    }
+    if !attract_timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let demo = match demo {
+        Some(demo) => demo,
+        None => return,
+    };
+    let replay = match replays.get(&demo.0) {
+        Some(replay) => replay,
+        None => {
+            // Try again later; the demo may still be loading.
+            attract_timer.reset();
+            return;
+        }
+    };
+
+    new_game_event.send(NewGameEvent {
+        variation: replay.header.variation.max(1),
+        replay: Some(demo.0.clone()),
+    });
+    let _ = state.set(AppState::Playing);
+}
+
+/// Attract mode goes back to the title screen when any key is pressed, or a little while after
+/// the demo runs out.
+pub fn attract(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    control_target: Res<ControlTarget>,
+    yar_record: Res<Record<YarCommandEvent>>,
+    cannon_record: Res<Record<CannonCommandEvent>>,
+    mut state: ResMut<State<AppState>>,
+    mut ran_out_at: Local<Option<f64>>,
+) {
+    if !matches!(*control_target, ControlTarget::Demo) || *state.current() == AppState::Title {
+        *ran_out_at = None;
+        return;
+    }
+
+    let now = time.seconds_since_startup();
+    let finished = if yar_record.is_empty() && cannon_record.is_empty() {
+        let ran_out_at = *ran_out_at.get_or_insert(now);
+        now - ran_out_at >= ATTRACT_TAIL_TIME
+    } else {
+        false
+    };
+
+    if finished || keys.get_just_pressed().next().is_some() {
+        let _ = state.set(AppState::Title);
+    }
+}
+
+pub fn hide(mut commands: Commands, query: Query<Entity, With<TitleScreen>>) {
+    for e in query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
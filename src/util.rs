@@ -1,28 +1,45 @@
-use crate::SCREEN_SIZE;
+use crate::PLAYFIELD_SIZE;
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-pub fn is_offscreen(point: Vec3) -> bool {
-    let half_width = SCREEN_SIZE.x / 2.0;
-    let half_height = SCREEN_SIZE.y / 2.0;
+pub fn is_offscreen(point: IVec2) -> bool {
+    let half_size = PLAYFIELD_SIZE / 2;
 
-    point.x < -half_width || point.x > half_width || point.y < -half_height || point.y > half_height
+    point.x < -half_size.x
+        || point.x > half_size.x
+        || point.y < -half_size.y
+        || point.y > half_size.y
 }
 
 // intersect 2d aabb
 // This is a Valve interview question. Sort of like asking "have you ever written a game before?"
-pub fn intersect_rect(p1: &Vec3, b1: &Vec2, p2: &Vec3, b2: &Vec2) -> bool {
-    let y1min = p1.y - b1.y / 2.0;
-    let y1max = p1.y + b1.y / 2.0;
-    let x1min = p1.x - b1.x / 2.0;
-    let x1max = p1.x + b1.x / 2.0;
+// Everything is doubled so that odd sizes don't lose half a pixel.
+pub fn intersect_rect(p1: &IVec2, b1: &IVec2, p2: &IVec2, b2: &IVec2) -> bool {
+    let y1min = p1.y * 2 - b1.y;
+    let y1max = p1.y * 2 + b1.y;
+    let x1min = p1.x * 2 - b1.x;
+    let x1max = p1.x * 2 + b1.x;
 
-    let y2min = p2.y - b2.y / 2.0;
-    let y2max = p2.y + b2.y / 2.0;
-    let x2min = p2.x - b2.x / 2.0;
-    let x2max = p2.x + b2.x / 2.0;
+    let y2min = p2.y * 2 - b2.y;
+    let y2max = p2.y * 2 + b2.y;
+    let x2min = p2.x * 2 - b2.x;
+    let x2max = p2.x * 2 + b2.x;
 
     let intersect_y = y1min < y2max && y1max > y2min;
     let intersect_x = x1min < x2max && x1max > x2min;
 
     intersect_y && intersect_x
 }
+
+/// Why an entity is being removed from the world.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DespawnReason {
+    /// Left the playfield.
+    Offscreen,
+    /// Struck something.
+    Collision,
+    /// The entity, or the Yar it belongs to, died.
+    Death,
+    /// Qotile was destroyed and the round is over.
+    RoundEnd,
+}
@@ -1,17 +1,52 @@
+use crate::animation::{AnimationFinishedEvent, SpriteAnimation};
+use crate::clock::GameClock;
+use crate::console::{self, ConsoleAppExt, ConsoleCommand, ConsoleVar};
 use crate::control::ControlEvent;
+use crate::difficulty::{Difficulty, DifficultySwitches};
+use crate::level::{GameOverEvent, NewGameEvent};
+use crate::motion::{GamePos, StepRate};
 use crate::qotile::{DespawnQotileEvent, Qotile, SwirlState, QOTILE_BOUNDS};
-use crate::shield::{ShieldBlock, ShieldHealth, SHIELD_BLOCK_SPRITE_SIZE};
+use crate::shield::{ShieldBlock, ShieldDamageEvent, ShieldDamageSource, SHIELD_BLOCK_SPRITE_SIZE};
 use crate::util;
-use crate::zorlon_cannon::{DespawnZorlonCannonEvent, SpawnZorlonCannonEvent};
-use crate::{SCREEN_SCALE, SCREEN_SIZE};
-use bevy::math::const_vec2;
+use crate::util::DespawnReason;
+use crate::zorlon_cannon::SpawnZorlonCannonEvent;
+use crate::{AppState, GameSystem, PLAYFIELD_SIZE, SQUARE_PIXEL};
+use anyhow::bail;
+use bevy::math::const_ivec2;
 use bevy::prelude::*;
-
-pub const YAR_BOUNDS: Vec2 = const_vec2!([16.0 * SCREEN_SCALE, 16.0 * SCREEN_SCALE]);
-const YAR_EAT_KNOCKBACK: f32 = 8.0 * SCREEN_SCALE;
-
-pub struct YarShootEvent;
-pub struct YarDiedEvent;
+use serde::{Deserialize, Serialize};
+
+pub const YAR_BOUNDS: IVec2 = const_ivec2!([8, 16]);
+const YAR_STEP_X: StepRate = StepRate::new(3, 8);
+const YAR_STEP_Y: StepRate = StepRate::new(3, 4);
+/// How far Yar is pushed back after taking a bite of the shield.
+const YAR_EAT_KNOCKBACK: IVec2 = const_ivec2!([4, 8]);
+/// With the difficulty switch on A, Yar is pushed back further and can't eat through the shield
+/// so easily.
+const YAR_EAT_KNOCKBACK_A: IVec2 = const_ivec2!([8, 16]);
+/// Yars the player starts each game with.
+pub const YAR_LIVES: u32 = 4;
+
+/// The given Yar fired its weapon.
+pub struct YarShootEvent(pub Entity);
+/// The given Yar was killed.
+pub struct YarDiedEvent {
+    pub yar: Entity,
+    pub cause: DeathCause,
+    /// Where Yar was when it was killed.
+    pub pos: IVec2,
+}
+/// What killed Yar.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeathCause {
+    DestroyerMissile,
+    /// Touching Qotile while it swirls, whether sitting or in flight.
+    Swirl,
+    /// A Zorlon Cannon fired by the player, or by a replay driving both Yar and the cannon.
+    OwnCannon,
+    /// A Zorlon Cannon replaying the player's last life while the player flies Yar.
+    GhostCannon,
+}
 pub struct YarRespawnEvent;
 
 pub struct YarPlugin;
@@ -22,18 +57,46 @@ impl Plugin for YarPlugin {
             .add_event::<YarDiedEvent>()
             .add_event::<YarRespawnEvent>()
             .add_event::<YarCommandEvent>()
-            .add_startup_system(setup.after(crate::setup_sprites))
-            .add_system(input)
-            .add_system(animate)
-            .add_system(collide_qotile)
-            .add_system(collide_shield)
-            .add_system(death)
-            .add_system(respawn);
+            .init_resource::<Lives>()
+            .init_resource::<YarSpeed>()
+            .init_resource::<GodMode>()
+            .add_console_command(ConsoleCommand {
+                name: "god",
+                usage: "[on|off]",
+                help: "Make Yar unkillable, or mortal again.",
+                run: god,
+            })
+            .add_console_var(ConsoleVar::new(
+                "yar.speed",
+                "Multiple of Yar's usual speed.",
+                0.1..=4.0,
+                |world| &mut world.resource_mut::<YarSpeed>().into_inner().0,
+            ))
+            .add_system(new_game.label(GameSystem::Start))
+            .add_system(input.label(GameSystem::Movement).after(GameSystem::Input))
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .label(GameSystem::Collision)
+                    .after(GameSystem::Movement)
+                    .with_system(collide_shield)
+                    .with_system(collide_qotile.after(collide_shield)),
+            )
+            // Dying plays the death animation, and the end of it brings the next life.
+            .add_system_set(
+                SystemSet::new()
+                    .label(GameSystem::Reaction)
+                    .after(GameSystem::Collision)
+                    .with_system(death)
+                    .with_system(animate.after(death))
+                    .with_system(death_finished.after(crate::animation::animate_sprites))
+                    .with_system(respawn.after(death_finished)),
+            );
     }
 }
 
 /// An input command to Yar
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct YarCommandEvent {
     pub direction: Option<YarDirection>,
     pub shoot: bool,
@@ -61,54 +124,86 @@ impl From<&Input<KeyCode>> for YarCommandEvent {
         if keys.pressed(KeyCode::D) {
             dx += 1;
         }
-        let direction = match (dx.signum(), dy.signum()) {
-            (1, 1) => Some(YarDirection::UpRight),
-            (1, 0) => Some(YarDirection::Right),
-            (1, -1) => Some(YarDirection::DownRight),
-            (-1, 1) => Some(YarDirection::UpLeft),
-            (-1, 0) => Some(YarDirection::Left),
-            (-1, -1) => Some(YarDirection::DownLeft),
-            (0, 1) => Some(YarDirection::Up),
-            (0, -1) => Some(YarDirection::Down),
-            _ => None,
-        };
         Self {
-            direction,
+            direction: YarDirection::towards(IVec2::new(dx.into(), dy.into())),
             shoot: keys.pressed(KeyCode::Space),
         }
     }
 }
 
-#[derive(Component, Deref, DerefMut)]
-pub struct AnimationTimer(pub Timer);
-
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum YarDirection {
     Left,
     Right,
     Up,
     UpRight,
     UpLeft,
+    #[default]
     Down,
     DownRight,
     DownLeft,
 }
 
-impl From<YarDirection> for Vec3 {
+impl From<YarDirection> for IVec2 {
     fn from(direction: YarDirection) -> Self {
         match direction {
-            YarDirection::Left => Vec3::new(-1.0, 0.0, 1.0),
-            YarDirection::Right => Vec3::new(1.0, 0.0, 1.0),
-            YarDirection::Up => Vec3::new(0.0, 1.0, 1.0),
-            YarDirection::UpRight => Vec3::new(1.0, 1.0, 1.0),
-            YarDirection::UpLeft => Vec3::new(-1.0, 1.0, 1.0),
-            YarDirection::Down => Vec3::new(0.0, -1.0, 1.0),
-            YarDirection::DownRight => Vec3::new(1.0, -1.0, 1.0),
-            YarDirection::DownLeft => Vec3::new(-1.0, -1.0, 1.0),
+            YarDirection::Left => IVec2::new(-1, 0),
+            YarDirection::Right => IVec2::new(1, 0),
+            YarDirection::Up => IVec2::new(0, 1),
+            YarDirection::UpRight => IVec2::new(1, 1),
+            YarDirection::UpLeft => IVec2::new(-1, 1),
+            YarDirection::Down => IVec2::new(0, -1),
+            YarDirection::DownRight => IVec2::new(1, -1),
+            YarDirection::DownLeft => IVec2::new(-1, -1),
+        }
+    }
+}
+
+impl YarDirection {
+    pub const ALL: [YarDirection; 8] = [
+        YarDirection::Left,
+        YarDirection::Right,
+        YarDirection::Up,
+        YarDirection::UpRight,
+        YarDirection::UpLeft,
+        YarDirection::Down,
+        YarDirection::DownRight,
+        YarDirection::DownLeft,
+    ];
+
+    /// The direction closest to `offset`, if it goes anywhere.
+    pub fn towards(offset: IVec2) -> Option<Self> {
+        match (offset.x.signum(), offset.y.signum()) {
+            (1, 1) => Some(YarDirection::UpRight),
+            (1, 0) => Some(YarDirection::Right),
+            (1, -1) => Some(YarDirection::DownRight),
+            (-1, 1) => Some(YarDirection::UpLeft),
+            (-1, 0) => Some(YarDirection::Left),
+            (-1, -1) => Some(YarDirection::DownLeft),
+            (0, 1) => Some(YarDirection::Up),
+            (0, -1) => Some(YarDirection::Down),
+            _ => None,
+        }
+    }
+
+    /// Name of the animation clip Yar flies in this direction with.
+    pub fn clip(&self) -> &'static str {
+        match self {
+            YarDirection::Up => "yar_up",
+            YarDirection::UpRight => "yar_up_right",
+            YarDirection::Right => "yar_right",
+            YarDirection::DownRight => "yar_down_right",
+            YarDirection::Down => "yar_down",
+            YarDirection::DownLeft => "yar_down_left",
+            YarDirection::Left => "yar_left",
+            YarDirection::UpLeft => "yar_up_left",
         }
     }
 }
 
+/// The clip Yar plays while dying.
+pub const YAR_DEATH_CLIP: &str = "yar_death";
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum YarAnim {
     Fly,
@@ -118,7 +213,6 @@ pub enum YarAnim {
 #[derive(Component)]
 pub struct Yar {
     pub direction: YarDirection,
-    anim_frame: usize,
     pub anim: YarAnim,
 }
 
@@ -127,7 +221,7 @@ impl Yar {
         matches!(self.anim, YarAnim::Death)
     }
 
-    pub fn direction_to_vector(&self) -> Vec3 {
+    pub fn direction_to_vector(&self) -> IVec2 {
         self.direction.into()
     }
 }
@@ -135,150 +229,140 @@ impl Yar {
 impl Default for Yar {
     fn default() -> Self {
         Yar {
-            direction: YarDirection::Down,
-            anim_frame: 0,
+            direction: YarDirection::default(),
             anim: YarAnim::Fly,
         }
     }
 }
 
-pub fn setup(commands: Commands, game_state: Res<crate::GameState>) {
-    spawn(commands, game_state);
+/// Multiple of Yar's usual speed, for playtesting.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct YarSpeed(pub f32);
+
+impl Default for YarSpeed {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+/// Nothing kills Yar, for playtesting.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct GodMode(pub bool);
+
+fn god(world: &mut World, args: &[&str]) -> anyhow::Result<String> {
+    let on = match args {
+        [] => !world.resource::<GodMode>().0,
+        [arg] if arg.eq_ignore_ascii_case("on") => true,
+        [arg] if arg.eq_ignore_ascii_case("off") => false,
+        _ => bail!("usage: god [on|off]"),
+    };
+    world.insert_resource(GodMode(on));
+    if on {
+        console::discard_replay(world);
+    }
+    Ok(format!("god mode {}", if on { "on" } else { "off" }))
+}
+
+/// Yars left, including the one in play.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Lives(pub u32);
+
+impl Default for Lives {
+    fn default() -> Self {
+        Self(YAR_LIVES)
+    }
+}
+
+/// Each game starts with one Yar on the left of the screen.
+pub fn new_game(
+    mut commands: Commands,
+    mut new_game_event: EventReader<NewGameEvent>,
+    mut lives: ResMut<Lives>,
+    game_state: Res<crate::GameState>,
+) {
+    if new_game_event.iter().count() > 0 {
+        *lives = Lives::default();
+        spawn(&mut commands, &game_state);
+    }
 }
 
-pub fn spawn(mut commands: Commands, game_state: Res<crate::GameState>) {
-    let mut transform = Transform::from_scale(Vec3::splat(SCREEN_SCALE));
-    transform.translation.x -= (SCREEN_SIZE.x / 2.0) - (YAR_BOUNDS.x * 2.0);
+pub fn spawn(commands: &mut Commands, game_state: &crate::GameState) -> Entity {
+    let pos = IVec2::new(-(PLAYFIELD_SIZE.x / 2) + YAR_BOUNDS.x * 2, 0);
 
     commands
         .spawn_bundle(SpriteSheetBundle {
             texture_atlas: game_state.sprite_atlas.clone(),
-            transform,
+            transform: Transform::from_translation(pos.as_vec2().extend(0.0))
+                .with_scale(SQUARE_PIXEL),
             ..default()
         })
+        .insert(GamePos(pos))
+        .insert(SpriteAnimation::new(YarDirection::default().clip()))
         .insert(Yar::default())
-        .insert(AnimationTimer(Timer::from_seconds(0.1, true)));
+        .id()
 }
 
 pub fn input(
+    clock: Res<GameClock>,
+    speed: Res<YarSpeed>,
     mut yar_commands: EventReader<YarCommandEvent>,
     mut shoot_event: EventWriter<YarShootEvent>,
-    mut query: Query<(&mut Transform, &mut Yar)>,
+    mut query: Query<(Entity, &mut GamePos, &mut Yar)>,
 ) {
-    if query.is_empty() {
-        return;
-    }
-
-    let (mut transform, mut yar) = query.single_mut();
-
-    if yar.is_dead() {
-        return;
-    }
-
-    let speed = 3.0;
-
     // I'm not too sure what is appropriate for multiple input command events.
     // Is this function always called once per frame?
     // For regular keyboard inputs there should be <= one command event per frame.
     // For recorded events it ideally will be one per frame, if somehow there are multiple per
     // frame then Yar should do them all so that it stays in sync.
-    for command in yar_commands.iter() {
-        // Originally yar_delta was Transform, only the translation Vec3 is used.
-        let mut yar_delta = command.direction.map_or(Vec3::ZERO, Vec3::from);
-        yar_delta.x *= speed;
-        yar_delta.y *= speed;
-
-        // If Yar moves offscreen in the horizontal direction, correct the move to bound Yar.
-        {
-            let x_pos = transform.translation.x + yar_delta.x;
-
-            let x_underflow = (x_pos - YAR_BOUNDS.x / 2.0) - (-SCREEN_SIZE.x / 2.0);
-            if x_underflow < 0.0 {
-                yar_delta.x -= x_underflow;
-            }
-
-            let x_overflow = (x_pos + YAR_BOUNDS.x / 2.0) - (SCREEN_SIZE.x / 2.0);
-            if x_overflow > 0.0 {
-                yar_delta.x -= x_overflow;
-            }
-        }
+    let yar_commands: Vec<YarCommandEvent> = yar_commands.iter().copied().collect();
 
-        // If Yar's centerpoint moves offscreen in the vertical direction, wrap Yar to the other side.
-        {
-            let y_pos = transform.translation.y + yar_delta.y;
+    let step = IVec2::new(
+        YAR_STEP_X.scaled(speed.0).step(clock.tick),
+        YAR_STEP_Y.scaled(speed.0).step(clock.tick),
+    );
 
-            if y_pos < -SCREEN_SIZE.y / 2.0 {
-                yar_delta.y += SCREEN_SIZE.y;
-            } else if y_pos > SCREEN_SIZE.y / 2.0 {
-                yar_delta.y -= SCREEN_SIZE.y;
-            }
+    for (e, mut pos, mut yar) in query.iter_mut() {
+        if yar.is_dead() {
+            continue;
         }
 
-        transform.translation += yar_delta;
-        if let Some(dir) = command.direction {
-            yar.direction = dir;
-        }
+        for command in yar_commands.iter() {
+            let yar_delta = command.direction.map_or(IVec2::ZERO, IVec2::from) * step;
+            pos.0 = keep_on_playfield(pos.0 + yar_delta);
+            if let Some(dir) = command.direction {
+                yar.direction = dir;
+            }
 
-        if command.shoot {
-            shoot_event.send(YarShootEvent);
+            if command.shoot {
+                shoot_event.send(YarShootEvent(e));
+            }
         }
     }
 }
 
-pub fn animate(
-    mut commands: Commands,
-    time: Res<Time>,
-    mut respawn_event: EventWriter<YarRespawnEvent>,
-    mut query: Query<(
-        Entity,
-        &mut AnimationTimer,
-        &mut TextureAtlasSprite,
-        &mut Yar,
-    )>,
-) {
-    if query.is_empty() {
-        return;
-    }
+/// Where Yar ends up after moving to `pos`, by flying or by being knocked back.
+fn keep_on_playfield(mut pos: IVec2) -> IVec2 {
+    let half_size = PLAYFIELD_SIZE / 2;
 
-    let (e, mut timer, mut sprite, mut yar) = query.single_mut();
+    // If Yar moves offscreen in the horizontal direction, correct the move to bound Yar.
+    pos.x = pos.x.clamp(
+        -half_size.x + YAR_BOUNDS.x / 2,
+        half_size.x - YAR_BOUNDS.x / 2,
+    );
+
+    // If Yar's centerpoint moves offscreen in the vertical direction, wrap Yar to the other side.
+    if !(-half_size.y..=half_size.y).contains(&pos.y) {
+        pos.y = (pos.y + half_size.y).rem_euclid(PLAYFIELD_SIZE.y) - half_size.y;
+    }
+    pos
+}
 
-    timer.tick(time.delta());
-    if timer.just_finished() {
+/// Fly in whichever direction Yar faces. Dying Yars play out their death instead.
+pub fn animate(mut query: Query<(&Yar, &mut SpriteAnimation)>) {
+    for (yar, mut animation) in query.iter_mut() {
         match yar.anim {
-            YarAnim::Fly => {
-                let sprite_base = match yar.direction {
-                    YarDirection::Up => 0,
-                    YarDirection::UpRight => 2,
-                    YarDirection::Right => 4,
-                    YarDirection::DownRight => 6,
-                    YarDirection::Down => 8,
-                    YarDirection::DownLeft => 10,
-                    YarDirection::Left => 12,
-                    YarDirection::UpLeft => 14,
-                };
-
-                let anim_length = 2;
-
-                yar.anim_frame = (yar.anim_frame + 1) % anim_length;
-
-                sprite.index = sprite_base + yar.anim_frame;
-            }
-            YarAnim::Death => {
-                let death_anim: Vec<usize> = vec![
-                    5, 7, 9, 11, 13, 15, 1, 1, 1, 1, 16, 16, 16, 17, 17, 17, 18, 19, 20, 22, 22, 22,
-                ];
-                yar.anim_frame += 1;
-
-                if yar.anim_frame >= death_anim.len() {
-                    // todo: move this out of here
-                    // probably a despawn yar event, then respawn...
-                    commands.entity(e).despawn();
-
-                    respawn_event.send(YarRespawnEvent);
-                } else {
-                    sprite.index = death_anim[yar.anim_frame];
-                }
-            }
+            YarAnim::Fly => animation.play(yar.direction.clip()),
+            YarAnim::Death => animation.play(YAR_DEATH_CLIP),
         }
     }
 }
@@ -287,86 +371,109 @@ pub fn collide_qotile(
     // mut spawn_event: EventWriter<SpawnZorlonCannonEvent>,
     mut death_event: EventWriter<YarDiedEvent>,
     mut despawn_event: EventWriter<DespawnQotileEvent>,
-    yar_query: Query<&Transform, (With<Yar>, Without<Qotile>)>,
-    qotile_query: Query<(&Transform, &Qotile), Without<Yar>>,
+    yar_query: Query<(Entity, &GamePos), (With<Yar>, Without<Qotile>)>,
+    qotile_query: Query<(Entity, &GamePos, &Qotile), Without<Yar>>,
+    god_mode: Res<GodMode>,
 ) {
-    if yar_query.is_empty() || qotile_query.is_empty() {
+    if god_mode.0 {
         return;
     }
+    for (yar_entity, yar_pos) in yar_query.iter() {
+        for (qotile_entity, qotile_pos, qotile) in qotile_query.iter() {
+            if !util::intersect_rect(yar_pos, &YAR_BOUNDS, qotile_pos, &QOTILE_BOUNDS) {
+                continue;
+            }
 
-    let yar_transform = yar_query.single();
-    let (qotile_transform, qotile) = qotile_query.single();
-
-    if util::intersect_rect(
-        &yar_transform.translation,
-        &YAR_BOUNDS,
-        &qotile_transform.translation,
-        &QOTILE_BOUNDS,
-    ) {
-        if matches!(qotile.swirl_state, SwirlState::NotSwirl) {
-            // spawn_event.send(SpawnZorlonCannonEvent);
-        } else {
-            death_event.send(YarDiedEvent);
-            despawn_event.send(DespawnQotileEvent);
+            if matches!(qotile.swirl_state, SwirlState::NotSwirl) {
+                // spawn_event.send(SpawnZorlonCannonEvent);
+            } else {
+                death_event.send(YarDiedEvent {
+                    yar: yar_entity,
+                    cause: DeathCause::Swirl,
+                    pos: yar_pos.0,
+                });
+                despawn_event.send(DespawnQotileEvent {
+                    entity: qotile_entity,
+                    reason: DespawnReason::Collision,
+                });
+            }
         }
     }
 }
 
 pub fn collide_shield(
     // mut spawn_event: EventWriter<SpawnZorlonCannonEvent>,
-    mut yar_query: Query<(&mut Transform, &Yar), Without<ShieldBlock>>,
-    mut shield_query: Query<(&Transform, &mut ShieldHealth), With<ShieldBlock>>,
+    mut damage_event: EventWriter<ShieldDamageEvent>,
+    switches: Res<DifficultySwitches>,
+    mut yar_query: Query<(&mut GamePos, &Yar), Without<ShieldBlock>>,
+    shield_query: Query<(Entity, &GamePos), With<ShieldBlock>>,
 ) {
-    if yar_query.is_empty() || shield_query.is_empty() {
-        return;
+    let knockback = match switches.active() {
+        Difficulty::A => YAR_EAT_KNOCKBACK_A,
+        Difficulty::B => YAR_EAT_KNOCKBACK,
+    };
+    for (mut yar_pos, yar) in yar_query.iter_mut() {
+        for (block, shield_pos) in shield_query.iter() {
+            if util::intersect_rect(&yar_pos, &YAR_BOUNDS, shield_pos, &SHIELD_BLOCK_SPRITE_SIZE) {
+                damage_event.send(ShieldDamageEvent {
+                    block,
+                    damage: 1,
+                    source: ShieldDamageSource::Eaten,
+                });
+
+                yar_pos.0 = keep_on_playfield(yar_pos.0 - yar.direction_to_vector() * knockback);
+
+                // spawn_event.send(SpawnZorlonCannonEvent);
+            }
+        }
     }
+}
 
-    let (mut yar_transform, yar) = yar_query.single_mut();
-    for (shield_transform, mut shield_health) in shield_query.iter_mut() {
-        if util::intersect_rect(
-            &yar_transform.translation,
-            &YAR_BOUNDS,
-            &shield_transform.translation,
-            &SHIELD_BLOCK_SPRITE_SIZE,
-        ) {
-            shield_health.health -= 1;
-
-            let mut knockback = yar.direction_to_vector();
-            knockback.z = 0.0;
-            yar_transform.translation -= knockback * YAR_EAT_KNOCKBACK;
-
-            // spawn_event.send(SpawnZorlonCannonEvent);
+pub fn death(mut death_event: EventReader<YarDiedEvent>, mut query: Query<&mut Yar>) {
+    for YarDiedEvent { yar: e, .. } in death_event.iter() {
+        if let Ok(mut yar) = query.get_mut(*e) {
+            // Several things may kill Yar in the same frame; only start dying once.
+            if yar.is_dead() {
+                continue;
+            }
+            yar.anim = YarAnim::Death;
         }
     }
 }
 
-pub fn death(
-    mut death_event: EventReader<YarDiedEvent>,
-    mut despawn_cannon_event: EventWriter<DespawnZorlonCannonEvent>,
-    mut query: Query<&mut Yar>,
+/// Once the death animation is over, Yar comes back if there are any lives left.
+pub fn death_finished(
+    mut commands: Commands,
+    mut finished_event: EventReader<AnimationFinishedEvent>,
+    mut respawn_event: EventWriter<YarRespawnEvent>,
+    mut game_over_event: EventWriter<GameOverEvent>,
+    mut lives: ResMut<Lives>,
+    query: Query<&Yar>,
 ) {
-    if death_event.iter().next().is_none() || query.is_empty() {
-        return;
-    }
-
-    despawn_cannon_event.send(DespawnZorlonCannonEvent);
+    for AnimationFinishedEvent { entity, clip } in finished_event.iter() {
+        if clip != YAR_DEATH_CLIP || query.get(*entity).is_err() {
+            continue;
+        }
 
-    let mut yar = query.single_mut();
-    yar.anim = YarAnim::Death;
-    yar.anim_frame = 0;
+        commands.entity(*entity).despawn();
+        lives.0 = lives.0.saturating_sub(1);
+        if lives.0 > 0 {
+            respawn_event.send(YarRespawnEvent);
+        } else {
+            game_over_event.send(GameOverEvent);
+        }
+    }
 }
 
 pub fn respawn(
-    commands: Commands,
+    mut commands: Commands,
     game_state: Res<crate::GameState>,
     mut respawn_event: EventReader<YarRespawnEvent>,
     mut spawn_cannon_event: EventWriter<SpawnZorlonCannonEvent>,
 ) {
-    if respawn_event.iter().next().is_none() {
-        return;
-    }
-
-    spawn(commands, game_state);
+    for _ in respawn_event.iter() {
+        let owner = spawn(&mut commands, &game_state);
 
-    spawn_cannon_event.send(SpawnZorlonCannonEvent);
+        spawn_cannon_event.send(SpawnZorlonCannonEvent { owner });
+    }
 }
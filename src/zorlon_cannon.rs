@@ -1,18 +1,49 @@
-use crate::control::ControlEvent;
+use crate::atlas::SpriteFrames;
+use crate::clock::GameClock;
+use crate::control::{ControlEvent, ControlTarget, Record};
+use crate::difficulty::{Difficulty, DifficultySwitches};
+use crate::motion::{GamePos, StepRate};
 use crate::qotile::{Qotile, QotileDiedEvent, QOTILE_BOUNDS};
-use crate::shield::{ShieldBlock, ShieldHealth, SHIELD_BLOCK_SPRITE_SIZE};
+use crate::shield::{ShieldBlock, ShieldDamageEvent, ShieldDamageSource, SHIELD_BLOCK_SPRITE_SIZE};
 use crate::util;
-use crate::yar::{Yar, YarDiedEvent, YAR_BOUNDS};
-use crate::SCREEN_SCALE;
-use crate::SCREEN_SIZE;
-use bevy::math::const_vec2;
+use crate::util::DespawnReason;
+use crate::yar::{DeathCause, GodMode, Yar, YarDiedEvent, YAR_BOUNDS};
+use crate::AppState;
+use crate::GameSystem;
+use crate::PLAYFIELD_SIZE;
+use crate::SQUARE_PIXEL;
+use bevy::math::const_ivec2;
 use bevy::prelude::*;
-
-const ZORLON_CANNON_SPEED: f32 = 6.0;
-const ZORLON_CANNON_BOUNDS: Vec2 = const_vec2!([16.0 * SCREEN_SCALE, 16.0 * SCREEN_SCALE]);
-
-pub struct SpawnZorlonCannonEvent;
-pub struct DespawnZorlonCannonEvent;
+use bevy::utils::{Duration, HashMap};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+
+/// Horizontal speed once launched.
+const ZORLON_CANNON_STEP: StepRate = StepRate::new(3, 4);
+/// Vertical speed while lining up a shot.
+const ZORLON_CANNON_AIM_STEP: StepRate = StepRate::new(3, 4);
+pub const ZORLON_CANNON_BOUNDS: IVec2 = const_ivec2!([8, 16]);
+
+/// Pulses per second while the cannon waits.
+const PULSE_RATE: f32 = 2.0;
+/// Pulses per second when the cannon is about to fire, or lined up to hit.
+const PULSE_RATE_WARNING: f32 = 8.0;
+/// How far ahead a replayed cannon warns that it is about to fire.
+const PULSE_WARNING_TIME: Duration = Duration::from_millis(500);
+const PLAYER_TINT: Color = Color::rgb(1.0, 0.85, 0.4);
+const GHOST_TINT: Color = Color::rgb(0.5, 0.7, 1.0);
+
+/// Spawn a Zorlon Cannon belonging to the given Yar.
+pub struct SpawnZorlonCannonEvent {
+    pub owner: Entity,
+}
+/// The given Zorlon Cannon was fired.
+pub struct ZorlonCannonLaunchEvent(pub Entity);
+/// Despawn the given Zorlon Cannon entity.
+pub struct DespawnZorlonCannonEvent {
+    pub entity: Entity,
+    pub reason: DespawnReason,
+}
 
 pub struct ZorlonCannonPlugin;
 
@@ -20,28 +51,61 @@ impl Plugin for ZorlonCannonPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<SpawnZorlonCannonEvent>()
             .add_event::<DespawnZorlonCannonEvent>()
+            .add_event::<ZorlonCannonLaunchEvent>()
             .add_event::<CannonCommandEvent>()
-            .add_system(spawn)
-            .add_system(despawn)
             // .add_system(track)
             // .add_system(shoot)
-            .add_system(input)
-            .add_system(fly)
-            .add_system(leave_world)
-            .add_system(collide_yar)
-            .add_system(collide_qotile)
-            .add_system(collide_shield);
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .label(GameSystem::Movement)
+                    .after(GameSystem::Input)
+                    .with_system(input.after(crate::qotile::timer))
+                    .with_system(fly.after(input)),
+            )
+            .add_system(
+                pulse
+                    .with_run_criteria(crate::not_paused)
+                    .label(GameSystem::Movement)
+                    .after(GameSystem::Input)
+                    .after(fly),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .label(GameSystem::Collision)
+                    .after(GameSystem::Movement)
+                    .with_system(collide_yar.after(crate::yar::collide_qotile))
+                    .with_system(collide_qotile.after(collide_yar))
+                    .with_system(
+                        collide_shield
+                            .after(collide_qotile)
+                            .after(crate::bullet::collide_shield),
+                    )
+                    .with_system(leave_world.after(collide_shield)),
+            )
+            .add_system(
+                yar_died
+                    .label(GameSystem::Reaction)
+                    .after(GameSystem::Collision),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .label(GameSystem::Spawn)
+                    .after(GameSystem::Reaction)
+                    .with_system(despawn)
+                    .with_system(spawn),
+            );
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CannonDirection {
     Up,
     Down,
 }
 
 /// An input command to the cannon
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CannonCommandEvent {
     pub direction: Option<CannonDirection>,
     pub shoot: bool,
@@ -69,191 +133,303 @@ impl From<&Input<KeyCode>> for CannonCommandEvent {
 
 #[derive(Component)]
 pub struct ZorlonCannon {
+    /// The Yar this cannon was summoned for.
+    pub owner: Entity,
     launched: bool,
+    /// Bounced off the shield and now flying back towards Yar.
+    bounced: bool,
+}
+
+impl ZorlonCannon {
+    /// Whether the cannon has been fired.
+    pub fn is_launched(&self) -> bool {
+        self.launched
+    }
+
+    /// Whether the cannon has bounced off the shield.
+    pub fn has_bounced(&self) -> bool {
+        self.bounced
+    }
 }
 
+/// Where the cannon is in its pulse cycle, in cycles.
+#[derive(Component, Default)]
+pub struct CannonPulse(pub f32);
+
 pub fn spawn(
     mut commands: Commands,
     mut spawn_event: EventReader<SpawnZorlonCannonEvent>,
     game_state: Res<crate::GameState>,
-    zc_query: Query<&Transform, With<ZorlonCannon>>,
+    sprite_frames: Res<SpriteFrames>,
 ) {
-    if spawn_event.iter().next().is_none() || !zc_query.is_empty() {
-        return;
-    }
-
-    let mut zorlon_transform = Transform::from_scale(Vec3::splat(SCREEN_SCALE));
-    zorlon_transform.translation.x = -SCREEN_SIZE.x / 2.0;
-
-    commands
-        .spawn_bundle(SpriteSheetBundle {
-            sprite: TextureAtlasSprite {
-                index: 23,
+    for SpawnZorlonCannonEvent { owner } in spawn_event.iter() {
+        let pos = IVec2::new(-PLAYFIELD_SIZE.x / 2, 0);
+        let mut zorlon_transform = Transform::from_scale(SQUARE_PIXEL);
+        zorlon_transform.translation = pos.as_vec2().extend(0.0);
+
+        commands
+            .spawn_bundle(SpriteSheetBundle {
+                sprite: TextureAtlasSprite {
+                    index: sprite_frames.index("zorlon_cannon"),
+                    ..default()
+                },
+                texture_atlas: game_state.sprite_atlas.clone(),
+                transform: zorlon_transform,
                 ..default()
-            },
-            texture_atlas: game_state.sprite_atlas.clone(),
-            transform: zorlon_transform,
-            ..default()
-        })
-        .insert(ZorlonCannon { launched: false });
+            })
+            .insert(GamePos(pos))
+            .insert(ZorlonCannon {
+                owner: *owner,
+                launched: false,
+                bounced: false,
+            })
+            .insert(CannonPulse::default());
+    }
 }
 
 pub fn despawn(
     mut commands: Commands,
     mut despawn_event: EventReader<DespawnZorlonCannonEvent>,
-    mut death_event: EventReader<YarDiedEvent>,
     query: Query<Entity, With<ZorlonCannon>>,
 ) {
-    if (despawn_event.iter().next().is_none() && death_event.iter().next().is_none())
-        || query.is_empty()
-    {
-        return;
+    let targets: HashMap<Entity, DespawnReason> = despawn_event
+        .iter()
+        .map(|event| (event.entity, event.reason))
+        .collect();
+
+    for e in query.iter() {
+        if let Some(reason) = targets.get(&e) {
+            debug!("despawning Zorlon Cannon {:?}: {:?}", e, reason);
+            commands.entity(e).despawn();
+        }
     }
+}
 
-    let e = query.single();
-    commands.entity(e).despawn();
+/// A dead Yar's cannon goes with it.
+pub fn yar_died(
+    mut death_event: EventReader<YarDiedEvent>,
+    mut despawn_event: EventWriter<DespawnZorlonCannonEvent>,
+    query: Query<(Entity, &ZorlonCannon)>,
+) {
+    for YarDiedEvent {
+        yar: yar_entity, ..
+    } in death_event.iter()
+    {
+        for (e, zorlon_cannon) in query.iter() {
+            if zorlon_cannon.owner == *yar_entity {
+                despawn_event.send(DespawnZorlonCannonEvent {
+                    entity: e,
+                    reason: DespawnReason::Death,
+                });
+            }
+        }
+    }
 }
 
 pub fn input(
     mut cannon_commands: EventReader<CannonCommandEvent>,
-    mut query: Query<(&mut Transform, &mut ZorlonCannon)>,
+    mut launch_event: EventWriter<ZorlonCannonLaunchEvent>,
+    clock: Res<GameClock>,
+    mut query: Query<(Entity, &mut GamePos, &mut ZorlonCannon)>,
 ) {
-    if query.is_empty() {
-        return;
-    }
-
-    let (mut transform, mut zorlon_cannon) = query.single_mut();
-    if zorlon_cannon.launched {
-        return;
-    }
+    let cannon_commands: Vec<CannonCommandEvent> = cannon_commands.iter().copied().collect();
 
-    let speed = 3.0;
-    for command in cannon_commands.iter() {
-        if let Some(direction) = command.direction {
-            transform.translation.y += match direction {
-                CannonDirection::Up => speed,
-                CannonDirection::Down => -speed,
-            };
+    for (e, mut pos, mut zorlon_cannon) in query.iter_mut() {
+        if zorlon_cannon.launched {
+            continue;
         }
-        if command.shoot {
-            zorlon_cannon.launched = true;
-            // Don't allow more movement after shooting
-            break;
+
+        for command in cannon_commands.iter() {
+            if let Some(direction) = command.direction {
+                pos.y += match direction {
+                    CannonDirection::Up => ZORLON_CANNON_AIM_STEP,
+                    CannonDirection::Down => ZORLON_CANNON_AIM_STEP.reversed(),
+                }
+                .step(clock.tick);
+            }
+            if command.shoot {
+                zorlon_cannon.launched = true;
+                launch_event.send(ZorlonCannonLaunchEvent(e));
+                // Don't allow more movement after shooting
+                break;
+            }
         }
     }
 }
 
-pub fn fly(mut zc_query: Query<(&mut Transform, &ZorlonCannon)>) {
-    if zc_query.is_empty() {
-        return;
-    }
+/// Pulse the cannon while it waits to fire, tinted by who is driving it.
+///
+/// The pulse quickens when the cannon is lined up with a Yar, and when a replayed cannon is
+/// about to fire, so the player gets some warning.
+pub fn pulse(
+    clock: Res<GameClock>,
+    control_target: Res<ControlTarget>,
+    cannon_record: Res<Record<CannonCommandEvent>>,
+    mut cannon_query: Query<(
+        &GamePos,
+        &ZorlonCannon,
+        &mut CannonPulse,
+        &mut TextureAtlasSprite,
+    )>,
+    yar_query: Query<&GamePos, (With<Yar>, Without<ZorlonCannon>)>,
+) {
+    let ghost_driven = !matches!(*control_target, ControlTarget::Cannon);
+    let tint = if ghost_driven {
+        GHOST_TINT
+    } else {
+        PLAYER_TINT
+    };
+    let about_to_launch = ghost_driven
+        && cannon_record
+            .peek_before(&clock, PULSE_WARNING_TIME)
+            .any(|command| command.shoot);
+
+    for (pos, zorlon_cannon, mut pulse, mut sprite) in cannon_query.iter_mut() {
+        if zorlon_cannon.launched {
+            sprite.color = tint;
+            continue;
+        }
 
-    let (mut transform, zorlon_cannon) = zc_query.single_mut();
-    if !zorlon_cannon.launched {
-        return;
+        let lined_up = yar_query
+            .iter()
+            .any(|yar_pos| (yar_pos.y - pos.y).abs() * 2 < YAR_BOUNDS.y);
+        let rate = if about_to_launch || lined_up {
+            PULSE_RATE_WARNING
+        } else {
+            PULSE_RATE
+        };
+        pulse.0 = (pulse.0 + rate * clock.delta_seconds()).fract();
+
+        let brightness = 0.7 + 0.3 * (pulse.0 * TAU).sin();
+        sprite.color = Color::rgb(
+            tint.r() * brightness,
+            tint.g() * brightness,
+            tint.b() * brightness,
+        );
     }
+}
 
-    transform.translation.x += ZORLON_CANNON_SPEED;
+pub fn fly(clock: Res<GameClock>, mut zc_query: Query<(&mut GamePos, &ZorlonCannon)>) {
+    for (mut pos, zorlon_cannon) in zc_query.iter_mut() {
+        if zorlon_cannon.launched {
+            pos.x += if zorlon_cannon.bounced {
+                ZORLON_CANNON_STEP.reversed()
+            } else {
+                ZORLON_CANNON_STEP
+            }
+            .step(clock.tick);
+        }
+    }
 }
 
 pub fn leave_world(
     mut despawn_event: EventWriter<DespawnZorlonCannonEvent>,
-    mut query: Query<&Transform, With<ZorlonCannon>>,
+    query: Query<(Entity, &GamePos), With<ZorlonCannon>>,
 ) {
-    if query.is_empty() {
-        return;
-    }
-
-    let transform = query.single_mut();
-
-    if util::is_offscreen(transform.translation) {
-        despawn_event.send(DespawnZorlonCannonEvent);
+    for (e, pos) in query.iter() {
+        if util::is_offscreen(pos.0) {
+            despawn_event.send(DespawnZorlonCannonEvent {
+                entity: e,
+                reason: DespawnReason::Offscreen,
+            });
+        }
     }
 }
 
 pub fn collide_yar(
     mut death_event: EventWriter<YarDiedEvent>,
     mut despawn_event: EventWriter<DespawnZorlonCannonEvent>,
-    yar_query: Query<&Transform, (With<Yar>, Without<ZorlonCannon>)>,
-    zc_query: Query<(&Transform, &ZorlonCannon), Without<Yar>>,
+    yar_query: Query<(Entity, &GamePos), (With<Yar>, Without<ZorlonCannon>)>,
+    zc_query: Query<(Entity, &GamePos, &ZorlonCannon), Without<Yar>>,
+    control_target: Res<ControlTarget>,
+    god_mode: Res<GodMode>,
 ) {
-    if yar_query.is_empty() || zc_query.is_empty() {
+    if god_mode.0 {
         return;
     }
+    // While the player flies Yar, the cannon is replaying their last life.
+    let cause = match *control_target {
+        ControlTarget::Yar => DeathCause::GhostCannon,
+        ControlTarget::Cannon | ControlTarget::Demo => DeathCause::OwnCannon,
+    };
+    for (zc_entity, zc_pos, zorlon_cannon) in zc_query.iter() {
+        if !zorlon_cannon.launched {
+            continue;
+        }
 
-    let (zc_transform, zorlon_cannon) = zc_query.single();
-    if !zorlon_cannon.launched {
-        return;
-    }
-
-    let yar_transform = yar_query.single();
-
-    if util::intersect_rect(
-        &yar_transform.translation,
-        &YAR_BOUNDS,
-        &zc_transform.translation,
-        &ZORLON_CANNON_BOUNDS,
-    ) {
-        death_event.send(YarDiedEvent);
-        despawn_event.send(DespawnZorlonCannonEvent);
+        for (yar_entity, yar_pos) in yar_query.iter() {
+            if util::intersect_rect(yar_pos, &YAR_BOUNDS, zc_pos, &ZORLON_CANNON_BOUNDS) {
+                death_event.send(YarDiedEvent {
+                    yar: yar_entity,
+                    cause,
+                    pos: yar_pos.0,
+                });
+                despawn_event.send(DespawnZorlonCannonEvent {
+                    entity: zc_entity,
+                    reason: DespawnReason::Collision,
+                });
+            }
+        }
     }
 }
 
 pub fn collide_qotile(
     mut death_event: EventWriter<QotileDiedEvent>,
     mut despawn_event: EventWriter<DespawnZorlonCannonEvent>,
-    qotile_query: Query<&Transform, (With<Qotile>, Without<ZorlonCannon>)>,
-    zc_query: Query<(&Transform, &ZorlonCannon), Without<Qotile>>,
+    qotile_query: Query<(Entity, &GamePos), (With<Qotile>, Without<ZorlonCannon>)>,
+    zc_query: Query<(Entity, &GamePos, &ZorlonCannon), Without<Qotile>>,
 ) {
-    if qotile_query.is_empty() || zc_query.is_empty() {
-        return;
-    }
-
-    let (zc_transform, zorlon_cannon) = zc_query.single();
-    if !zorlon_cannon.launched {
-        return;
-    }
-
-    let q_transform = qotile_query.single();
+    for (zc_entity, zc_pos, zorlon_cannon) in zc_query.iter() {
+        if !zorlon_cannon.launched {
+            continue;
+        }
 
-    if util::intersect_rect(
-        &q_transform.translation,
-        &QOTILE_BOUNDS,
-        &zc_transform.translation,
-        &ZORLON_CANNON_BOUNDS,
-    ) {
-        death_event.send(QotileDiedEvent);
-        despawn_event.send(DespawnZorlonCannonEvent);
+        for (q_entity, q_pos) in qotile_query.iter() {
+            if util::intersect_rect(q_pos, &QOTILE_BOUNDS, zc_pos, &ZORLON_CANNON_BOUNDS) {
+                death_event.send(QotileDiedEvent {
+                    qotile: q_entity,
+                    pos: q_pos.0,
+                });
+                despawn_event.send(DespawnZorlonCannonEvent {
+                    entity: zc_entity,
+                    reason: DespawnReason::Collision,
+                });
+            }
+        }
     }
 }
 
+/// The cannon breaks the first shield block it hits. With the difficulty switch on A it then
+/// bounces back across the screen, otherwise it is spent.
 pub fn collide_shield(
     mut despawn_event: EventWriter<DespawnZorlonCannonEvent>,
-    mut shield_query: Query<
-        (&Transform, &mut ShieldHealth),
-        (With<ShieldBlock>, Without<ZorlonCannon>),
-    >,
-    zc_query: Query<(&Transform, &ZorlonCannon), Without<ShieldBlock>>,
+    mut damage_event: EventWriter<ShieldDamageEvent>,
+    switches: Res<DifficultySwitches>,
+    shield_query: Query<(Entity, &GamePos), (With<ShieldBlock>, Without<ZorlonCannon>)>,
+    mut zc_query: Query<(Entity, &GamePos, &mut ZorlonCannon), Without<ShieldBlock>>,
 ) {
-    if shield_query.is_empty() || zc_query.is_empty() {
-        return;
-    }
-
-    let (zc_transform, zorlon_cannon) = zc_query.single();
-    if !zorlon_cannon.launched {
-        return;
-    }
+    for (zc_entity, zc_pos, mut zorlon_cannon) in zc_query.iter_mut() {
+        if !zorlon_cannon.launched || zorlon_cannon.bounced {
+            continue;
+        }
 
-    for (shield_transform, mut shield_health) in shield_query.iter_mut() {
-        if util::intersect_rect(
-            &shield_transform.translation,
-            &YAR_BOUNDS,
-            &zc_transform.translation,
-            &SHIELD_BLOCK_SPRITE_SIZE,
-        ) {
-            shield_health.health -= 5;
-            despawn_event.send(DespawnZorlonCannonEvent);
-            return; // Can only break one shield block at a time. Awful, really.
+        for (block, shield_pos) in shield_query.iter() {
+            if util::intersect_rect(shield_pos, &YAR_BOUNDS, zc_pos, &SHIELD_BLOCK_SPRITE_SIZE) {
+                damage_event.send(ShieldDamageEvent {
+                    block,
+                    damage: 5,
+                    source: ShieldDamageSource::ZorlonCannon,
+                });
+                if switches.active() == Difficulty::A {
+                    zorlon_cannon.bounced = true;
+                } else {
+                    despawn_event.send(DespawnZorlonCannonEvent {
+                        entity: zc_entity,
+                        reason: DespawnReason::Collision,
+                    });
+                }
+                break; // Can only break one shield block at a time. Awful, really.
+            }
         }
     }
 }
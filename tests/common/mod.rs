@@ -0,0 +1,63 @@
+//! The seeded session the integration tests play, one frame at a time.
+#![allow(dead_code)]
+
+use bevy::prelude::*;
+use ya_rs::control::ControlTarget;
+use ya_rs::env::{Action, EnvConfig, YarsEnv};
+use ya_rs::zorlon_cannon::ZorlonCannon;
+
+pub const SEED: u64 = 2600;
+/// Frames to give up after if a moment never comes.
+pub const MAX_FRAMES: u32 = 5000;
+
+pub const STILL: Action = Action {
+    direction: None,
+    shoot: false,
+};
+pub const FIRE: Action = Action {
+    direction: None,
+    shoot: true,
+};
+
+/// A game played one frame at a time.
+pub struct Session {
+    pub env: YarsEnv,
+}
+
+impl Session {
+    /// A game with the default settings, started from `SEED`.
+    pub fn new() -> Self {
+        Self::with_config(EnvConfig::default())
+    }
+
+    pub fn with_config(config: EnvConfig) -> Self {
+        let mut env = YarsEnv::new(config);
+        env.reset(SEED).expect("the game should start");
+        Self { env }
+    }
+
+    pub fn world(&mut self) -> &mut World {
+        self.env.world().unwrap()
+    }
+
+    pub fn step(&mut self, action: Action) {
+        self.env.step(action);
+    }
+
+    /// Play `action` until `moment` has come.
+    pub fn step_until(&mut self, action: Action, mut moment: impl FnMut(&mut World) -> bool) {
+        for _ in 0..MAX_FRAMES {
+            if moment(self.world()) {
+                return;
+            }
+            self.step(action);
+        }
+        panic!("the moment didn't come within {} frames", MAX_FRAMES);
+    }
+}
+
+/// Whether the player is at the cannon and it is on the playfield, as after Yar's first death.
+pub fn player_has_cannon(world: &mut World) -> bool {
+    matches!(*world.resource::<ControlTarget>(), ControlTarget::Cannon)
+        && world.query::<&ZorlonCannon>().iter(world).count() > 0
+}
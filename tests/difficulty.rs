@@ -0,0 +1,104 @@
+//! How the difficulty switches change the game. Each test plays the same seeded session with
+//! the player's switch on A and on B.
+mod common;
+
+use bevy::prelude::*;
+use common::{Session, FIRE, MAX_FRAMES, STILL};
+use ya_rs::difficulty::{Difficulty, DifficultySwitches};
+use ya_rs::env::{Action, EnvConfig};
+use ya_rs::motion::GamePos;
+use ya_rs::shield::{ShieldBlock, ShieldHealth};
+use ya_rs::yar::{GodMode, Yar, YarDirection, YAR_BOUNDS};
+use ya_rs::zorlon_cannon::ZorlonCannon;
+
+fn session(difficulty: Difficulty) -> Session {
+    Session::with_config(EnvConfig {
+        difficulty: DifficultySwitches {
+            left: difficulty,
+            ..default()
+        },
+        ..default()
+    })
+}
+
+fn shield_health(world: &mut World) -> i32 {
+    world
+        .query::<&ShieldHealth>()
+        .iter(world)
+        .map(|health| health.health.max(0))
+        .sum()
+}
+
+/// Shield health Yar eats in a few seconds of flying into the shield from just left of it.
+fn bites(difficulty: Difficulty) -> i32 {
+    let mut session = session(difficulty);
+    let world = session.world();
+    world.resource_mut::<GodMode>().0 = true;
+    let leftmost = world
+        .query_filtered::<&GamePos, With<ShieldBlock>>()
+        .iter(world)
+        .map(|pos| pos.0)
+        .min_by_key(|pos| pos.x)
+        .unwrap();
+    for mut pos in world
+        .query_filtered::<&mut GamePos, With<Yar>>()
+        .iter_mut(world)
+    {
+        pos.0 = IVec2::new(leftmost.x - YAR_BOUNDS.x, leftmost.y);
+    }
+    let before = shield_health(world);
+
+    let right = Action {
+        direction: Some(YarDirection::Right),
+        shoot: false,
+    };
+    for _ in 0..240 {
+        session.step(right);
+    }
+    before - shield_health(session.world())
+}
+
+#[test]
+fn yar_eats_the_shield_slower_on_a() {
+    let (a, b) = (bites(Difficulty::A), bites(Difficulty::B));
+    assert!(a > 0, "Yar should still eat the shield on A");
+    assert!(a < b, "Yar ate {} on A and {} on B", a, b);
+}
+
+/// Whether the player's cannon, fired along the shield's row, comes back off the shield.
+fn cannon_bounces(difficulty: Difficulty) -> bool {
+    let mut session = session(difficulty);
+    // Yar's first death hands the player the cannon.
+    session.step_until(STILL, common::player_has_cannon);
+
+    let world = session.world();
+    let shield_y = world
+        .query_filtered::<&GamePos, With<ShieldBlock>>()
+        .iter(world)
+        .next()
+        .unwrap()
+        .y;
+    for mut pos in world
+        .query_filtered::<&mut GamePos, With<ZorlonCannon>>()
+        .iter_mut(world)
+    {
+        pos.y = shield_y;
+    }
+    session.step(FIRE);
+
+    for _ in 0..MAX_FRAMES {
+        let world = session.world();
+        match world.query::<&ZorlonCannon>().iter(world).next() {
+            Some(cannon) if cannon.has_bounced() => return true,
+            Some(_) => session.step(STILL),
+            None => return false,
+        };
+    }
+    panic!("the cannon never came back or went away");
+}
+
+#[test]
+fn cannon_bounces_off_the_shield_only_on_a() {
+    assert!(cannon_bounces(Difficulty::A));
+    assert!(!cannon_bounces(Difficulty::B));
+}
@@ -0,0 +1,232 @@
+//! Fuzzing. Random seeds and streams of commands for Yar and the cannon are played headless,
+//! checking after every frame that the game still holds together:
+//!
+//! - Yar stays on the playfield, between its sides and with its centre wrapped top to bottom,
+//!   whether it flies there or the shield knocks it back;
+//! - there is never more than one bullet;
+//! - shield cells whose health runs out are gone by the next frame;
+//! - nothing panics.
+//!
+//! When a case fails, proptest shrinks it, and the smallest failing game is saved as a replay
+//! in `fuzz` in Cargo's temporary directory for the tests, to watch or to add to `replays/` once
+//! fixed, or to set up as a fixed case below. Each run tries `FUZZ_CASES` cases, 8 unless set.
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use proptest::prelude::*;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use ya_rs::bullet::Bullet;
+use ya_rs::control::Replay;
+use ya_rs::difficulty::{Difficulty, DifficultySwitches};
+use ya_rs::env::{EnvConfig, YarsEnv};
+use ya_rs::motion::GamePos;
+use ya_rs::shield::{ShieldBlock, ShieldHealth};
+use ya_rs::yar::{GodMode, Yar, YarCommandEvent, YarDirection, YAR_BOUNDS};
+use ya_rs::zorlon_cannon::{CannonCommandEvent, CannonDirection};
+use ya_rs::PLAYFIELD_SIZE;
+
+/// Commands held for a number of frames.
+#[derive(Debug, Clone)]
+struct Segment {
+    yar: YarCommandEvent,
+    cannon: CannonCommandEvent,
+    frames: u32,
+}
+
+fn yar_command() -> impl Strategy<Value = YarCommandEvent> {
+    (
+        proptest::option::of(proptest::sample::select(YarDirection::ALL.to_vec())),
+        any::<bool>(),
+    )
+        .prop_map(|(direction, shoot)| YarCommandEvent { direction, shoot })
+}
+
+fn cannon_command() -> impl Strategy<Value = CannonCommandEvent> {
+    (
+        proptest::option::of(proptest::sample::select(vec![
+            CannonDirection::Up,
+            CannonDirection::Down,
+        ])),
+        any::<bool>(),
+    )
+        .prop_map(|(direction, shoot)| CannonCommandEvent { direction, shoot })
+}
+
+fn segment() -> impl Strategy<Value = Segment> {
+    (yar_command(), cannon_command(), 1..90u32).prop_map(|(yar, cannon, frames)| Segment {
+        yar,
+        cannon,
+        frames,
+    })
+}
+
+/// Invariants that must hold after every frame.
+#[derive(Default)]
+struct Invariants {
+    /// Shield cells seen with no health left, which must be gone by the next frame.
+    dead_cells: HashSet<Entity>,
+}
+
+impl Invariants {
+    fn check(&mut self, world: &mut World) -> Result<(), String> {
+        let half_width = PLAYFIELD_SIZE.x / 2 - YAR_BOUNDS.x / 2;
+        let half_height = PLAYFIELD_SIZE.y / 2;
+        for pos in world.query_filtered::<&GamePos, With<Yar>>().iter(world) {
+            if pos.x.abs() > half_width || pos.y.abs() > half_height {
+                return Err(format!("Yar left the playfield at {}", pos.0));
+            }
+        }
+
+        let bullets = world.query::<&Bullet>().iter(world).count();
+        if bullets > 1 {
+            return Err(format!("{} bullets at once", bullets));
+        }
+
+        for entity in self.dead_cells.drain() {
+            if world.get::<ShieldHealth>(entity).is_some() {
+                return Err(format!("shield cell {:?} outlived its health", entity));
+            }
+        }
+        for (entity, health) in world.query::<(Entity, &ShieldHealth)>().iter(world) {
+            if health.health <= 0 {
+                self.dead_cells.insert(entity);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Play `segments` from `seed`, stopping at the first broken invariant or at the end of the
+/// game. Returns the game as a replay along with what went wrong.
+fn play(seed: u64, segments: &[Segment]) -> (Option<Replay>, Result<(), String>) {
+    let mut env = YarsEnv::new(EnvConfig::default());
+    if let Err(err) = env.reset(seed) {
+        return (None, Err(format!("the game didn't start: {}", err)));
+    }
+
+    let mut invariants = Invariants::default();
+    let mut result = Ok(());
+    'play: for segment in segments {
+        for _ in 0..segment.frames {
+            let step = catch_unwind(AssertUnwindSafe(|| {
+                env.step_commands(segment.yar, segment.cannon).2
+            }));
+            let done = match step {
+                Ok(done) => done,
+                Err(panic) => {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    result = Err(format!("a system panicked: {}", message));
+                    break 'play;
+                }
+            };
+            result = invariants.check(env.world().unwrap());
+            if result.is_err() || done {
+                break 'play;
+            }
+        }
+    }
+    (env.replay(), result)
+}
+
+/// Save the game that broke an invariant. Shrinking replays smaller and smaller failing games,
+/// so the file left behind is the smallest.
+fn save_failure(name: &str, replay: &Replay) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("fuzz");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name).with_extension("replay");
+    let text =
+        ron::ser::to_string_pretty(replay, ron::ser::PrettyConfig::new().depth_limit(2)).unwrap();
+    std::fs::write(&path, text).unwrap();
+    path
+}
+
+fn cases() -> u32 {
+    std::env::var("FUZZ_CASES")
+        .ok()
+        .and_then(|cases| cases.parse().ok())
+        .unwrap_or(8)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig {
+        cases: cases(),
+        ..ProptestConfig::default()
+    })]
+
+    #[test]
+    fn commands_keep_invariants(
+        seed in any::<u64>(),
+        segments in proptest::collection::vec(segment(), 1..40),
+    ) {
+        let (replay, result) = play(seed, &segments);
+        if let Err(failure) = result {
+            let saved = match replay {
+                Some(replay) => {
+                    let path = save_failure("commands_keep_invariants", &replay);
+                    format!("; the game is saved in {}", path.display())
+                }
+                None => String::new(),
+            };
+            return Err(TestCaseError::fail(format!("{}{}", failure, saved)));
+        }
+    }
+}
+
+/// Yar eating a shield cell right at the playfield's edge, facing into it, so the knockback
+/// pushes it over the edge.
+fn knockback_at_the_edge(difficulty: Difficulty, pos: IVec2, direction: YarDirection) {
+    let mut env = YarsEnv::new(EnvConfig {
+        difficulty: DifficultySwitches {
+            left: difficulty,
+            ..default()
+        },
+        ..default()
+    });
+    env.reset(2600).expect("the game should start");
+    let world = env.world().unwrap();
+    world.resource_mut::<GodMode>().0 = true;
+    for (mut yar_pos, mut yar) in world.query::<(&mut GamePos, &mut Yar)>().iter_mut(world) {
+        yar_pos.0 = pos;
+        yar.direction = direction;
+    }
+    let cell = world
+        .query_filtered::<Entity, With<ShieldBlock>>()
+        .iter(world)
+        .next()
+        .expect("the shield should be up");
+    world.get_mut::<GamePos>(cell).unwrap().0 = pos;
+
+    let mut invariants = Invariants::default();
+    for _ in 0..4 {
+        env.step_commands(YarCommandEvent::default(), CannonCommandEvent::default());
+        if let Err(failure) = invariants.check(env.world().unwrap()) {
+            panic!(
+                "{:?} knockback from {} facing {:?}: {}",
+                difficulty, pos, direction, failure
+            );
+        }
+    }
+}
+
+fn knockback_at_the_edges(difficulty: Difficulty) {
+    let half_width = PLAYFIELD_SIZE.x / 2 - YAR_BOUNDS.x / 2;
+    let half_height = PLAYFIELD_SIZE.y / 2;
+    knockback_at_the_edge(difficulty, IVec2::new(half_width, 0), YarDirection::Left);
+    knockback_at_the_edge(difficulty, IVec2::new(-half_width, 0), YarDirection::Right);
+    knockback_at_the_edge(difficulty, IVec2::new(0, half_height), YarDirection::Down);
+    knockback_at_the_edge(difficulty, IVec2::new(0, -half_height), YarDirection::Up);
+}
+
+#[test]
+fn knockback_keeps_yar_on_the_playfield() {
+    knockback_at_the_edges(Difficulty::B);
+}
+
+#[test]
+fn a_difficulty_knockback_keeps_yar_on_the_playfield() {
+    knockback_at_the_edges(Difficulty::A);
+}
@@ -0,0 +1,161 @@
+//! Golden-image tests. Each plays a seeded, scripted session to a moment worth looking at and
+//! compares the frame drawn on the CPU with a PNG checked in under `tests/golden`.
+//!
+//! When a frame doesn't match, the frame and an image of the differences are written to
+//! `golden` in Cargo's temporary directory for the tests: differing pixels are red, the rest a
+//! faint copy of the golden image. Run with `UPDATE_GOLDEN=1` to accept the current frames as
+//! the new goldens.
+mod common;
+
+use bevy::ecs::query::{FilterFetch, WorldQuery};
+use bevy::prelude::*;
+use common::{Session, FIRE, STILL};
+use std::path::PathBuf;
+use ya_rs::animation::SpriteAnimation;
+use ya_rs::bot::{Bots, RuleBot};
+use ya_rs::framebuffer::{self, Framebuffer};
+use ya_rs::qotile::{Qotile, SwirlState};
+use ya_rs::shield::ShieldBlock;
+use ya_rs::yar::{Yar, YAR_DEATH_CLIP};
+use ya_rs::zorlon_cannon::ZorlonCannon;
+
+fn frame(session: &mut Session) -> Framebuffer {
+    framebuffer::render(session.world())
+}
+
+fn count<F: WorldQuery>(world: &mut World) -> usize
+where
+    F::Fetch: FilterFetch,
+{
+    world.query_filtered::<Entity, F>().iter(world).count()
+}
+
+fn shield_blocks(world: &mut World) -> usize {
+    count::<With<ShieldBlock>>(world)
+}
+
+/// Whether Yar's death animation is showing frame `frame`.
+fn yar_death_frame(world: &mut World, frame: usize) -> bool {
+    world
+        .query_filtered::<&SpriteAnimation, With<Yar>>()
+        .iter(world)
+        .any(|animation| animation.clip == YAR_DEATH_CLIP && animation.frame == frame)
+}
+
+fn cannon_is_launched(world: &mut World) -> bool {
+    world
+        .query::<&ZorlonCannon>()
+        .iter(world)
+        .any(|cannon| cannon.is_launched())
+}
+
+/// An image of where `actual` differs from `expected`.
+fn diff_image(expected: &Framebuffer, actual: &Framebuffer) -> Framebuffer {
+    let mut diff = Framebuffer::new(expected.width, expected.height);
+    for (i, pixel) in diff.pixels.iter_mut().enumerate() {
+        let (expected, actual) = (expected.pixels[i], actual.pixels[i]);
+        *pixel = if expected == actual {
+            let [r, g, b, _] = expected;
+            let grey = ((r as u32 + g as u32 + b as u32) / 12) as u8;
+            [grey, grey, grey, 255]
+        } else {
+            [255, 0, 0, 255]
+        };
+    }
+    diff
+}
+
+fn assert_golden(name: &str, actual: &Framebuffer) {
+    let golden = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name)
+        .with_extension("png");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        actual.save_png(&golden).unwrap();
+        return;
+    }
+
+    let expected = Framebuffer::load_png(&golden).unwrap();
+    if expected == *actual {
+        return;
+    }
+
+    let out = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&out).unwrap();
+    let actual_path = out.join(format!("{}.png", name));
+    actual.save_png(&actual_path).unwrap();
+    if (expected.width, expected.height) != (actual.width, actual.height) {
+        panic!(
+            "`{}` is {}x{} but the frame is {}x{}; the frame is in {}",
+            name,
+            expected.width,
+            expected.height,
+            actual.width,
+            actual.height,
+            actual_path.display()
+        );
+    }
+
+    let diff_path = out.join(format!("{}.diff.png", name));
+    diff_image(&expected, actual).save_png(&diff_path).unwrap();
+    let differing = expected
+        .pixels
+        .iter()
+        .zip(&actual.pixels)
+        .filter(|(expected, actual)| expected != actual)
+        .count();
+    panic!(
+        "{} pixels differ from `{}`; the frame is in {} and the differences in {}",
+        differing,
+        name,
+        actual_path.display(),
+        diff_path.display()
+    );
+}
+
+#[test]
+fn initial_spawn() {
+    let mut session = Session::new();
+    assert_golden("initial_spawn", &frame(&mut session));
+}
+
+#[test]
+fn shield_half_eaten() {
+    let mut session = Session::new();
+    let full = shield_blocks(session.world());
+    // The computer player eats its way into the shield.
+    session.world().insert_resource(Bots {
+        yar: Some(Box::new(RuleBot::default())),
+        cannon: None,
+    });
+    session.step_until(STILL, |world| shield_blocks(world) * 2 <= full);
+    assert_golden("shield_half_eaten", &frame(&mut session));
+}
+
+#[test]
+fn swirl_launch() {
+    let mut session = Session::new();
+    session.step_until(STILL, |world| {
+        world
+            .query::<&Qotile>()
+            .iter(world)
+            .any(|qotile| qotile.swirl_state == SwirlState::SwirlFly)
+    });
+    assert_golden("swirl_launch", &frame(&mut session));
+}
+
+#[test]
+fn yar_death_frame_10() {
+    let mut session = Session::new();
+    session.step_until(STILL, |world| yar_death_frame(world, 10));
+    assert_golden("yar_death_frame_10", &frame(&mut session));
+}
+
+#[test]
+fn cannon_launch() {
+    let mut session = Session::new();
+    // Yar's first death hands the player the cannon.
+    session.step_until(STILL, common::player_has_cannon);
+    session.step_until(FIRE, cannon_is_launched);
+    assert_golden("cannon_launch", &frame(&mut session));
+}
@@ -0,0 +1,69 @@
+//! The replay corpus. Every replay under `replays/` is played back headless and checked against
+//! the checksums recorded with it, so any change to how the game plays out shows up as the first
+//! frame it happened on.
+//!
+//! The window runs its systems in the same order as the environment, so games recorded in the
+//! window belong in the corpus too; `window_plugins.replay` was played by the app the window
+//! runs. A change that is meant to alter the game invalidates the corpus; record the replays
+//! again once it is in.
+use std::path::{Path, PathBuf};
+use ya_rs::control::Replay;
+use ya_rs::env::{verify_replay, EnvConfig};
+
+fn corpus() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("replays");
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|err| panic!("reading `{}`: {}", dir.display(), err))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "replay"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn load(path: &Path) -> Replay {
+    let text = std::fs::read_to_string(path).unwrap();
+    ron::de::from_str(&text).unwrap_or_else(|err| panic!("parsing `{}`: {}", path.display(), err))
+}
+
+#[test]
+fn corpus_replays_without_desync() {
+    let paths = corpus();
+    assert!(!paths.is_empty(), "the corpus is empty");
+
+    let mut failures = Vec::new();
+    for path in &paths {
+        let name = path.file_name().unwrap().to_string_lossy();
+        let report = verify_replay(&EnvConfig::default(), load(path)).unwrap();
+        if let Some(desync) = report.desync {
+            failures.push(format!(
+                "{} desynced on frame {}: expected checksum {:016x}, got {:016x}",
+                name, desync.tick, desync.expected, desync.actual
+            ));
+        } else if report.unchecked > 0 {
+            failures.push(format!(
+                "{} ended on frame {} with {} checksums unchecked",
+                name, report.ticks, report.unchecked
+            ));
+        } else if report.matched == 0 {
+            failures.push(format!("{} has no checksums", name));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn tampered_checksum_is_reported() {
+    let path = &corpus()[0];
+    let mut replay = load(path);
+    let tampered = replay.checksums.len() / 2;
+    replay.checksums[tampered].hash ^= 1;
+    let tick = replay.checksums[tampered].tick;
+
+    let report = verify_replay(&EnvConfig::default(), replay).unwrap();
+    let desync = report
+        .desync
+        .expect("the tampered checksum should be reported");
+    assert_eq!(desync.tick, tick);
+    assert_eq!(report.matched, tampered);
+}
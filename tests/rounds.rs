@@ -0,0 +1,67 @@
+//! Destroying Qotile sets off the explosion, and the next round starts once it is over.
+mod common;
+
+use bevy::ecs::event::Events;
+use bevy::prelude::*;
+use common::{Session, FIRE, STILL};
+use ya_rs::level::CurrentRound;
+use ya_rs::motion::GamePos;
+use ya_rs::qotile::{Qotile, QotileDiedEvent, QOTILE_BOUNDS};
+use ya_rs::zorlon_cannon::ZorlonCannon;
+use ya_rs::AppState;
+
+fn qotile(world: &mut World) -> (Entity, IVec2) {
+    world
+        .query_filtered::<(Entity, &GamePos), With<Qotile>>()
+        .iter(world)
+        .map(|(entity, pos)| (entity, pos.0))
+        .next()
+        .expect("Qotile should be on the playfield")
+}
+
+fn round(world: &mut World) -> usize {
+    world.resource::<CurrentRound>().number
+}
+
+fn exploding(world: &mut World) -> bool {
+    *world.resource::<State<AppState>>().current() == AppState::QotileDeathSequence
+}
+
+#[test]
+fn next_round_starts_after_the_cannon_destroys_qotile() {
+    let mut session = Session::new();
+    // Yar's first death hands the player the cannon.
+    session.step_until(STILL, common::player_has_cannon);
+
+    // Fire from just in front of Qotile, past the shield.
+    let world = session.world();
+    let (_, qotile_pos) = qotile(world);
+    for mut pos in world
+        .query_filtered::<&mut GamePos, With<ZorlonCannon>>()
+        .iter_mut(world)
+    {
+        pos.0 = qotile_pos - IVec2::new(QOTILE_BOUNDS.x, 0);
+    }
+    session.step(FIRE);
+
+    session.step_until(STILL, exploding);
+    session.step_until(STILL, |world| round(world) == 1);
+    assert_eq!(
+        *session.world().resource::<State<AppState>>().current(),
+        AppState::Playing
+    );
+}
+
+#[test]
+fn explosion_starts_even_if_qotile_is_already_gone() {
+    let mut session = Session::new();
+    let world = session.world();
+    let (qotile, pos) = qotile(world);
+    world
+        .resource_mut::<Events<QotileDiedEvent>>()
+        .send(QotileDiedEvent { qotile, pos });
+    world.despawn(qotile);
+
+    session.step_until(STILL, exploding);
+    session.step_until(STILL, |world| round(world) == 1);
+}
@@ -0,0 +1,80 @@
+//! The statistics gathered while the player flies Yar.
+mod common;
+
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use common::{Session, FIRE, STILL};
+use ya_rs::bullet::Bullet;
+use ya_rs::motion::GamePos;
+use ya_rs::neutral_zone::NeutralZone;
+use ya_rs::stats::SessionStats;
+use ya_rs::yar::{GodMode, Yar};
+
+/// Put the player's Yar at `pos`, out of the Swirl's reach.
+fn place_yar(session: &mut Session, pos: IVec2) {
+    let world = session.world();
+    world.resource_mut::<GodMode>().0 = true;
+    for mut yar_pos in world
+        .query_filtered::<&mut GamePos, With<Yar>>()
+        .iter_mut(world)
+    {
+        yar_pos.0 = pos;
+    }
+}
+
+fn stats(session: &mut Session) -> SessionStats {
+    session.world().resource::<SessionStats>().clone()
+}
+
+#[test]
+fn holding_fire_counts_the_bullets_fired() {
+    const FRAMES: usize = 120;
+    let mut session = Session::new();
+    // Left of the Neutral Zone, where Yar may shoot.
+    place_yar(&mut session, IVec2::new(-60, 0));
+
+    let mut bullets = HashSet::default();
+    for _ in 0..FRAMES {
+        session.step(FIRE);
+        let world = session.world();
+        bullets.extend(world.query_filtered::<Entity, With<Bullet>>().iter(world));
+    }
+    // The last bullet is counted the frame after it comes into play.
+    session.step(STILL);
+
+    let shots = stats(&mut session).shots;
+    assert!(bullets.len() > 1, "Yar should have fired more than once");
+    assert!(
+        bullets.len() < FRAMES,
+        "Yar has one bullet in the air at a time"
+    );
+    assert_eq!(shots as usize, bullets.len());
+}
+
+#[test]
+fn time_in_the_neutral_zone_is_counted() {
+    let mut session = Session::new();
+    place_yar(&mut session, IVec2::new(-60, 0));
+    session.step_until(STILL, |world| {
+        world.query::<&NeutralZone>().iter(world).count() > 0
+    });
+    for _ in 0..30 {
+        session.step(STILL);
+    }
+    assert_eq!(stats(&mut session).neutral_zone_time, 0.0);
+
+    let world = session.world();
+    let zone = world
+        .query_filtered::<&GamePos, With<NeutralZone>>()
+        .iter(world)
+        .next()
+        .unwrap()
+        .0;
+    place_yar(&mut session, zone);
+    for _ in 0..30 {
+        session.step(STILL);
+    }
+    let stats = stats(&mut session);
+    assert!(stats.neutral_zone_time > 0.0);
+    assert!(stats.neutral_zone_time < stats.yar_time);
+}
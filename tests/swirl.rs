@@ -0,0 +1,50 @@
+//! When Qotile turns into the Swirl and launches it at Yar.
+mod common;
+
+use bevy::prelude::*;
+use common::{Session, STILL};
+use ya_rs::qotile::{Qotile, SwirlState, SwirlTimer};
+use ya_rs::yar::{GodMode, Yar};
+
+fn swirl_state(world: &mut World) -> SwirlState {
+    world
+        .query::<&Qotile>()
+        .iter(world)
+        .next()
+        .expect("Qotile should be on the playfield")
+        .swirl_state
+}
+
+#[test]
+fn idle_swirl_launches_once_yar_is_back() {
+    let mut session = Session::new();
+    session.world().resource_mut::<GodMode>().0 = true;
+    session.step_until(STILL, |world| swirl_state(world) == SwirlState::SwirlIdle);
+
+    // With no Yar to launch at, the Swirl idles past the end of its wait.
+    let world = session.world();
+    let yars: Vec<Entity> = world
+        .query_filtered::<Entity, With<Yar>>()
+        .iter(world)
+        .collect();
+    for &yar in &yars {
+        world.entity_mut(yar).remove::<Yar>();
+    }
+    session.step_until(STILL, |world| {
+        world
+            .query::<&SwirlTimer>()
+            .iter(world)
+            .all(|timer| timer.finished())
+    });
+    for _ in 0..30 {
+        session.step(STILL);
+    }
+    assert_eq!(swirl_state(session.world()), SwirlState::SwirlIdle);
+
+    let world = session.world();
+    for &yar in &yars {
+        world.entity_mut(yar).insert(Yar::default());
+    }
+    session.step(STILL);
+    assert_eq!(swirl_state(session.world()), SwirlState::SwirlFly);
+}